@@ -0,0 +1,445 @@
+use crate::geometry::{EdgeGeo, EdgeShape, NodeGeo};
+use crate::intersections::crosses;
+use crate::layout::{GraphLayout, NodeDataId};
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircularSettings {
+    /// Concentric circles to spread the nodes over, busiest nodes innermost.
+    pub rings: usize,
+    /// Smallest radius of the innermost circle.
+    pub radius: f64,
+    /// Arc length between neighbouring nodes on a circle.
+    pub spacing: f64,
+    /// Distance between consecutive circles.
+    pub ring_gap: f64,
+    /// Keeps nodes sharing a `group` on one arc of each circle.
+    pub group: bool,
+    /// Empty slots left between neighbouring groups' arcs.
+    pub group_gap: usize,
+    /// Most passes of moving single nodes to better slots.
+    pub passes: usize,
+}
+
+impl Default for CircularSettings {
+    fn default() -> Self {
+        return CircularSettings {
+            rings: 1,
+            radius: 0.0,
+            spacing: 50.0,
+            ring_gap: 50.0,
+            group: true,
+            group_gap: 1,
+            passes: 20,
+        };
+    }
+}
+
+/// Whether chords of one circle between points at angles `(a, b)` and
+/// `(c, d)` cross. Chords sharing an endpoint don't. Exact, as it only
+/// compares angles: two chords cross iff exactly one end of the second lies
+/// strictly between the ends of the first. Edges between circles of
+/// different radii need a geometric test instead.
+pub fn chords_cross((a, b): (f64, f64), (c, d): (f64, f64)) -> bool {
+    if a == c || a == d || b == c || b == d {
+        return false;
+    }
+    let (lo, hi) = if a < b { (a, b) } else { (b, a) };
+    let inside = |x: f64| lo < x && x < hi;
+    return inside(c) != inside(d);
+}
+
+/// Nodes on one circle in cyclic order, in groups separated by `gap` empty
+/// slots.
+#[derive(Clone)]
+struct Ring {
+    groups: Vec<Vec<usize>>,
+    gap: usize,
+}
+
+impl Ring {
+    fn slots(&self) -> usize {
+        let nodes: usize = self.groups.iter().map(|g| g.len()).sum();
+        let gaps = if self.groups.len() > 1 {
+            self.groups.len() * self.gap
+        } else {
+            0
+        };
+        return nodes + gaps;
+    }
+
+    fn set_angles(&self, angles: &mut [f64]) {
+        let slots = self.slots().max(1) as f64;
+        let mut slot = 0;
+        for group in self.groups.iter() {
+            for v in group.iter() {
+                angles[*v] = 2.0 * PI * slot as f64 / slots;
+                slot += 1;
+            }
+            if self.groups.len() > 1 {
+                slot += self.gap;
+            }
+        }
+    }
+}
+
+/// Which circle each node is on, and the circles' radii.
+struct Circles<'a> {
+    ring_of: &'a [usize],
+    radii: &'a [f64],
+}
+
+impl<'a> Circles<'a> {
+    fn position(&self, angles: &[f64], v: usize) -> NodeGeo {
+        let (angle, radius) = (angles[v], self.radii[self.ring_of[v]]);
+        return Coordinate {
+            x: radius * angle.cos(),
+            y: radius * angle.sin(),
+        };
+    }
+
+    /// Whether two edges cross. Edges on one circle are compared exactly by
+    /// their angles with `chords_cross`; any others are intersected as
+    /// segments.
+    fn cross(
+        &self,
+        angles: &[f64],
+        (a, b): (usize, usize),
+        (c, d): (usize, usize),
+    ) -> bool {
+        let ring = self.ring_of[a];
+        if [b, c, d].iter().all(|v| self.ring_of[*v] == ring) {
+            return chords_cross(
+                (angles[a], angles[b]),
+                (angles[c], angles[d]),
+            );
+        }
+        if a == c || a == d || b == c || b == d {
+            return false;
+        }
+        let segment = |v: usize, w: usize| {
+            EdgeGeo::new(self.position(angles, v), self.position(angles, w))
+        };
+        return crosses(&segment(a, b), &segment(c, d), None);
+    }
+
+    fn crossings(&self, angles: &[f64], edges: &[(usize, usize)]) -> usize {
+        let mut count = 0;
+        for (i, e) in edges.iter().enumerate() {
+            for f in edges.iter().skip(i + 1) {
+                if self.cross(angles, *e, *f) {
+                    count += 1;
+                }
+            }
+        }
+        return count;
+    }
+
+    /// Crossings of `v`'s edges with all others.
+    fn crossings_of(
+        &self,
+        angles: &[f64],
+        v: usize,
+        edges: &[(usize, usize)],
+        incident: &[Vec<usize>],
+    ) -> usize {
+        return incident[v]
+            .iter()
+            .map(|e| {
+                edges
+                    .iter()
+                    .filter(|f| self.cross(angles, edges[*e], **f))
+                    .count()
+            })
+            .sum();
+    }
+}
+
+/// Radius of each ring: wide enough for its slots at `spacing`, and at
+/// least `ring_gap` outside the one before.
+fn ring_radii(rings: &[Ring], settings: &CircularSettings) -> Vec<f64> {
+    let mut radii: Vec<f64> = vec![];
+    for ring in rings.iter() {
+        let fits = ring.slots() as f64 * settings.spacing / (2.0 * PI);
+        let radius = match radii.last() {
+            None if ring.slots() == 1 => settings.radius,
+            None => fits.max(settings.radius),
+            Some(inner) => fits.max(inner + settings.ring_gap),
+        };
+        radii.push(radius);
+    }
+    return radii;
+}
+
+impl GraphLayout {
+    /// Places the nodes on concentric circles, ordered to keep chord
+    /// crossings down: nodes start in breadth-first order from the busiest
+    /// node, then each is moved to whichever slot of its arc leaves its own
+    /// chords crossing the fewest others, while that lowers the total.
+    pub fn circular_layout_with(&mut self, settings: &CircularSettings) {
+        let nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        if nodes.is_empty() {
+            return;
+        }
+        let adjacency = self.adjacency(&nodes);
+        let edges: Vec<(usize, usize)> = adjacency
+            .iter()
+            .enumerate()
+            .flat_map(|(v, neighbors)| neighbors.iter().map(move |w| (v, *w)))
+            .filter(|(v, w)| v < w)
+            .collect();
+        let mut incident: Vec<Vec<usize>> = vec![vec![]; nodes.len()];
+        for (e, (v, w)) in edges.iter().enumerate() {
+            incident[*v].push(e);
+            incident[*w].push(e);
+        }
+
+        // Breadth-first order, restarting from the busiest unvisited node.
+        let mut by_degree: Vec<usize> = (0..nodes.len()).collect();
+        by_degree.sort_by_key(|v| std::cmp::Reverse(adjacency[*v].len()));
+        let mut order = vec![];
+        let mut seen = HashSet::new();
+        for start in by_degree.iter() {
+            if !seen.insert(*start) {
+                continue;
+            }
+            let mut queue = VecDeque::from(vec![*start]);
+            while let Some(v) = queue.pop_front() {
+                order.push(v);
+                for w in adjacency[v].iter() {
+                    if seen.insert(*w) {
+                        queue.push_back(*w);
+                    }
+                }
+            }
+        }
+
+        // Inner circles are shorter, so each ring takes a share of the
+        // nodes in proportion to its index.
+        let rings = settings.rings.max(1).min(nodes.len());
+        let weight: usize = (1..=rings).sum();
+        let mut ring_of = vec![0; nodes.len()];
+        let mut taken = 0;
+        for r in 0..rings {
+            let share = if r + 1 == rings {
+                nodes.len() - taken
+            } else {
+                nodes.len() * (r + 1) / weight
+            };
+            for v in by_degree.iter().skip(taken).take(share) {
+                ring_of[*v] = r;
+            }
+            taken += share;
+        }
+
+        let group_of: Vec<Option<NodeDataId>> = nodes
+            .iter()
+            .map(|idx| match settings.group {
+                true => self.graph[*idx].group,
+                false => None,
+            })
+            .collect();
+        let mut ring_list: Vec<Ring> = (0..rings)
+            .map(|r| {
+                let mut groups: Vec<(Option<NodeDataId>, Vec<usize>)> = vec![];
+                for v in order.iter().filter(|v| ring_of[**v] == r) {
+                    match groups.iter_mut().find(|(g, _)| *g == group_of[*v]) {
+                        Some((_, members)) => members.push(*v),
+                        None => groups.push((group_of[*v], vec![*v])),
+                    }
+                }
+                Ring {
+                    groups: groups.into_iter().map(|(_, g)| g).collect(),
+                    gap: settings.group_gap,
+                }
+            })
+            .collect();
+
+        // Reordering a ring doesn't change how many slots it has, so the
+        // radii are known up front.
+        let radii = ring_radii(&ring_list, settings);
+        let circles = Circles {
+            ring_of: &ring_of,
+            radii: &radii,
+        };
+        let mut angles = vec![0.0; nodes.len()];
+        for ring in ring_list.iter() {
+            ring.set_angles(&mut angles);
+        }
+        // With several rings a move can shift other nodes' chords too, so
+        // the best arrangement seen is kept.
+        let mut fewest = circles.crossings(&angles, &edges);
+        let mut best_rings = ring_list.clone();
+        for _ in 0..settings.passes {
+            if fewest == 0 {
+                break;
+            }
+            for v in order.iter() {
+                let ring = &mut ring_list[ring_of[*v]];
+                let g = ring.groups.iter().position(|g| g.contains(v)).unwrap();
+                let from = ring.groups[g].iter().position(|w| w == v).unwrap();
+                let mut best = (
+                    circles.crossings_of(&angles, *v, &edges, &incident),
+                    from,
+                );
+                for to in 0..ring.groups[g].len() {
+                    let moved = ring.groups[g].remove(from);
+                    ring.groups[g].insert(to, moved);
+                    ring.set_angles(&mut angles);
+                    let count =
+                        circles.crossings_of(&angles, *v, &edges, &incident);
+                    if count < best.0 {
+                        best = (count, to);
+                    }
+                    let moved = ring.groups[g].remove(to);
+                    ring.groups[g].insert(from, moved);
+                }
+                let moved = ring.groups[g].remove(from);
+                ring.groups[g].insert(best.1, moved);
+                ring.set_angles(&mut angles);
+            }
+            let total = circles.crossings(&angles, &edges);
+            if total >= fewest {
+                break;
+            }
+            fewest = total;
+            best_rings = ring_list.clone();
+        }
+        let ring_list = best_rings;
+        for ring in ring_list.iter() {
+            ring.set_angles(&mut angles);
+        }
+
+        let center = self.placement_center(&HashSet::new());
+        let mut positions: HashMap<usize, NodeGeo> = HashMap::new();
+        for v in ring_list
+            .iter()
+            .flat_map(|ring| ring.groups.iter().flatten())
+        {
+            positions.insert(*v, center + circles.position(&angles, *v));
+        }
+        for (v, position) in positions {
+            self.place_node(nodes[v], position);
+        }
+        let segments: Vec<_> = self.segment_edge_indices().collect();
+        for idx in segments {
+            self.set_edge_shape(idx, EdgeShape::Straight);
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Places the nodes on circles in an order with few crossings, as one
+    /// undo step.
+    pub fn circular_layout(
+        &mut self,
+        settings: JsValue,
+    ) -> Result<(), JsValue> {
+        let settings: CircularSettings = settings_from_js(&settings)?;
+        self.checkpoint_with_label("circular layout");
+        self.circular_layout_with(&settings);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::NodeData;
+    use crate::test_utils::{edge, graph, id, node};
+
+    #[test]
+    fn test_chords_cross() {
+        assert!(chords_cross((0.0, 2.0), (1.0, 3.0)));
+        assert!(chords_cross((2.0, 0.0), (3.0, 1.0)));
+        assert!(!chords_cross((0.0, 1.0), (2.0, 3.0)));
+        assert!(!chords_cross((0.0, 3.0), (1.0, 2.0)));
+        assert!(!chords_cross((0.0, 2.0), (2.0, 3.0)));
+    }
+
+    #[test]
+    fn test_crossings_between_rings() {
+        let circles = Circles {
+            ring_of: &[0, 0, 1, 1],
+            radii: &[1.0, 3.0],
+        };
+        // By angle alone the outer edge's ends interleave with the inner
+        // chord's, but it passes outside it.
+        let angles = [0.0, PI / 2.0, PI / 4.0, PI];
+        assert!(chords_cross((angles[0], angles[1]), (angles[2], angles[3])));
+        assert!(!circles.cross(&angles, (0, 1), (2, 3)));
+        // A spoke from the inner circle out across the inner chord.
+        let angles = [0.0, PI, PI / 2.0, -PI / 2.0];
+        assert!(circles.cross(&angles, (0, 1), (2, 3)));
+        assert!(!circles.cross(&angles, (0, 1), (1, 3)));
+    }
+
+    #[test]
+    fn test_circular_layout_untangles_groups() {
+        let grouped = |i: usize| NodeData {
+            group: Some(id(if i % 2 == 0 { "even" } else { "odd" })),
+            ..node(i, (i * 37 % 11) as f64, (i * 17 % 13) as f64)
+        };
+        // A cycle through the evens and one through the odds, listed in a
+        // scrambled order, with one edge joining them.
+        let evens = vec![0, 6, 2, 8, 4];
+        let odds = vec![5, 1, 9, 3, 7];
+        let mut edges = vec![edge(0, 1)];
+        for cycle in vec![&evens, &odds] {
+            for k in 0..cycle.len() {
+                edges.push(edge(cycle[k], cycle[(k + 1) % cycle.len()]));
+            }
+        }
+        let mut layout = graph((0..10).map(grouped).collect(), edges);
+        layout.circular_layout_with(&CircularSettings::default());
+
+        let nodes: Vec<NodeIndex> = layout.graph.node_indices().collect();
+        let center = layout.placement_center(&HashSet::new());
+        let angle = |i: usize| {
+            let p =
+                *layout.node_geo(layout.node_index(&id(i)).unwrap()).unwrap();
+            (p.y - center.y).atan2(p.x - center.x)
+        };
+        let angles: Vec<f64> = (0..10).map(angle).collect();
+        let local: Vec<(usize, usize)> = layout
+            .adjacency(&nodes)
+            .iter()
+            .enumerate()
+            .flat_map(|(v, ns)| ns.iter().map(move |w| (v, *w)))
+            .filter(|(v, w)| v < w)
+            .map(|(v, w)| {
+                let number = |n: usize| {
+                    layout
+                        .node_id(nodes[n])
+                        .unwrap()
+                        .as_str()
+                        .parse::<usize>()
+                        .unwrap()
+                };
+                (number(v), number(w))
+            })
+            .collect();
+        let circle = Circles {
+            ring_of: &[0; 10],
+            radii: &[1.0],
+        };
+        assert_eq!(circle.crossings(&angles, &local), 0);
+
+        // Each group fills an arc: sorted by angle, the group changes only
+        // twice going round.
+        let mut around: Vec<usize> = (0..10).collect();
+        around.sort_by(|a, b| angles[*a].partial_cmp(&angles[*b]).unwrap());
+        let changes = (0..10)
+            .filter(|k| around[*k] % 2 != around[(k + 1) % 10] % 2)
+            .count();
+        assert_eq!(changes, 2);
+    }
+}
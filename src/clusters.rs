@@ -0,0 +1,472 @@
+use crate::geometry::NodeGeo;
+use crate::layout::{GraphLayout, NodeDataId};
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ClusterShape {
+    Hull,
+    Rectangle,
+}
+
+impl Default for ClusterShape {
+    fn default() -> Self {
+        return ClusterShape::Hull;
+    }
+}
+
+/// The region drawn around each group of nodes.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusterSettings {
+    pub shape: ClusterShape,
+    /// Space between the nodes and the region's boundary.
+    pub padding: f64,
+}
+
+impl Default for ClusterSettings {
+    fn default() -> Self {
+        return ClusterSettings {
+            shape: ClusterShape::default(),
+            padding: 10.0,
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClusterRegion {
+    pub group: NodeDataId,
+    /// Corners in counter-clockwise order.
+    pub polygon: Vec<(f64, f64)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ClusterMetrics {
+    /// Total area shared by pairs of regions.
+    pub overlap_area: f64,
+    /// Nodes lying inside another group's region.
+    pub intruders: usize,
+    /// Mean silhouette of the grouped nodes, from -1 (closer to another
+    /// group than to their own) to 1 (well separated).
+    pub silhouette: f64,
+}
+
+fn cross(o: NodeGeo, a: NodeGeo, b: NodeGeo) -> f64 {
+    return (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x);
+}
+
+/// Convex hull by Andrew's monotone chain, counter-clockwise.
+pub fn convex_hull(mut points: Vec<NodeGeo>) -> Vec<NodeGeo> {
+    points.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let mut hull: Vec<NodeGeo> = vec![];
+    for pass in 0..2 {
+        let start = hull.len();
+        let ordered: Vec<NodeGeo> = if pass == 0 {
+            points.clone()
+        } else {
+            points.iter().rev().copied().collect()
+        };
+        for p in ordered {
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        hull.pop();
+    }
+    return hull;
+}
+
+pub fn polygon_area(polygon: &[NodeGeo]) -> f64 {
+    let n = polygon.len();
+    return (0..n)
+        .map(|i| {
+            let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum::<f64>()
+        .abs()
+        / 2.0;
+}
+
+/// The intersection of two counter-clockwise convex polygons, clipping one
+/// by each edge of the other in turn (Sutherland-Hodgman).
+pub fn clip_convex(subject: &[NodeGeo], clip: &[NodeGeo]) -> Vec<NodeGeo> {
+    let mut output = subject.to_vec();
+    for i in 0..clip.len() {
+        if output.is_empty() {
+            break;
+        }
+        let (a, b) = (clip[i], clip[(i + 1) % clip.len()]);
+        let input = std::mem::replace(&mut output, vec![]);
+        for j in 0..input.len() {
+            let (p, q) = (input[j], input[(j + 1) % input.len()]);
+            let (side_p, side_q) = (cross(a, b, p), cross(a, b, q));
+            if side_p >= 0.0 {
+                output.push(p);
+            }
+            if (side_p >= 0.0) != (side_q >= 0.0) {
+                let t = side_p / (side_p - side_q);
+                output.push(p + (q - p) * t);
+            }
+        }
+    }
+    return output;
+}
+
+fn inside_convex(polygon: &[NodeGeo], p: NodeGeo) -> bool {
+    let n = polygon.len();
+    return n >= 3
+        && (0..n).all(|i| cross(polygon[i], polygon[(i + 1) % n], p) >= 0.0);
+}
+
+fn distance(a: NodeGeo, b: NodeGeo) -> f64 {
+    return ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt();
+}
+
+impl GraphLayout {
+    /// Nodes by `group`, leaving out nodes without one.
+    pub fn clusters(&self) -> BTreeMap<NodeDataId, Vec<NodeIndex>> {
+        let mut clusters: BTreeMap<NodeDataId, Vec<NodeIndex>> =
+            BTreeMap::new();
+        for idx in self.graph.node_indices() {
+            if let Some(group) = self.graph[idx].group {
+                clusters.entry(group).or_default().push(idx);
+            }
+        }
+        return clusters;
+    }
+
+    /// Each group's region, with `moved` at its new position if given.
+    pub fn cluster_regions_with(
+        &self,
+        settings: &ClusterSettings,
+        moved: Option<(NodeIndex, NodeGeo)>,
+    ) -> Vec<(NodeDataId, Vec<NodeGeo>)> {
+        let pad = settings.padding;
+        return self
+            .clusters()
+            .into_iter()
+            .map(|(group, members)| {
+                let points: Vec<NodeGeo> = members
+                    .iter()
+                    .filter_map(|n| match moved {
+                        Some((idx, p)) if idx == *n => Some(p),
+                        _ => self.node_geo(*n).copied(),
+                    })
+                    .collect();
+                let corners: Vec<NodeGeo> = match settings.shape {
+                    // Each node as an octagon around it, so single nodes
+                    // and straight rows still get some area.
+                    ClusterShape::Hull => points
+                        .iter()
+                        .flat_map(|p| {
+                            (0..8).map(move |k| {
+                                let angle = PI * k as f64 / 4.0;
+                                Coordinate {
+                                    x: p.x + pad * angle.cos(),
+                                    y: p.y + pad * angle.sin(),
+                                }
+                            })
+                        })
+                        .collect(),
+                    ClusterShape::Rectangle => {
+                        let (min, max) = points.iter().fold(
+                            (
+                                Coordinate {
+                                    x: f64::MAX,
+                                    y: f64::MAX,
+                                },
+                                Coordinate {
+                                    x: f64::MIN,
+                                    y: f64::MIN,
+                                },
+                            ),
+                            |(min, max): (NodeGeo, NodeGeo), p| {
+                                (
+                                    Coordinate {
+                                        x: min.x.min(p.x),
+                                        y: min.y.min(p.y),
+                                    },
+                                    Coordinate {
+                                        x: max.x.max(p.x),
+                                        y: max.y.max(p.y),
+                                    },
+                                )
+                            },
+                        );
+                        vec![
+                            Coordinate {
+                                x: min.x - pad,
+                                y: min.y - pad,
+                            },
+                            Coordinate {
+                                x: max.x + pad,
+                                y: max.y + pad,
+                            },
+                            Coordinate {
+                                x: min.x - pad,
+                                y: max.y + pad,
+                            },
+                            Coordinate {
+                                x: max.x + pad,
+                                y: min.y - pad,
+                            },
+                        ]
+                    }
+                };
+                (group, convex_hull(corners))
+            })
+            .collect();
+    }
+
+    /// Area shared by pairs of group regions, with `moved` at its new
+    /// position if given.
+    pub fn cluster_overlap_with(
+        &self,
+        settings: &ClusterSettings,
+        moved: Option<(NodeIndex, NodeGeo)>,
+    ) -> f64 {
+        let regions = self.cluster_regions_with(settings, moved);
+        let mut area = 0.0;
+        for (i, (_, a)) in regions.iter().enumerate() {
+            for (_, b) in regions.iter().skip(i + 1) {
+                area += polygon_area(&clip_convex(a, b));
+            }
+        }
+        return area;
+    }
+
+    /// The node's share of the squared distances between grouped nodes and
+    /// their group's centroid, if the node were at `position`: its squared
+    /// distances to the rest of its group over the group's size.
+    pub fn cluster_attraction_at(
+        &self,
+        idx: NodeIndex,
+        position: NodeGeo,
+    ) -> f64 {
+        let group = match self.graph.node_weight(idx).and_then(|n| n.group) {
+            Some(group) => group,
+            None => return 0.0,
+        };
+        let members: Vec<NodeGeo> = self
+            .graph
+            .node_indices()
+            .filter(|n| self.graph[*n].group == Some(group))
+            .filter(|n| *n != idx)
+            .filter_map(|n| self.node_geo(n).copied())
+            .collect();
+        return members
+            .iter()
+            .map(|p| (p.x - position.x).powi(2) + (p.y - position.y).powi(2))
+            .sum::<f64>()
+            / (members.len() + 1) as f64;
+    }
+
+    /// Squared distances of grouped nodes from their group's centroid.
+    pub fn cluster_attraction(&self) -> f64 {
+        return self
+            .clusters()
+            .values()
+            .map(|members| {
+                let points: Vec<NodeGeo> = members
+                    .iter()
+                    .filter_map(|n| self.node_geo(*n).copied())
+                    .collect();
+                let centroid = points
+                    .iter()
+                    .fold(Coordinate { x: 0.0, y: 0.0 }, |sum, p| sum + *p)
+                    / points.len().max(1) as f64;
+                points
+                    .iter()
+                    .map(|p| {
+                        (p.x - centroid.x).powi(2) + (p.y - centroid.y).powi(2)
+                    })
+                    .sum::<f64>()
+            })
+            .sum();
+    }
+
+    pub fn cluster_metrics(
+        &self,
+        settings: &ClusterSettings,
+    ) -> ClusterMetrics {
+        let clusters = self.clusters();
+        let regions = self.cluster_regions_with(settings, None);
+        let intruders = clusters
+            .iter()
+            .flat_map(|(group, members)| {
+                members.iter().map(move |n| (group, *n))
+            })
+            .filter(|(group, n)| {
+                let p = match self.node_geo(*n) {
+                    Some(p) => *p,
+                    None => return false,
+                };
+                regions.iter().any(|(other, region)| {
+                    other != *group && inside_convex(region, p)
+                })
+            })
+            .count();
+
+        let mean_distance =
+            |p: NodeGeo, members: &[NodeIndex], skip: NodeIndex| {
+                let distances: Vec<f64> = members
+                    .iter()
+                    .filter(|m| **m != skip)
+                    .filter_map(|m| Some(distance(p, *self.node_geo(*m)?)))
+                    .collect();
+                if distances.is_empty() {
+                    None
+                } else {
+                    Some(distances.iter().sum::<f64>() / distances.len() as f64)
+                }
+            };
+        let silhouettes: Vec<f64> = clusters
+            .iter()
+            .flat_map(|(group, members)| {
+                members.iter().map(move |n| (group, members, *n))
+            })
+            .filter_map(|(group, members, n)| {
+                let p = *self.node_geo(n)?;
+                let own = mean_distance(p, members, n).unwrap_or(0.0);
+                let nearest = clusters
+                    .iter()
+                    .filter(|(other, _)| *other != group)
+                    .filter_map(|(_, others)| mean_distance(p, others, n))
+                    .fold(f64::INFINITY, f64::min);
+                if !nearest.is_finite() || own.max(nearest) == 0.0 {
+                    return None;
+                }
+                Some((nearest - own) / own.max(nearest))
+            })
+            .collect();
+        return ClusterMetrics {
+            overlap_area: self.cluster_overlap_with(settings, None),
+            intruders: intruders,
+            silhouette: silhouettes.iter().sum::<f64>()
+                / silhouettes.len().max(1) as f64,
+        };
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Each group's region as `{ group, polygon }`, for drawing.
+    pub fn cluster_regions(
+        &self,
+        settings: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let settings: ClusterSettings = settings_from_js(&settings)?;
+        let regions: Vec<ClusterRegion> = self
+            .cluster_regions_with(&settings, None)
+            .into_iter()
+            .map(|(group, polygon)| ClusterRegion {
+                group: group,
+                polygon: polygon.iter().map(|p| (p.x, p.y)).collect(),
+            })
+            .collect();
+        return Ok(JsValue::from_serde(&regions).unwrap());
+    }
+
+    /// How well the groups are separated: region overlap, nodes inside
+    /// other groups' regions and mean silhouette.
+    pub fn evaluate_clusters(
+        &self,
+        settings: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let settings: ClusterSettings = settings_from_js(&settings)?;
+        let metrics = self.cluster_metrics(&settings);
+        return Ok(JsValue::from_serde(&metrics).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::NodeData;
+    use crate::optimize::objective::LayoutObjective;
+    use crate::optimize::{DescentSettings, Optimizer, OptimizerSettings};
+    use crate::test_utils::{edge, graph, id, node};
+
+    #[test]
+    fn test_convex_clipping() {
+        let square = |x: f64, y: f64, side: f64| {
+            convex_hull(vec![
+                Coordinate { x: x, y: y },
+                Coordinate { x: x + side, y: y },
+                Coordinate {
+                    x: x + side,
+                    y: y + side,
+                },
+                Coordinate { x: x, y: y + side },
+                Coordinate {
+                    x: x + side / 2.0,
+                    y: y + side / 2.0,
+                },
+            ])
+        };
+        assert_eq!(square(0.0, 0.0, 2.0).len(), 4);
+        let overlap =
+            clip_convex(&square(0.0, 0.0, 2.0), &square(1.0, 1.0, 2.0));
+        assert!((polygon_area(&overlap) - 1.0).abs() < 1e-9);
+        assert!(clip_convex(&square(0.0, 0.0, 1.0), &square(5.0, 5.0, 1.0))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_clusters_pull_apart() {
+        // Two groups, interleaved along a line.
+        let grouped = |i: usize| NodeData {
+            group: Some(id(if i % 2 == 0 { "even" } else { "odd" })),
+            ..node(i, i as f64 * 20.0, (i % 3) as f64 * 10.0)
+        };
+        let mut layout = graph(
+            (0..8).map(grouped).collect(),
+            (0..7).map(|i| edge(i, i + 1)).collect(),
+        );
+        let settings = ClusterSettings::default();
+        let before = layout.cluster_metrics(&settings);
+        assert!(before.overlap_area > 0.0);
+        assert!(before.silhouette < 0.1);
+
+        let objective = LayoutObjective {
+            cluster_attraction: 0.01,
+            cluster_overlap: 0.1,
+            ..LayoutObjective::default()
+        };
+        // The cluster terms' local deltas agree with the whole objective.
+        let a = layout.node_index(&id(3)).unwrap();
+        let to = Coordinate { x: 90.0, y: 40.0 };
+        let total =
+            |layout: &GraphLayout| layout.objective_breakdown(&objective).total;
+        let start = total(&layout);
+        let delta = layout.objective_delta(&objective, a, to);
+        layout.set_node_geo(a, to);
+        assert!((total(&layout) - start - delta).abs() < 1e-6);
+
+        let mut optimizer = Optimizer::new(
+            OptimizerSettings::Descent(DescentSettings {
+                step_size: 20.0,
+                objective: objective,
+            }),
+            7,
+        );
+        optimizer.run(&mut layout, 3000, |_| true);
+        let after = layout.cluster_metrics(&settings);
+        assert!(after.overlap_area < before.overlap_area);
+        assert!(after.silhouette > before.silhouette);
+    }
+}
@@ -1,14 +1,16 @@
 use bimap::BiMap;
 use fixed::types::I32F32;
 use geo::algorithm::intersects::Intersects;
-use geo::{Coordinate, Line};
+use geo::{Coordinate, Line, LineString};
 use rstar::RTree;
+pub mod grid;
 pub mod tree;
 use log::info;
 use num_traits::pow::Pow;
 use petgraph::visit::EdgeRef;
+use petgraph::{Incoming, Outgoing};
 
-type Fixed = I32F32;
+pub type Fixed = I32F32;
 type EdgeLine = Line<f64>;
 pub type NodeGeo = Coordinate<f64>;
 
@@ -21,22 +23,113 @@ pub fn quantize(n: NodeGeo, grid_power: isize) -> (Fixed, Fixed) {
     return (to_grid(n.x, grid_power), to_grid(n.y, grid_power));
 }
 
-#[derive(Clone, Copy, Debug)]
+const CURVE_SEGMENTS: usize = 16;
+
+/// Distances below this count as this when computing charges, so that
+/// nodes on top of each other repel strongly instead of infinitely.
+pub const MIN_DISTANCE: f64 = 1e-3;
+
+/// Repulsion between two nodes `distance_squared` apart.
+pub fn charge(distance_squared: f64) -> f64 {
+    return 1.0 / distance_squared.max(MIN_DISTANCE * MIN_DISTANCE);
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EdgeShape {
+    Straight,
+    /// Bend points visited in order between the two endpoints.
+    Polyline(Vec<NodeGeo>),
+    /// Quadratic Bézier with a single control point.
+    Quadratic(NodeGeo),
+    /// Cubic Bézier with two control points.
+    Cubic(NodeGeo, NodeGeo),
+}
+
+impl Default for EdgeShape {
+    fn default() -> Self {
+        return EdgeShape::Straight;
+    }
+}
+
+impl EdgeShape {
+    pub fn is_straight(&self) -> bool {
+        return *self == EdgeShape::Straight;
+    }
+
+    /// The same shape with every bend or control point sent through `f`.
+    pub fn map_points(&self, f: impl Fn(NodeGeo) -> NodeGeo) -> EdgeShape {
+        return match self {
+            EdgeShape::Straight => EdgeShape::Straight,
+            EdgeShape::Polyline(bends) => {
+                EdgeShape::Polyline(bends.iter().map(|p| f(*p)).collect())
+            }
+            EdgeShape::Quadratic(c) => EdgeShape::Quadratic(f(*c)),
+            EdgeShape::Cubic(c1, c2) => EdgeShape::Cubic(f(*c1), f(*c2)),
+        };
+    }
+
+    /// The same shape shifted by `offset`, for moving along with its nodes.
+    pub fn translated(&self, offset: NodeGeo) -> EdgeShape {
+        return self.map_points(|p| p + offset);
+    }
+
+    /// Approximates the shape by a polyline from `a` to `b`, or `None` if
+    /// it's just the straight segment.
+    pub fn flatten(&self, a: NodeGeo, b: NodeGeo) -> Option<Vec<NodeGeo>> {
+        let steps =
+            (0..=CURVE_SEGMENTS).map(|i| i as f64 / CURVE_SEGMENTS as f64);
+        return match self {
+            EdgeShape::Straight => None,
+            EdgeShape::Polyline(bends) => Some(
+                std::iter::once(a)
+                    .chain(bends.iter().copied())
+                    .chain(std::iter::once(b))
+                    .collect(),
+            ),
+            EdgeShape::Quadratic(c) => Some(
+                steps
+                    .map(|t| {
+                        let s = 1.0 - t;
+                        a * (s * s) + *c * (2.0 * s * t) + b * (t * t)
+                    })
+                    .collect(),
+            ),
+            EdgeShape::Cubic(c1, c2) => Some(
+                steps
+                    .map(|t| {
+                        let s = 1.0 - t;
+                        a * (s * s * s)
+                            + *c1 * (3.0 * s * s * t)
+                            + *c2 * (3.0 * s * t * t)
+                            + b * (t * t * t)
+                    })
+                    .collect(),
+            ),
+        };
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct EdgeGeo {
     line: EdgeLine,
+    path: Option<LineString<f64>>,
 }
 
 impl EdgeGeo {
     pub fn to_grid(&self, grid_power: isize) -> EdgeGeo {
+        let snap = |c: NodeGeo| match quantize(c, grid_power) {
+            (x, y) => Coordinate {
+                x: Fixed::to_num::<f64>(x),
+                y: Fixed::to_num::<f64>(y),
+            },
+        };
         let mut fixed_edge = self.clone();
-        match fixed_edge.quantized_coordinates(grid_power) {
-            ((x1, y1), (x2, y2)) => {
-                fixed_edge.line.start.x = Fixed::to_num::<f64>(x1);
-                fixed_edge.line.start.y = Fixed::to_num::<f64>(y1);
-                fixed_edge.line.end.x = Fixed::to_num::<f64>(x2);
-                fixed_edge.line.end.y = Fixed::to_num::<f64>(y2);
-            }
-        }
+        fixed_edge.line =
+            EdgeLine::new(snap(self.line.start), snap(self.line.end));
+        fixed_edge.path = self
+            .path
+            .as_ref()
+            .map(|path| path.0.iter().map(|c| snap(*c)).collect());
         return fixed_edge;
     }
     pub fn quantized_coordinates(
@@ -48,26 +141,68 @@ impl EdgeGeo {
             quantize(self.line.end, grid_power),
         );
     }
+    pub fn quantized_points(&self, grid_power: isize) -> Vec<(Fixed, Fixed)> {
+        return self
+            .points()
+            .into_iter()
+            .map(|c| quantize(c, grid_power))
+            .collect();
+    }
     pub fn new(a: NodeGeo, b: NodeGeo) -> EdgeGeo {
         EdgeGeo {
             line: EdgeLine::new(a, b),
+            path: None,
+        }
+    }
+    pub fn with_shape(a: NodeGeo, b: NodeGeo, shape: &EdgeShape) -> EdgeGeo {
+        EdgeGeo {
+            line: EdgeLine::new(a, b),
+            path: shape.flatten(a, b).map(LineString::from),
         }
     }
+    pub fn is_straight(&self) -> bool {
+        return self.path.is_none();
+    }
+    /// The straight segment between the endpoints, regardless of shape.
+    pub fn chord(&self) -> EdgeLine {
+        return self.line;
+    }
+    pub fn points(&self) -> Vec<NodeGeo> {
+        return match &self.path {
+            Some(path) => path.0.clone(),
+            None => vec![self.line.start, self.line.end],
+        };
+    }
+    pub fn segments(&self) -> Vec<EdgeLine> {
+        return match &self.path {
+            Some(path) => path.lines().collect(),
+            None => vec![self.line],
+        };
+    }
 }
 
+/// Uses the robust predicates in `intersections`, so that moving a node by
+/// a tiny amount can't flip the answer for nearly degenerate edges.
 impl Intersects for EdgeGeo {
     fn intersects(&self, other: &Self) -> bool {
-        return self.line.intersects(&other.line);
+        if self.is_straight() && other.is_straight() {
+            return segments_intersect(&self.line, &other.line);
+        }
+        let others = other.segments();
+        return self
+            .segments()
+            .iter()
+            .any(|a| others.iter().any(|b| segments_intersect(a, b)));
     }
 }
 
 impl PartialEq for EdgeGeo {
     fn eq(&self, other: &Self) -> bool {
-        return self.quantized_coordinates(0) == other.quantized_coordinates(0);
+        return self.quantized_points(0) == other.quantized_points(0);
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum GraphGeoElement {
     Node(NodeIndex, NodeGeo),
     Edge(EdgeIndex, EdgeGeo),
@@ -94,7 +229,7 @@ impl Into<Option<NodeIndex>> for &GraphGeoElement {
 impl Into<Option<EdgeGeo>> for &GraphGeoElement {
     fn into(self) -> Option<EdgeGeo> {
         match self {
-            Edge(_, geo) => Some(*geo),
+            Edge(_, geo) => Some(geo.clone()),
             _ => None,
         }
     }
@@ -118,17 +253,22 @@ pub struct GraphGeo {
     pub edges: BiMap<EdgeIndex, GraphGeoElement>,
 }
 
+use crate::intersections::segments_intersect;
 use crate::layout::{GraphLayout, NodeData};
 use petgraph::graph::{EdgeIndex, NodeIndex};
 
 impl GraphLayout {
     pub fn edge_geo(&self, idx: EdgeIndex) -> Option<EdgeGeo> {
+        if let Some(segment) = self.graph.edge_weight(idx)?.segment_of {
+            return self.edge_geo(segment);
+        }
+
         let endpoints_geo = |(a, b): (NodeIndex, NodeIndex)| {
             Some((self.node_geo(a), self.node_geo(b)))
         };
 
         let edge_geo = |(a, b): (Option<&NodeGeo>, Option<&NodeGeo>)| {
-            Some(EdgeGeo::new(*a?, *b?))
+            Some(EdgeGeo::with_shape(*a?, *b?, &self.graph[idx].shape))
         };
 
         return self
@@ -138,27 +278,40 @@ impl GraphLayout {
             .and_then(edge_geo);
     }
 
+    pub fn edge_endpoints_geo(
+        &self,
+        idx: EdgeIndex,
+    ) -> Option<(NodeGeo, NodeGeo)> {
+        let (a, b) = self.graph.edge_endpoints(idx)?;
+        return Some((*self.node_geo(a)?, *self.node_geo(b)?));
+    }
+
     pub fn edges_geo(&self) -> Vec<EdgeGeo> {
         return self
-            .graph
-            .edge_indices()
+            .segment_edge_indices()
             .filter_map(|e| self.edge_geo(e))
             .collect();
     }
 
     fn update_graph_geo_tree_for_nodes(&mut self, nodes_idx: Vec<NodeIndex>) {
         for idx in nodes_idx.iter() {
+            let fresh = self.graph_geo.nodes.get_by_left(&idx).cloned();
             self.graph_geo.remove_node(*idx);
-            match self.graph_geo.nodes.clone().get_by_left(&idx) {
+            match fresh {
                 Some(Node(_, node_geo)) => {
-                    self.graph_geo.insert_node(*idx, *node_geo);
+                    self.graph_geo.insert_node(*idx, node_geo);
                 }
                 _ => {}
             };
         }
         let associated_edges: Vec<EdgeIndex> = nodes_idx
             .iter()
-            .flat_map(|idx| self.graph.edges(*idx))
+            .flat_map(|idx| {
+                self.graph
+                    .edges_directed(*idx, Outgoing)
+                    .chain(self.graph.edges_directed(*idx, Incoming))
+            })
+            .filter(|r| r.weight().segment_of.is_none())
             .map(|r| r.id())
             .collect();
         // info!(
@@ -189,10 +342,18 @@ impl GraphLayout {
         }
     }
 
+    /// Moves a node, snapping it to the grid in grid mode. Callers are
+    /// responsible for keeping grid cells unique; see `place_node`.
     pub fn set_node_geo(&mut self, idx: NodeIndex, position: NodeGeo) {
+        let position = match self.options.grid_power {
+            Some(grid_power) => grid::snap(position, grid_power),
+            None => position,
+        };
         // info!("position: {}, {}", position.x, position.y);
         // info!("set_node_geo before: {}", before.is_some());
-        self.graph_geo.nodes.insert(idx, Node(idx, position));
+        self.stretch_edge_shapes(idx, position);
+        self.graph_geo.insert_node(idx, position);
+        self.node_positions.insert(idx, position);
         self.update_graph_geo_tree_for_node(idx);
         // info!("set_node_geo after: {}", after.unwrap().x);
     }
@@ -0,0 +1,250 @@
+use super::{quantize, Fixed, NodeGeo};
+use crate::layout::GraphLayout;
+use geo::{Coordinate, Point};
+use num_traits::pow::Pow;
+use petgraph::graph::NodeIndex;
+use rstar::RTreeObject;
+use wasm_bindgen::prelude::*;
+
+/// Distance between neighbouring grid lines for a `grid_power`, matching
+/// `to_grid`: positive powers give finer grids, negative ones coarser.
+pub fn grid_spacing(grid_power: isize) -> f64 {
+    return 2.0f64.pow(-grid_power as f64);
+}
+
+/// The nearest grid point, as a float that converts back to the same
+/// fixed-point value exactly.
+pub fn snap(position: NodeGeo, grid_power: isize) -> NodeGeo {
+    let (x, y) = quantize(position, grid_power);
+    return Coordinate {
+        x: Fixed::to_num::<f64>(x),
+        y: Fixed::to_num::<f64>(y),
+    };
+}
+
+impl GraphLayout {
+    pub fn grid_power(&self) -> Option<isize> {
+        return self.options.grid_power;
+    }
+
+    /// The node sitting exactly on the grid point `position`, if any.
+    pub fn grid_occupant(&self, position: NodeGeo) -> Option<NodeIndex> {
+        return self
+            .graph_geo
+            .nodes_in_envelope(&Point::from(position).envelope())
+            .map(|(idx, _)| *idx)
+            .next();
+    }
+
+    fn cell_is_free(&self, idx: NodeIndex, position: NodeGeo) -> bool {
+        return self.grid_occupant(position).map_or(true, |n| n == idx);
+    }
+
+    /// The closest grid point to `position` that no other node occupies,
+    /// searching outwards one ring of cells at a time.
+    pub fn nearest_free_cell(
+        &self,
+        idx: NodeIndex,
+        position: NodeGeo,
+        grid_power: isize,
+    ) -> NodeGeo {
+        let spacing = grid_spacing(grid_power);
+        let center = snap(position, grid_power);
+        let rings = self.graph.node_count() as i64 + 1;
+        for ring in 0..=rings {
+            let free = (-ring..=ring)
+                .flat_map(|i| (-ring..=ring).map(move |j| (i, j)))
+                .filter(|(i, j)| i.abs().max(j.abs()) == ring)
+                .map(|(i, j)| Coordinate {
+                    x: center.x + i as f64 * spacing,
+                    y: center.y + j as f64 * spacing,
+                })
+                .filter(|cell| self.cell_is_free(idx, *cell))
+                .min_by(|a, b| {
+                    let d = |c: &NodeGeo| {
+                        (c.x - position.x).powi(2) + (c.y - position.y).powi(2)
+                    };
+                    d(a).partial_cmp(&d(b)).unwrap()
+                });
+            if let Some(cell) = free {
+                return cell;
+            }
+        }
+        return center;
+    }
+
+    /// Moves a node as close to `position` as it can go. In grid mode that's
+    /// the nearest free cell; otherwise it's `position` itself.
+    pub fn place_node(&mut self, idx: NodeIndex, position: NodeGeo) {
+        let position = match self.grid_power() {
+            Some(grid_power) => {
+                self.nearest_free_cell(idx, position, grid_power)
+            }
+            None => position,
+        };
+        self.set_node_geo(idx, position);
+    }
+
+    /// Moves several nodes at once with `place_node`. In grid mode they're
+    /// all lifted off the grid first, so that none is pushed aside by a cell
+    /// another one is about to leave.
+    pub fn place_nodes_at(&mut self, moves: Vec<(NodeIndex, NodeGeo)>) {
+        if self.grid_power().is_some() {
+            for (idx, _) in moves.iter() {
+                self.graph_geo.remove_node(*idx);
+            }
+        }
+        for (idx, position) in moves {
+            self.place_node(idx, position);
+        }
+    }
+
+    /// The position `steps` grid cells away from the node, if that cell is
+    /// free.
+    pub fn grid_step(
+        &self,
+        idx: NodeIndex,
+        (i, j): (i64, i64),
+        grid_power: isize,
+    ) -> Option<NodeGeo> {
+        let spacing = grid_spacing(grid_power);
+        let from = *self.node_geo(idx)?;
+        let to = snap(
+            Coordinate {
+                x: from.x + i as f64 * spacing,
+                y: from.y + j as f64 * spacing,
+            },
+            grid_power,
+        );
+        if to == from || !self.cell_is_free(idx, to) {
+            return None;
+        }
+        return Some(to);
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Switches to grid mode, moving every node to the nearest free point of
+    /// a grid with spacing `2^-grid_power`. This clears any symmetry, which
+    /// can't be kept on a grid.
+    pub fn snap_to_grid(&mut self, grid_power: i32) {
+        self.checkpoint_with_label("snap to grid");
+        self.options.grid_power = Some(grid_power as isize);
+        self.symmetry = None;
+        let nodes: Vec<(NodeIndex, NodeGeo)> = self
+            .node_positions
+            .iter()
+            .map(|(idx, geo)| (*idx, *geo))
+            .collect();
+        self.place_nodes_at(nodes);
+    }
+
+    /// Lets nodes move freely again, as one undo step. They stay where
+    /// they are.
+    pub fn leave_grid(&mut self) {
+        self.checkpoint_with_label("leave grid");
+        self.options.grid_power = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Snapshot;
+    use crate::layout::LayoutOptions;
+    use crate::optimize::{Optimizer, OptimizerSettings};
+    use crate::test_utils::{edge, graph, graph_with, node};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_grid_nodes_stay_on_unique_cells() {
+        let grid_power = -2;
+        let mut layout = graph_with(
+            vec![
+                node("a", 0., 0.),
+                node("b", 0.5, 0.5),
+                node("c", 1., -1.),
+                node("d", 9., 3.),
+            ],
+            vec![edge("a", "b"), edge("c", "d"), edge("b", "d")],
+            LayoutOptions {
+                grid_power: Some(grid_power),
+                ..LayoutOptions::default()
+            },
+        );
+        let spacing = grid_spacing(grid_power);
+        let check = |layout: &GraphLayout| {
+            let cells: HashSet<(i64, i64)> = layout
+                .node_positions
+                .values()
+                .map(|p| {
+                    assert_eq!(p.x % spacing, 0.0);
+                    assert_eq!(p.y % spacing, 0.0);
+                    ((p.x / spacing) as i64, (p.y / spacing) as i64)
+                })
+                .collect();
+            assert_eq!(cells.len(), 4);
+        };
+        check(&layout);
+
+        let mut optimizer = Optimizer::new(OptimizerSettings::default(), 3);
+        for _ in 0..500 {
+            optimizer.step(&mut layout);
+        }
+        check(&layout);
+
+        // Restores, transitions and buffers that would stack nodes in one
+        // cell spread them out instead.
+        let start = layout.snapshot("start");
+        let stacked = Snapshot {
+            label: "stacked".to_string(),
+            positions: start
+                .positions
+                .keys()
+                .map(|idx| (*idx, Coordinate { x: 0.5, y: 0.5 }))
+                .collect(),
+        };
+        layout.restore_positions(&stacked.positions);
+        check(&layout);
+        layout.restore_positions(&start.positions);
+        assert_eq!(layout.node_positions, start.positions);
+        layout.set_positions(&[0.0; 8]);
+        check(&layout);
+        layout.start_transition_to(&stacked, 4, Default::default());
+        while layout.advance_transition() {
+            check(&layout);
+        }
+        assert!(layout.undo());
+        check(&layout);
+    }
+
+    #[test]
+    fn test_snapping_can_be_undone() {
+        let mut layout = graph(
+            vec![
+                node("a", 0.3, 0.1),
+                node("b", 0.4, 0.2),
+                node("c", 7.7, 2.5),
+            ],
+            vec![edge("a", "b")],
+        );
+        let start = layout.node_positions.clone();
+        layout.snap_to_grid(0);
+        let snapped = layout.node_positions.clone();
+        assert_ne!(snapped, start);
+
+        assert!(layout.undo());
+        assert_eq!(layout.grid_power(), None);
+        assert_eq!(layout.node_positions, start);
+        assert!(layout.redo());
+        assert_eq!(layout.grid_power(), Some(0));
+        assert_eq!(layout.node_positions, snapped);
+
+        layout.leave_grid();
+        assert_eq!(layout.grid_power(), None);
+        assert!(layout.undo());
+        assert_eq!(layout.grid_power(), Some(0));
+        assert_eq!(layout.node_positions, snapped);
+    }
+}
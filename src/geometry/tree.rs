@@ -49,12 +49,12 @@ impl GraphGeo {
     ) -> bimap::Overwritten<NodeIndex, GraphGeoElement> {
         // info!("added node to tree: ({},{})", geo.x, geo.y);
         let fresh = Node(idx, geo);
-        let result = self.nodes.insert(idx, fresh);
-        match result {
+        let result = self.nodes.insert(idx, fresh.clone());
+        match &result {
             Overwritten::Left(_, stale)
             | Overwritten::Pair(_, stale)
             | Overwritten::Both((_, stale), _) => {
-                self.rtree.remove(&stale);
+                self.rtree.remove(stale);
             }
             _ => {}
         };
@@ -72,12 +72,12 @@ impl GraphGeo {
         //     geo.line.start.x, geo.line.start.x, geo.line.end.x, geo.line.end.y
         // );
         let fresh = Edge(idx, geo);
-        let result = self.edges.insert(idx, fresh);
-        match result {
+        let result = self.edges.insert(idx, fresh.clone());
+        match &result {
             Overwritten::Left(_, stale)
             | Overwritten::Pair(_, stale)
             | Overwritten::Both((_, stale), _) => {
-                self.rtree.remove(&stale);
+                self.rtree.remove(stale);
             }
             _ => {}
         };
@@ -108,16 +108,19 @@ impl GraphGeo {
         return self.rtree.locate_in_envelope(envelope);
     }
 
+    /// Edges whose bounding boxes touch `envelope`, not only those lying
+    /// entirely inside it.
     pub fn edges_in_envelope(
         &self,
         envelope: &Envelope,
     ) -> impl Iterator<Item = (&EdgeIndex, &EdgeGeo)> {
-        return self.locate_in_envelope(envelope).filter_map(
-            move |obj| match obj {
+        return self
+            .rtree
+            .locate_in_envelope_intersecting(envelope)
+            .filter_map(move |obj| match obj {
                 Edge(idx, geo) => Some((idx, geo)),
                 _ => None,
-            },
-        );
+            });
     }
 
     pub fn nodes_in_envelope(
@@ -136,7 +139,10 @@ impl GraphGeo {
 impl RTreeObject for EdgeGeo {
     type Envelope = Envelope;
     fn envelope(&self) -> Self::Envelope {
-        return self.line.envelope();
+        return match &self.path {
+            Some(path) => path.envelope(),
+            None => self.line.envelope(),
+        };
     }
 }
 
@@ -0,0 +1,308 @@
+use crate::geometry::NodeGeo;
+use crate::layout::{GraphLayout, NodeDataId};
+use geo::Coordinate;
+use im::OrdMap;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+const MAX_UNDO: usize = 100;
+
+/// Every node's position at some moment. Positions live in a persistent map,
+/// so taking a snapshot shares structure with the layout instead of copying.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub label: String,
+    pub positions: OrdMap<NodeIndex, NodeGeo>,
+}
+
+/// A snapshot keyed by node id, for saving as JSON.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotData {
+    pub label: String,
+    pub positions: BTreeMap<NodeDataId, (f64, f64)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotSummary {
+    pub index: usize,
+    pub label: String,
+    pub nodes: usize,
+}
+
+/// An undo or redo step: the positions, and the grid mode they were in, as
+/// snapping to or leaving the grid changes both.
+#[derive(Clone, Debug)]
+struct Step {
+    snapshot: Snapshot,
+    grid_power: Option<isize>,
+}
+
+#[derive(Default, Debug)]
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    saved: Vec<Snapshot>,
+}
+
+impl GraphLayout {
+    pub fn snapshot(&self, label: &str) -> Snapshot {
+        return Snapshot {
+            label: label.to_string(),
+            positions: self.node_positions.clone(),
+        };
+    }
+
+    /// Moves every node that's somewhere else in `positions`, through
+    /// `place_nodes_at`.
+    pub fn restore_positions(
+        &mut self,
+        positions: &OrdMap<NodeIndex, NodeGeo>,
+    ) {
+        let moved: Vec<(NodeIndex, NodeGeo)> = positions
+            .iter()
+            .filter(|(idx, geo)| self.node_positions.get(idx) != Some(geo))
+            .filter(|(idx, _)| self.graph.contains_node(**idx))
+            .map(|(idx, geo)| (*idx, *geo))
+            .collect();
+        self.place_nodes_at(moved);
+    }
+
+    fn step(&self, label: &str) -> Step {
+        return Step {
+            snapshot: self.snapshot(label),
+            grid_power: self.options.grid_power,
+        };
+    }
+
+    fn restore_step(&mut self, step: &Step) {
+        self.options.grid_power = step.grid_power;
+        self.restore_positions(&step.snapshot.positions);
+    }
+
+    /// Records the current positions and grid mode as an undo step, e.g.
+    /// before a drag or an optimizer run, and forgets anything that could be
+    /// redone.
+    pub fn checkpoint_with_label(&mut self, label: &str) {
+        let step = self.step(label);
+        self.history.undo.push(step);
+        if self.history.undo.len() > MAX_UNDO {
+            self.history.undo.remove(0);
+        }
+        self.history.redo.clear();
+    }
+
+    pub fn snapshot_data(&self, snapshot: &Snapshot) -> SnapshotData {
+        return SnapshotData {
+            label: snapshot.label.clone(),
+            positions: snapshot
+                .positions
+                .iter()
+                .filter_map(|(idx, geo)| {
+                    Some((self.node_id(*idx)?, (geo.x, geo.y)))
+                })
+                .collect(),
+        };
+    }
+
+    /// Turns saved data back into a snapshot, skipping ids that aren't in
+    /// this layout.
+    pub fn snapshot_from_data(&self, data: &SnapshotData) -> Snapshot {
+        return Snapshot {
+            label: data.label.clone(),
+            positions: data
+                .positions
+                .iter()
+                .filter_map(|(id, (x, y))| {
+                    Some((self.node_index(id)?, Coordinate { x: *x, y: *y }))
+                })
+                .collect(),
+        };
+    }
+
+    pub fn saved_snapshot(&self, index: usize) -> Option<&Snapshot> {
+        return self.history.saved.get(index);
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    pub fn checkpoint(&mut self, label: Option<String>) {
+        self.checkpoint_with_label(&label.unwrap_or_default());
+    }
+
+    pub fn undo(&mut self) -> bool {
+        let previous = match self.history.undo.pop() {
+            Some(step) => step,
+            None => return false,
+        };
+        let current = self.step(&previous.snapshot.label);
+        self.history.redo.push(current);
+        self.restore_step(&previous);
+        return true;
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let next = match self.history.redo.pop() {
+            Some(step) => step,
+            None => return false,
+        };
+        let current = self.step(&next.snapshot.label);
+        self.history.undo.push(current);
+        self.restore_step(&next);
+        return true;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        return !self.history.undo.is_empty();
+    }
+
+    pub fn can_redo(&self) -> bool {
+        return !self.history.redo.is_empty();
+    }
+
+    /// Saves the current positions under a label and returns the snapshot's
+    /// index.
+    pub fn take_snapshot(&mut self, label: String) -> usize {
+        let snapshot = self.snapshot(&label);
+        self.history.saved.push(snapshot);
+        return self.history.saved.len() - 1;
+    }
+
+    pub fn snapshots(&self) -> JsValue {
+        let summaries: Vec<SnapshotSummary> = self
+            .history
+            .saved
+            .iter()
+            .enumerate()
+            .map(|(i, snapshot)| SnapshotSummary {
+                index: i,
+                label: snapshot.label.clone(),
+                nodes: snapshot.positions.len(),
+            })
+            .collect();
+        return JsValue::from_serde(&summaries).unwrap();
+    }
+
+    /// Moves the nodes back to a saved snapshot. This can be undone.
+    pub fn restore_snapshot(&mut self, index: usize) -> bool {
+        let snapshot = match self.history.saved.get(index) {
+            Some(snapshot) => snapshot.clone(),
+            None => return false,
+        };
+        self.checkpoint_with_label(&snapshot.label);
+        self.restore_positions(&snapshot.positions);
+        return true;
+    }
+
+    pub fn remove_snapshot(&mut self, index: usize) -> bool {
+        if index >= self.history.saved.len() {
+            return false;
+        }
+        self.history.saved.remove(index);
+        return true;
+    }
+
+    /// A saved snapshot as a plain object, ready for `JSON.stringify`.
+    pub fn export_snapshot(&self, index: usize) -> JsValue {
+        return match self.history.saved.get(index) {
+            Some(snapshot) => {
+                JsValue::from_serde(&self.snapshot_data(snapshot)).unwrap()
+            }
+            None => JsValue::UNDEFINED,
+        };
+    }
+
+    /// Adds a snapshot previously produced by `export_snapshot`, returning
+    /// its index.
+    pub fn import_snapshot(&mut self, data: JsValue) -> Option<usize> {
+        let data: SnapshotData = data.into_serde().ok()?;
+        let snapshot = self.snapshot_from_data(&data);
+        self.history.saved.push(snapshot);
+        return Some(self.history.saved.len() - 1);
+    }
+
+    pub fn set_node_position(&mut self, id: &str, x: f64, y: f64) -> bool {
+        return match self.node_index(&NodeDataId::from_str_truncate(id)) {
+            Some(idx) => {
+                self.place_node(idx, Coordinate { x: x, y: y });
+                true
+            }
+            None => false,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{graph, node};
+
+    #[test]
+    fn test_undo_redo_and_snapshots() {
+        let mut layout =
+            graph(vec![node("a", 0.0, 0.0), node("b", 1.0, 0.0)], vec![]);
+        let x_of_a =
+            |layout: &GraphLayout| layout.all_nodes_data()[0].x.unwrap();
+        let saved = layout.take_snapshot("start".to_string());
+
+        layout.checkpoint(Some("drag".to_string()));
+        layout.set_node_position("a", 5.0, 5.0);
+        assert!(layout.undo());
+        assert_eq!(x_of_a(&layout), 0.0);
+        assert!(layout.redo());
+        assert_eq!(x_of_a(&layout), 5.0);
+        assert!(!layout.redo());
+
+        let data = layout.snapshot_data(layout.saved_snapshot(saved).unwrap());
+        assert_eq!(data.positions.len(), 2);
+        let restored = layout.snapshot_from_data(&data);
+        layout.restore_positions(&restored.positions);
+        assert_eq!(x_of_a(&layout), 0.0);
+    }
+
+    /// `node_positions` mirrors `graph_geo` for every node in the graph.
+    fn assert_positions_in_sync(layout: &GraphLayout) {
+        assert_eq!(layout.node_positions.len(), layout.graph.node_count());
+        for idx in layout.graph.node_indices() {
+            assert_eq!(
+                layout.node_positions.get(&idx),
+                layout.node_geo(idx),
+                "{:?} is out of sync",
+                idx
+            );
+        }
+    }
+
+    #[test]
+    fn test_positions_stay_in_sync() {
+        let diagonal = |name, x: f64| node(name, x, x / 2.0);
+        let mut layout = graph(
+            vec![diagonal("a", 0.0), diagonal("b", 3.3), diagonal("c", 7.1)],
+            vec![],
+        );
+        assert_positions_in_sync(&layout);
+        let start = layout.snapshot("start");
+
+        layout.set_node_position("a", 5.0, 5.0);
+        assert_positions_in_sync(&layout);
+        layout.set_positions(&[1.0, 2.0, 3.0, 4.0]);
+        assert_positions_in_sync(&layout);
+        layout.snap_to_grid(-2);
+        assert_positions_in_sync(&layout);
+        assert!(layout.undo());
+        assert_positions_in_sync(&layout);
+        assert!(layout.redo());
+        assert_positions_in_sync(&layout);
+
+        layout.start_transition_to(&start, 3, Default::default());
+        while layout.advance_transition() {
+            assert_positions_in_sync(&layout);
+        }
+        layout.leave_grid();
+        layout.restore_positions(&start.positions);
+        assert_positions_in_sync(&layout);
+        assert_eq!(layout.node_positions, start.positions);
+    }
+}
@@ -1,26 +1,212 @@
-use crate::geometry::EdgeGeo;
+use crate::geometry::{EdgeGeo, Fixed};
 use crate::layout::GraphLayout;
 use geo::algorithm::intersects::Intersects;
+use geo::Line;
 use petgraph::graph::EdgeIndex;
 use rstar::RTreeObject;
+use std::cmp::Ordering;
+
+type FixedPoint = (Fixed, Fixed);
+
+/// `a * b` as the high and low halves of a 256-bit product.
+fn wide_product(a: u128, b: u128) -> (u128, u128) {
+    let half = |x: u128| (x >> 64, x & u128::from(u64::MAX));
+    let ((a1, a0), (b1, b0)) = (half(a), half(b));
+    let (middle, middle_carry) = (a1 * b0).overflowing_add(a0 * b1);
+    let (low, low_carry) = (a0 * b0).overflowing_add(middle << 64);
+    let high = a1 * b1
+        + (middle >> 64)
+        + (u128::from(middle_carry) << 64)
+        + u128::from(low_carry);
+    return (high, low);
+}
+
+/// Compares `a * b` with `c * d` exactly, for factors below `2^127` in
+/// magnitude.
+fn compare_products(a: i128, b: i128, c: i128, d: i128) -> Ordering {
+    let sign = |x: i128, y: i128| x.signum() * y.signum();
+    let (left, right) = (sign(a, b), sign(c, d));
+    if left != right {
+        return left.cmp(&right);
+    }
+    let magnitude = wide_product(a.unsigned_abs(), b.unsigned_abs())
+        .cmp(&wide_product(c.unsigned_abs(), d.unsigned_abs()));
+    return if left < 0 {
+        magnitude.reverse()
+    } else {
+        magnitude
+    };
+}
+
+/// Which side of the line through `a` and `b` the point `c` is on, computed
+/// exactly from the fixed-point bits. Differences of 64-bit coordinates
+/// take 65 bits, so their products are compared in 256 bits and every
+/// `Fixed` coordinate is safe.
+fn fixed_orientation(a: FixedPoint, b: FixedPoint, c: FixedPoint) -> Ordering {
+    let bits = |(x, y): FixedPoint| (x.to_bits() as i128, y.to_bits() as i128);
+    let ((ax, ay), (bx, by), (cx, cy)) = (bits(a), bits(b), bits(c));
+    return compare_products(bx - ax, cy - ay, by - ay, cx - ax);
+}
+
+/// Whether `c`, known to be collinear with `a` and `b`, lies between them.
+fn within<T: PartialOrd + Copy>(a: (T, T), b: (T, T), c: (T, T)) -> bool {
+    let between = |u: T, v: T, w: T| (u <= w && w <= v) || (v <= w && w <= u);
+    return between(a.0, b.0, c.0) && between(a.1, b.1, c.1);
+}
+
+/// Segment intersection from an orientation predicate, counting touching
+/// endpoints and collinear overlaps as intersections like `geo` does. Exact
+/// whenever `orientation` is.
+fn segments_intersect_by<T: PartialOrd + Copy>(
+    orientation: impl Fn((T, T), (T, T), (T, T)) -> Ordering,
+    (p1, p2): ((T, T), (T, T)),
+    (q1, q2): ((T, T), (T, T)),
+) -> bool {
+    let d1 = orientation(q1, q2, p1);
+    let d2 = orientation(q1, q2, p2);
+    let d3 = orientation(p1, p2, q1);
+    let d4 = orientation(p1, p2, q2);
+    let straddles = |a: Ordering, b: Ordering| {
+        a != Ordering::Equal && b != Ordering::Equal && a != b
+    };
+    if straddles(d1, d2) && straddles(d3, d4) {
+        return true;
+    }
+    return (d1 == Ordering::Equal && within(q1, q2, p1))
+        || (d2 == Ordering::Equal && within(q1, q2, p2))
+        || (d3 == Ordering::Equal && within(p1, p2, q1))
+        || (d4 == Ordering::Equal && within(p1, p2, q2));
+}
+
+pub fn fixed_segments_intersect(
+    p: (FixedPoint, FixedPoint),
+    q: (FixedPoint, FixedPoint),
+) -> bool {
+    return segments_intersect_by(fixed_orientation, p, q);
+}
+
+/// `a + b` as a float and the exact rounding error.
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let x = a + b;
+    let virtual_b = x - a;
+    let virtual_a = x - virtual_b;
+    return (x, (a - virtual_a) + (b - virtual_b));
+}
+
+/// `a * b` as a float and the exact rounding error.
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let x = a * b;
+    return (x, a.mul_add(b, -x));
+}
+
+/// Adds `b` to a nonoverlapping expansion ordered by increasing magnitude,
+/// keeping both properties (Shewchuk's Grow-Expansion).
+fn grow_expansion(expansion: &mut Vec<f64>, b: f64) {
+    let mut q = b;
+    for e in expansion.iter_mut() {
+        let (sum, error) = two_sum(q, *e);
+        *e = error;
+        q = sum;
+    }
+    expansion.push(q);
+}
+
+/// The exact sign of `(ax - cx)(by - cy) - (ay - cy)(bx - cx)`, with every
+/// difference and product kept as an exact sum of floats.
+fn orientation_exact(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> Ordering {
+    let acx = two_sum(a.0, -c.0);
+    let bcy = two_sum(b.1, -c.1);
+    let acy = two_sum(a.1, -c.1);
+    let bcx = two_sum(b.0, -c.0);
+    let mut expansion = vec![];
+    for (u, v, sign) in vec![(acx, bcy, 1.0), (acy, bcx, -1.0)] {
+        for x in vec![u.0, u.1] {
+            for y in vec![v.0, v.1] {
+                let (product, error) = two_product(x, y);
+                grow_expansion(&mut expansion, sign * error);
+                grow_expansion(&mut expansion, sign * product);
+            }
+        }
+    }
+    // In a nonoverlapping expansion the largest nonzero component decides
+    // the sign.
+    return expansion
+        .iter()
+        .rev()
+        .find(|e| **e != 0.0)
+        .map_or(Ordering::Equal, |e| e.partial_cmp(&0.0).unwrap());
+}
+
+/// Which side of the line through `a` and `b` the point `c` is on. Uses
+/// the plain floating-point determinant when it's clearly away from zero
+/// and falls back to exact arithmetic otherwise, so nearly collinear points
+/// get the same answer however they're perturbed.
+pub fn orientation(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> Ordering {
+    let left = (a.0 - c.0) * (b.1 - c.1);
+    let right = (a.1 - c.1) * (b.0 - c.0);
+    let det = left - right;
+    // Shewchuk's error bound for this evaluation order.
+    let epsilon = std::f64::EPSILON / 2.0;
+    let bound = (3.0 + 16.0 * epsilon) * epsilon * (left.abs() + right.abs());
+    if det > bound {
+        return Ordering::Greater;
+    }
+    if -det > bound {
+        return Ordering::Less;
+    }
+    return orientation_exact(a, b, c);
+}
+
+/// Exact segment intersection on floats.
+pub fn segments_intersect(p: &Line<f64>, q: &Line<f64>) -> bool {
+    let ends = |l: &Line<f64>| (l.start.x_y(), l.end.x_y());
+    return segments_intersect_by(orientation, ends(p), ends(q));
+}
+
+/// Whether two edges intersect once their points are on the grid, tested
+/// exactly so the answer doesn't depend on the platform.
+pub fn intersects_on_grid(a: &EdgeGeo, b: &EdgeGeo, grid_power: isize) -> bool {
+    let (a, b) = (
+        a.quantized_points(grid_power),
+        b.quantized_points(grid_power),
+    );
+    return a.windows(2).any(|s| {
+        b.windows(2)
+            .any(|t| fixed_segments_intersect((s[0], s[1]), (t[0], t[1])))
+    });
+}
+
+/// Crossing test used throughout the layout: exact in grid mode, floating
+/// point otherwise.
+pub fn crosses(a: &EdgeGeo, b: &EdgeGeo, grid_power: Option<isize>) -> bool {
+    return match grid_power {
+        Some(grid_power) => intersects_on_grid(a, b, grid_power),
+        None => a.intersects(b),
+    };
+}
 
 pub fn edge_intersects_edges<'a>(
     edge: EdgeGeo,
     edges: impl Iterator<Item = (&'a EdgeIndex, &'a EdgeGeo)>,
+    grid_power: Option<isize>,
 ) -> impl Iterator<Item = (&'a EdgeIndex, &'a EdgeGeo)> {
     return edges.into_iter().filter(move |(_, other_edge)| {
-        edge.intersects(other_edge) && edge != **other_edge
+        crosses(&edge, other_edge, grid_power) && edge != **other_edge
     });
 }
 
 impl GraphLayout {
     pub fn count_edge_intersections(&self, edge_index: EdgeIndex) -> usize {
         match self.edge_geo(edge_index) {
-            Some(edge) => edge_intersects_edges(
-                edge,
-                self.graph_geo.edges_in_envelope(&edge.envelope()),
-            )
-            .count(),
+            Some(edge) => {
+                let envelope = edge.envelope();
+                edge_intersects_edges(
+                    edge,
+                    self.graph_geo.edges_in_envelope(&envelope),
+                    self.grid_power(),
+                )
+                .count()
+            }
             None => 0,
         }
     }
@@ -28,10 +214,15 @@ impl GraphLayout {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use geo::Coordinate;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
 
     #[test]
     fn test_intersection() {
         use geo::algorithm::intersects::Intersects;
+        use geo::Line;
         use geo::{Coordinate, LineString};
 
         let p = |x, y| Coordinate { x: x, y: y };
@@ -43,6 +234,158 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fixed_segments() {
+        use super::fixed_segments_intersect;
+        use crate::geometry::Fixed;
+
+        let p = |x: i32, y: i32| (Fixed::from_num(x), Fixed::from_num(y));
+        let crossing = ((p(0, 0), p(4, 4)), (p(0, 4), p(4, 0)));
+        let touching = ((p(0, 0), p(2, 2)), (p(2, 2), p(4, 0)));
+        let overlapping = ((p(0, 0), p(2, 0)), (p(1, 0), p(3, 0)));
+        let apart = ((p(0, 0), p(2, 0)), (p(3, 0), p(5, 0)));
+        let parallel = ((p(0, 0), p(2, 0)), (p(0, 1), p(2, 1)));
+        assert!(fixed_segments_intersect(crossing.0, crossing.1));
+        assert!(fixed_segments_intersect(touching.0, touching.1));
+        assert!(fixed_segments_intersect(overlapping.0, overlapping.1));
+        assert!(!fixed_segments_intersect(apart.0, apart.1));
+        assert!(!fixed_segments_intersect(parallel.0, parallel.1));
+
+        // Differences across the whole range overflow 64 bits and their
+        // products overflow 128.
+        let (lo, hi) = (Fixed::MIN, Fixed::MAX);
+        let zero = Fixed::from_num(0);
+        let diagonal = ((lo, lo), (hi, hi));
+        assert_eq!(
+            fixed_orientation(diagonal.0, diagonal.1, (lo, hi)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            fixed_orientation(diagonal.0, diagonal.1, (hi, lo)),
+            Ordering::Less
+        );
+        assert_eq!(
+            fixed_orientation(diagonal.0, diagonal.1, (zero, zero)),
+            Ordering::Equal
+        );
+        assert!(fixed_segments_intersect(diagonal, ((lo, hi), (hi, lo))));
+        assert!(!fixed_segments_intersect(
+            diagonal,
+            ((lo, hi), (Fixed::from_bits(-1), hi))
+        ));
+    }
+
+    fn random_point(rng: &mut SmallRng) -> (i64, i64) {
+        return (
+            rng.gen_range(-1 << 26, 1 << 26),
+            rng.gen_range(-1 << 26, 1 << 26),
+        );
+    }
+
+    /// Reference orientation on integers scaled by `2^scale`, exact in
+    /// `i128`.
+    fn integer_orientation(
+        a: (i64, i64),
+        b: (i64, i64),
+        c: (i64, i64),
+    ) -> Ordering {
+        let w = |p: (i64, i64)| (p.0 as i128, p.1 as i128);
+        let (a, b, c) = (w(a), w(b), w(c));
+        return ((a.0 - c.0) * (b.1 - c.1) - (a.1 - c.1) * (b.0 - c.0)).cmp(&0);
+    }
+
+    fn to_float(p: (i64, i64), scale: i32) -> (f64, f64) {
+        let s = 2f64.powi(-scale);
+        return (p.0 as f64 * s, p.1 as f64 * s);
+    }
+
+    #[test]
+    fn test_orientation_matches_exact_reference() {
+        let mut rng = SmallRng::seed_from_u64(38);
+        for _ in 0..20000 {
+            let scale = rng.gen_range(0, 20);
+            let (a, b) = (random_point(&mut rng), random_point(&mut rng));
+            // Points on the line through a and b, then nudged by at most
+            // one unit in the last place.
+            let t = rng.gen_range(-4, 5);
+            let c = (
+                a.0 + t * (b.0 - a.0) / 4 + rng.gen_range(-1, 2),
+                a.1 + t * (b.1 - a.1) / 4 + rng.gen_range(-1, 2),
+            );
+            if c.0.abs() >= 1 << 28 || c.1.abs() >= 1 << 28 {
+                continue;
+            }
+            let expected = integer_orientation(a, b, c);
+            let (fa, fb, fc) =
+                (to_float(a, scale), to_float(b, scale), to_float(c, scale));
+            assert_eq!(orientation(fa, fb, fc), expected);
+            assert_eq!(orientation(fb, fc, fa), expected);
+            assert_eq!(orientation(fb, fa, fc), expected.reverse());
+        }
+    }
+
+    #[test]
+    fn test_segments_match_fixed_point() {
+        let mut rng = SmallRng::seed_from_u64(380);
+        let p =
+            |rng: &mut SmallRng| (rng.gen_range(-4, 5), rng.gen_range(-4, 5));
+        let fixed =
+            |p: (i64, i64)| (Fixed::from_num(p.0), Fixed::from_num(p.1));
+        let float = |p: (i64, i64)| Coordinate {
+            x: p.0 as f64,
+            y: p.1 as f64,
+        };
+        // Small integer coordinates make collinear, touching and zero-length
+        // segments common.
+        for _ in 0..20000 {
+            let (p1, p2, q1, q2) =
+                (p(&mut rng), p(&mut rng), p(&mut rng), p(&mut rng));
+            let expected = fixed_segments_intersect(
+                (fixed(p1), fixed(p2)),
+                (fixed(q1), fixed(q2)),
+            );
+            let s = Line::new(float(p1), float(p2));
+            let t = Line::new(float(q1), float(q2));
+            assert_eq!(segments_intersect(&s, &t), expected);
+            assert_eq!(segments_intersect(&t, &s), expected);
+            let reversed = Line::new(s.end, s.start);
+            assert_eq!(segments_intersect(&reversed, &t), expected);
+        }
+    }
+
+    #[test]
+    fn test_degenerate_segments() {
+        let c = |x, y| Coordinate { x: x, y: y };
+        let line = |a, b| Line::new(a, b);
+        let diagonal = line(c(0.1, 0.1), c(0.7, 0.7));
+        let on = c(0.3, 0.3);
+        // Zero-length segments behave like points.
+        assert!(segments_intersect(&diagonal, &line(on, on)));
+        assert!(segments_intersect(&line(on, on), &line(on, on)));
+        assert!(!segments_intersect(
+            &line(on, on),
+            &line(c(0.3, 0.4), c(0.3, 0.4))
+        ));
+        // Touching at an endpoint, and collinear but apart.
+        assert!(segments_intersect(
+            &diagonal,
+            &line(c(0.7, 0.7), c(1.0, 0.0))
+        ));
+        assert!(!segments_intersect(
+            &diagonal,
+            &line(c(0.8, 0.8), c(0.9, 0.9))
+        ));
+        // A point one ulp off the line is on one side of it, consistently.
+        let nudged = (0.5, 0.5f64 + std::f64::EPSILON / 2.0);
+        let side = orientation((0.1, 0.1), (0.7, 0.7), nudged);
+        assert_eq!(side, Ordering::Greater);
+        assert_eq!(orientation((0.7, 0.7), (0.1, 0.1), nudged), Ordering::Less);
+        assert_eq!(
+            orientation((0.1, 0.1), (0.7, 0.7), (0.5, 0.5)),
+            Ordering::Equal
+        );
+    }
+
     #[test]
     fn test_rstar() {
         use rstar::RTree;
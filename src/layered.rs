@@ -0,0 +1,662 @@
+use crate::geometry::EdgeShape;
+use crate::layout::GraphLayout;
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Ranking {
+    /// Every node as close to the sources as its predecessors allow.
+    LongestPath,
+    /// Ranks minimizing the total length of the edges (Gansner et al.).
+    NetworkSimplex,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Ordering {
+    Barycenter,
+    Median,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayeredSettings {
+    pub ranking: Ranking,
+    pub ordering: Ordering,
+    /// Crossing reduction sweeps, alternating down and up.
+    pub sweeps: usize,
+    pub layer_spacing: f64,
+    pub node_spacing: f64,
+}
+
+impl Default for LayeredSettings {
+    fn default() -> Self {
+        return LayeredSettings {
+            ranking: Ranking::NetworkSimplex,
+            ordering: Ordering::Median,
+            sweeps: 24,
+            layer_spacing: 80.0,
+            node_spacing: 50.0,
+        };
+    }
+}
+
+/// Reverses the edges that close a cycle in a depth-first search, leaving a
+/// DAG. Returns which edges were reversed.
+pub fn remove_cycles(n: usize, edges: &mut [(usize, usize)]) -> Vec<bool> {
+    let mut outgoing: Vec<Vec<usize>> = vec![vec![]; n];
+    for (e, (t, _)) in edges.iter().enumerate() {
+        outgoing[*t].push(e);
+    }
+    let mut state = vec![0u8; n];
+    let mut reversed = vec![false; edges.len()];
+    for start in 0..n {
+        if state[start] != 0 {
+            continue;
+        }
+        state[start] = 1;
+        let mut stack = vec![(start, 0)];
+        while let Some((v, next)) = stack.pop() {
+            if next == outgoing[v].len() {
+                state[v] = 2;
+                continue;
+            }
+            stack.push((v, next + 1));
+            let e = outgoing[v][next];
+            let w = edges[e].1;
+            match state[w] {
+                0 => {
+                    state[w] = 1;
+                    stack.push((w, 0));
+                }
+                1 => reversed[e] = true,
+                _ => {}
+            }
+        }
+    }
+    for (e, flip) in reversed.iter().enumerate() {
+        if *flip {
+            edges[e] = (edges[e].1, edges[e].0);
+        }
+    }
+    return reversed;
+}
+
+/// Ranks for a DAG putting every node one below its lowest predecessor.
+pub fn longest_path_ranks(n: usize, edges: &[(usize, usize)]) -> Vec<i64> {
+    let mut indegree = vec![0; n];
+    let mut outgoing: Vec<Vec<usize>> = vec![vec![]; n];
+    for (t, h) in edges {
+        indegree[*h] += 1;
+        outgoing[*t].push(*h);
+    }
+    let mut rank = vec![0i64; n];
+    let mut queue: VecDeque<usize> =
+        (0..n).filter(|v| indegree[*v] == 0).collect();
+    while let Some(v) = queue.pop_front() {
+        for w in outgoing[v].iter() {
+            rank[*w] = rank[*w].max(rank[v] + 1);
+            indegree[*w] -= 1;
+            if indegree[*w] == 0 {
+                queue.push_back(*w);
+            }
+        }
+    }
+    return rank;
+}
+
+/// Network simplex ranking for a DAG: starting from a feasible spanning tree
+/// of tight edges, repeatedly swaps a tree edge with negative cut value for
+/// the slackest-possible non-tree edge until the total edge length can't
+/// shrink any further.
+pub fn network_simplex_ranks(n: usize, edges: &[(usize, usize)]) -> Vec<i64> {
+    let mut rank = longest_path_ranks(n, edges);
+    let slack = |rank: &[i64], (t, h): (usize, usize)| rank[h] - rank[t] - 1;
+
+    // Work one connected component at a time, since each needs its own
+    // spanning tree.
+    let mut component = vec![usize::MAX; n];
+    let mut incident: Vec<Vec<usize>> = vec![vec![]; n];
+    for (e, (t, h)) in edges.iter().enumerate() {
+        incident[*t].push(e);
+        incident[*h].push(e);
+    }
+    let mut components = vec![];
+    for start in 0..n {
+        if component[start] != usize::MAX {
+            continue;
+        }
+        let c = components.len();
+        let mut nodes = vec![start];
+        component[start] = c;
+        let mut next = 0;
+        while next < nodes.len() {
+            let v = nodes[next];
+            next += 1;
+            for e in incident[v].iter() {
+                let (t, h) = edges[*e];
+                let w = if t == v { h } else { t };
+                if component[w] == usize::MAX {
+                    component[w] = c;
+                    nodes.push(w);
+                }
+            }
+        }
+        components.push(nodes);
+    }
+
+    for nodes in components {
+        if nodes.len() < 2 {
+            continue;
+        }
+        let c = component[nodes[0]];
+        let local: Vec<usize> = (0..edges.len())
+            .filter(|e| component[edges[*e].0] == c)
+            .collect();
+
+        // Feasible tree: grow tight edges from the first node, shifting the
+        // tree's ranks to tighten the least slack edge whenever it stalls.
+        let mut in_tree = vec![false; edges.len()];
+        let mut tree_node = vec![false; n];
+        tree_node[nodes[0]] = true;
+        let mut size = 1;
+        while size < nodes.len() {
+            let mut grew = true;
+            while grew {
+                grew = false;
+                for e in local.iter() {
+                    let (t, h) = edges[*e];
+                    if tree_node[t] != tree_node[h] && slack(&rank, (t, h)) == 0
+                    {
+                        in_tree[*e] = true;
+                        tree_node[t] = true;
+                        tree_node[h] = true;
+                        size += 1;
+                        grew = true;
+                    }
+                }
+            }
+            if size == nodes.len() {
+                break;
+            }
+            let e = *local
+                .iter()
+                .filter(|e| tree_node[edges[**e].0] != tree_node[edges[**e].1])
+                .min_by_key(|e| slack(&rank, edges[**e]))
+                .unwrap();
+            let (t, h) = edges[e];
+            let delta = if tree_node[h] {
+                -slack(&rank, (t, h))
+            } else {
+                slack(&rank, (t, h))
+            };
+            for v in nodes.iter().filter(|v| tree_node[**v]) {
+                rank[*v] += delta;
+            }
+        }
+
+        // Which side of a tree edge each node is on once it's cut.
+        let tail_side = |in_tree: &[bool], cut: usize| {
+            let mut side = vec![false; n];
+            let mut stack = vec![edges[cut].0];
+            side[edges[cut].0] = true;
+            while let Some(v) = stack.pop() {
+                for e in incident[v].iter() {
+                    if !in_tree[*e] || *e == cut {
+                        continue;
+                    }
+                    let (t, h) = edges[*e];
+                    let w = if t == v { h } else { t };
+                    if !side[w] {
+                        side[w] = true;
+                        stack.push(w);
+                    }
+                }
+            }
+            side
+        };
+
+        for _ in 0..10 * edges.len() {
+            let leaving = local.iter().filter(|e| in_tree[**e]).find_map(|e| {
+                let side = tail_side(&in_tree, *e);
+                let cut: i64 = local
+                    .iter()
+                    .map(|f| {
+                        let (t, h) = edges[*f];
+                        match (side[t], side[h]) {
+                            (true, false) => 1,
+                            (false, true) => -1,
+                            _ => 0,
+                        }
+                    })
+                    .sum();
+                if cut < 0 {
+                    Some((*e, side))
+                } else {
+                    None
+                }
+            });
+            let (leaving, side) = match leaving {
+                Some(found) => found,
+                None => break,
+            };
+            let entering = local
+                .iter()
+                .filter(|f| !in_tree[**f])
+                .filter(|f| !side[edges[**f].0] && side[edges[**f].1])
+                .min_by_key(|f| slack(&rank, edges[**f]));
+            let entering = match entering {
+                Some(f) => *f,
+                None => break,
+            };
+            let delta = slack(&rank, edges[entering]);
+            for v in nodes.iter().filter(|v| side[**v]) {
+                rank[*v] -= delta;
+            }
+            in_tree[leaving] = false;
+            in_tree[entering] = true;
+        }
+    }
+
+    let min = rank.iter().copied().min().unwrap_or(0);
+    return rank.into_iter().map(|r| r - min).collect();
+}
+
+/// Crossings between the edges from one layer to the next, given each
+/// edge's positions in the two layers.
+fn crossings_between(pairs: &[(usize, usize)]) -> usize {
+    let mut count = 0;
+    for (i, (a1, b1)) in pairs.iter().enumerate() {
+        for (a2, b2) in pairs.iter().skip(i + 1) {
+            if (a1 < a2 && b1 > b2) || (a1 > a2 && b1 < b2) {
+                count += 1;
+            }
+        }
+    }
+    return count;
+}
+
+fn median(mut positions: Vec<f64>) -> Option<f64> {
+    positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let len = positions.len();
+    let m = len / 2;
+    return match len {
+        0 => None,
+        _ if len % 2 == 1 => Some(positions[m]),
+        2 => Some((positions[0] + positions[1]) / 2.0),
+        _ => {
+            // Weighted towards the side where neighbours are packed tighter.
+            let left = positions[m - 1] - positions[0];
+            let right = positions[len - 1] - positions[m];
+            if left + right == 0.0 {
+                Some((positions[m - 1] + positions[m]) / 2.0)
+            } else {
+                Some(
+                    (positions[m - 1] * right + positions[m] * left)
+                        / (left + right),
+                )
+            }
+        }
+    };
+}
+
+/// Positions as close as possible to `desired`, in least squares, keeping
+/// their order and at least `spacing` apart: isotonic regression of
+/// `desired[i] - i * spacing` by pooling adjacent violators.
+fn place_in_order(desired: &[f64], spacing: f64) -> Vec<f64> {
+    let mut blocks: Vec<(f64, usize)> = vec![];
+    for (i, d) in desired.iter().enumerate() {
+        blocks.push((d - i as f64 * spacing, 1));
+        while blocks.len() > 1 {
+            let (s2, c2) = blocks[blocks.len() - 1];
+            let (s1, c1) = blocks[blocks.len() - 2];
+            if s1 / c1 as f64 <= s2 / c2 as f64 {
+                break;
+            }
+            blocks.pop();
+            *blocks.last_mut().unwrap() = (s1 + s2, c1 + c2);
+        }
+    }
+    return blocks
+        .into_iter()
+        .flat_map(|(sum, count)| vec![sum / count as f64; count])
+        .enumerate()
+        .map(|(i, y)| y + i as f64 * spacing)
+        .collect();
+}
+
+/// The proper layered graph: real nodes followed by dummy nodes, each on a
+/// layer, with edges only between consecutive layers.
+struct Layering {
+    layers: Vec<Vec<usize>>,
+    up: Vec<Vec<usize>>,
+    down: Vec<Vec<usize>>,
+}
+
+impl Layering {
+    fn positions(&self) -> Vec<usize> {
+        let size = self.up.len();
+        let mut position = vec![0; size];
+        for layer in self.layers.iter() {
+            for (i, v) in layer.iter().enumerate() {
+                position[*v] = i;
+            }
+        }
+        return position;
+    }
+
+    fn crossings(&self) -> usize {
+        let position = self.positions();
+        return self
+            .layers
+            .iter()
+            .map(|layer| {
+                let pairs: Vec<(usize, usize)> = layer
+                    .iter()
+                    .flat_map(|v| {
+                        let from = position[*v];
+                        self.down[*v].iter().map(move |w| (from, w))
+                    })
+                    .map(|(from, w)| (from, position[*w]))
+                    .collect();
+                crossings_between(&pairs)
+            })
+            .sum();
+    }
+
+    /// Reorders one layer by the median or barycenter of each node's
+    /// neighbours in the adjacent layer. Nodes without neighbours there
+    /// keep their place.
+    fn reorder(&mut self, l: usize, downward: bool, ordering: Ordering) {
+        let position = self.positions();
+        let mut keyed: Vec<(f64, usize)> = self.layers[l]
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let neighbors = if downward {
+                    &self.up[*v]
+                } else {
+                    &self.down[*v]
+                };
+                let positions: Vec<f64> =
+                    neighbors.iter().map(|w| position[*w] as f64).collect();
+                let key = match ordering {
+                    Ordering::Median => median(positions),
+                    Ordering::Barycenter if positions.is_empty() => None,
+                    Ordering::Barycenter => Some(
+                        positions.iter().sum::<f64>() / positions.len() as f64,
+                    ),
+                };
+                (key.unwrap_or(i as f64), *v)
+            })
+            .collect();
+        keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        self.layers[l] = keyed.into_iter().map(|(_, v)| v).collect();
+    }
+
+    /// Crossings among the edges of `v` and `w` if `v` is left of `w`.
+    fn pair_crossings(&self, position: &[usize], v: usize, w: usize) -> usize {
+        let mut count = 0;
+        for neighbors in
+            vec![(&self.up[v], &self.up[w]), (&self.down[v], &self.down[w])]
+        {
+            for x in neighbors.0.iter() {
+                for y in neighbors.1.iter() {
+                    if position[*x] > position[*y] {
+                        count += 1;
+                    }
+                }
+            }
+        }
+        return count;
+    }
+
+    /// Swaps neighbouring nodes while that removes crossings.
+    fn transpose(&mut self) {
+        let mut improved = true;
+        let mut rounds = 0;
+        while improved && rounds < 100 {
+            improved = false;
+            rounds += 1;
+            for l in 0..self.layers.len() {
+                for i in 0..self.layers[l].len().saturating_sub(1) {
+                    let position = self.positions();
+                    let (v, w) = (self.layers[l][i], self.layers[l][i + 1]);
+                    if self.pair_crossings(&position, w, v)
+                        < self.pair_crossings(&position, v, w)
+                    {
+                        self.layers[l].swap(i, i + 1);
+                        improved = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Horizontal coordinates: alternately pulls every layer towards its
+    /// neighbours' positions and spreads it back out to `spacing`.
+    fn coordinates(&self, spacing: f64, passes: usize) -> Vec<f64> {
+        let mut x = vec![0.0; self.up.len()];
+        for layer in self.layers.iter() {
+            for (i, v) in layer.iter().enumerate() {
+                x[*v] = i as f64 * spacing;
+            }
+        }
+        for pass in 0..passes {
+            let downward = pass % 2 == 0;
+            let order: Vec<usize> = if downward {
+                (0..self.layers.len()).collect()
+            } else {
+                (0..self.layers.len()).rev().collect()
+            };
+            for l in order {
+                let desired: Vec<f64> = self.layers[l]
+                    .iter()
+                    .map(|v| {
+                        let neighbors: Vec<f64> = self.up[*v]
+                            .iter()
+                            .chain(self.down[*v].iter())
+                            .map(|w| x[*w])
+                            .collect();
+                        if neighbors.is_empty() {
+                            x[*v]
+                        } else {
+                            neighbors.iter().sum::<f64>()
+                                / neighbors.len() as f64
+                        }
+                    })
+                    .collect();
+                let placed = place_in_order(&desired, spacing);
+                for (v, p) in self.layers[l].iter().zip(placed) {
+                    x[*v] = p;
+                }
+            }
+        }
+        return x;
+    }
+}
+
+impl GraphLayout {
+    /// Lays the graph out in layers following edge direction, with edges
+    /// spanning several layers bent through the positions of their dummy
+    /// nodes.
+    pub fn layered_layout_with(&mut self, settings: &LayeredSettings) {
+        let nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        let local: HashMap<NodeIndex, usize> =
+            nodes.iter().enumerate().map(|(i, n)| (*n, i)).collect();
+        let graph_edges: Vec<(EdgeIndex, usize, usize)> = self
+            .segment_edge_indices()
+            .filter_map(|e| {
+                let (a, b) = self.graph.edge_endpoints(e)?;
+                Some((e, local[&a], local[&b]))
+            })
+            .filter(|(_, a, b)| a != b)
+            .collect();
+        let mut edges: Vec<(usize, usize)> =
+            graph_edges.iter().map(|(_, a, b)| (*a, *b)).collect();
+        remove_cycles(nodes.len(), &mut edges);
+        let rank = match settings.ranking {
+            Ranking::LongestPath => longest_path_ranks(nodes.len(), &edges),
+            Ranking::NetworkSimplex => {
+                network_simplex_ranks(nodes.len(), &edges)
+            }
+        };
+
+        // Split long edges into chains of dummy nodes, one per layer.
+        let mut node_rank = rank.clone();
+        let mut up: Vec<Vec<usize>> = vec![vec![]; nodes.len()];
+        let mut down: Vec<Vec<usize>> = vec![vec![]; nodes.len()];
+        let mut chains: Vec<Vec<usize>> = vec![];
+        for (t, h) in edges.iter() {
+            let mut chain = vec![*t];
+            for r in rank[*t] + 1..rank[*h] {
+                node_rank.push(r);
+                up.push(vec![]);
+                down.push(vec![]);
+                chain.push(node_rank.len() - 1);
+            }
+            chain.push(*h);
+            for pair in chain.windows(2) {
+                down[pair[0]].push(pair[1]);
+                up[pair[1]].push(pair[0]);
+            }
+            chains.push(chain);
+        }
+        let depth = node_rank.iter().copied().max().map_or(0, |r| r + 1);
+        let mut layers: Vec<Vec<usize>> = vec![vec![]; depth as usize];
+        for (v, r) in node_rank.iter().enumerate() {
+            layers[*r as usize].push(v);
+        }
+        let mut layering = Layering {
+            layers: layers,
+            up: up,
+            down: down,
+        };
+
+        let mut best = layering.layers.clone();
+        let mut fewest = layering.crossings();
+        for sweep in 0..settings.sweeps {
+            let downward = sweep % 2 == 0;
+            if downward {
+                for l in 1..layering.layers.len() {
+                    layering.reorder(l, true, settings.ordering);
+                }
+            } else {
+                for l in (0..layering.layers.len().saturating_sub(1)).rev() {
+                    layering.reorder(l, false, settings.ordering);
+                }
+            }
+            layering.transpose();
+            let crossings = layering.crossings();
+            if crossings < fewest {
+                fewest = crossings;
+                best = layering.layers.clone();
+            }
+        }
+        layering.layers = best;
+
+        let x = layering.coordinates(settings.node_spacing, 8);
+        let at = |v: usize| Coordinate {
+            x: x[v],
+            y: node_rank[v] as f64 * settings.layer_spacing,
+        };
+        for (i, idx) in nodes.iter().enumerate() {
+            self.place_node(*idx, at(i));
+        }
+        for ((idx, a, _), chain) in graph_edges.iter().zip(chains) {
+            let mut bends: Vec<_> =
+                chain[1..chain.len() - 1].iter().map(|v| at(*v)).collect();
+            if chain[0] != *a {
+                bends.reverse();
+            }
+            let shape = if bends.is_empty() {
+                EdgeShape::Straight
+            } else {
+                EdgeShape::Polyline(bends)
+            };
+            self.set_edge_shape(*idx, shape);
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Arranges the graph in layers following edge direction, as one undo
+    /// step. Long edges get bend points, readable through `edges_data`.
+    pub fn layered_layout(&mut self, settings: JsValue) -> Result<(), JsValue> {
+        let settings: LayeredSettings = settings_from_js(&settings)?;
+        self.checkpoint_with_label("layered layout");
+        self.layered_layout_with(&settings);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{edge, graph, id, unplaced};
+
+    #[test]
+    fn test_network_simplex_shortens_edges() {
+        // e feeds straight into d, so it belongs next to c, not at the top.
+        let edges = vec![(0, 1), (1, 2), (2, 3), (4, 3)];
+        let total = |rank: &[i64]| {
+            edges.iter().map(|(t, h)| rank[*h] - rank[*t]).sum::<i64>()
+        };
+        let longest = longest_path_ranks(5, &edges);
+        let simplex = network_simplex_ranks(5, &edges);
+        assert_eq!(longest[4], 0);
+        assert_eq!(simplex[4], 2);
+        assert_eq!(total(&simplex), 4);
+        assert!(total(&simplex) < total(&longest));
+        for (t, h) in edges.iter() {
+            assert!(simplex[*h] > simplex[*t]);
+        }
+    }
+
+    #[test]
+    fn test_layered_layout() {
+        let mut layout = graph(
+            vec!["a", "b", "c", "d", "e", "f"]
+                .into_iter()
+                .map(unplaced)
+                .collect(),
+            vec![
+                edge("a", "b"),
+                edge("b", "c"),
+                edge("c", "d"),
+                edge("a", "d"),
+                edge("a", "e"),
+                edge("e", "f"),
+                edge("f", "b"),
+                // Closes a cycle, so something has to be reversed.
+                edge("d", "a"),
+            ],
+        );
+        layout.layered_layout_with(&LayeredSettings::default());
+
+        let y = |name| {
+            let idx = layout.node_index(&id(name));
+            layout.node_geo(idx.unwrap()).unwrap().y
+        };
+        assert!(y("a") < y("e") && y("e") < y("f") && y("f") < y("b"));
+        assert!(y("b") < y("c") && y("c") < y("d"));
+
+        let long = layout
+            .segment_edge_indices()
+            .filter_map(|e| layout.edge_data(e))
+            .find(|e| e.from.as_str() == "a" && e.to.as_str() == "d")
+            .unwrap();
+        let path = long.path.unwrap();
+        // a, a bend on each of the four layers in between, then d.
+        assert_eq!(path.len(), 6);
+        for pair in path.windows(2) {
+            assert!(pair[0].1 < pair[1].1);
+        }
+    }
+}
@@ -1,30 +1,45 @@
 use crate::geometry::tree::GraphGeo;
 use crate::geometry::tree::GraphGeoElement;
-use crate::geometry::NodeGeo;
+use crate::geometry::{charge, EdgeShape, NodeGeo};
 use crate::geometry::{Edge, Node};
+use crate::history::History;
+use crate::intersections::crosses;
+use crate::pitch::PitchClassSet;
+use crate::placement::PlacementSettings;
+use crate::symmetry::Symmetry;
+use crate::transitions::Transition;
+use crate::utils::settings_from_js;
 use arraystring::{typenum::U64, ArrayString};
 use bimap::BiMap;
 use geo::algorithm::euclidean_distance::EuclideanDistance;
-use geo::prelude::Intersects;
 use geo::{Coordinate, Point};
 use im::HashMap;
+use im::OrdMap;
 use itertools::Itertools;
 use js_sys::Math::random;
+use js_sys::{Array, Float64Array};
 use log::info;
 use log::Level;
-use petgraph::graph::NodeIndex;
+use petgraph::graph::{EdgeIndex, NodeIndex};
 use petgraph::stable_graph::StableGraph;
+use petgraph::visit::EdgeRef;
+use petgraph::{Incoming, Outgoing};
 use rstar::AABB;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use wasm_bindgen::prelude::*;
 
 pub type NodeDataId = ArrayString<U64>;
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
 pub struct NodeData {
     pub id: ArrayString<U64>,
     pub x: Option<f64>,
     pub y: Option<f64>,
+    /// Nodes sharing a group are kept together by layouts that support it.
+    pub group: Option<NodeDataId>,
+    /// The pitch-class set the node stands for, if any.
+    pub chroma: Option<PitchClassSet>,
 }
 
 impl NodeData {
@@ -62,12 +77,69 @@ impl Into<Point<f64>> for &NodeData {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct EdgeData {
     pub from: ArrayString<U64>,
     pub to: ArrayString<U64>,
+    pub multiplicity: Option<usize>,
+    pub reciprocal: Option<bool>,
+    /// Points the edge is drawn through, ends included. On input the
+    /// points between the ends become the edge's bends, so curves come
+    /// back as the polylines they were flattened to.
+    pub path: Option<Vec<(f64, f64)>>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeMode {
+    /// Reciprocal and parallel edges are merged into a single graph edge.
+    Undirected,
+    /// Every edge is kept, but edges joining the same pair of nodes share a
+    /// single geometric segment.
+    Directed,
+}
+
+impl Default for EdgeMode {
+    fn default() -> Self {
+        return EdgeMode::Directed;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutOptions {
+    pub edge_mode: EdgeMode,
+    /// Keeps every node on its own point of a grid with spacing
+    /// `2^-grid_power`, with crossings tested exactly in fixed point.
+    pub grid_power: Option<isize>,
+    /// Where nodes without coordinates go.
+    pub placement: PlacementSettings,
+}
+
+#[derive(Clone, Debug)]
+pub struct EdgeWeight {
+    /// How many input edges were merged into this one.
+    pub multiplicity: usize,
+    /// Whether the input contained this edge in both directions.
+    pub reciprocal: bool,
+    /// The edge whose segment this one is drawn and measured as, if any.
+    pub segment_of: Option<EdgeIndex>,
+    pub shape: EdgeShape,
+}
+
+impl Default for EdgeWeight {
+    fn default() -> Self {
+        return EdgeWeight {
+            multiplicity: 1,
+            reciprocal: false,
+            segment_of: None,
+            shape: EdgeShape::Straight,
+        };
+    }
+}
+
+pub type LayoutGraph = StableGraph<NodeData, EdgeWeight>;
+
 #[derive(Serialize, Deserialize)]
 pub struct GraphData {
     pub nodes: Vec<NodeData>,
@@ -77,12 +149,42 @@ pub struct GraphData {
 #[wasm_bindgen]
 pub struct GraphLayout {
     #[wasm_bindgen(skip)]
-    pub graph: StableGraph<NodeData, ()>,
+    pub graph: LayoutGraph,
     #[wasm_bindgen(skip)]
     pub graph_geo: GraphGeo,
+    #[wasm_bindgen(skip)]
+    pub options: LayoutOptions,
+    /// Copy of the node positions in `graph_geo` that's cheap to snapshot.
+    /// Only `set_node_geo` writes to it.
+    #[wasm_bindgen(skip)]
+    pub node_positions: OrdMap<NodeIndex, NodeGeo>,
+    #[wasm_bindgen(skip)]
+    pub history: History,
+    #[wasm_bindgen(skip)]
+    pub transition: Option<Transition>,
+    /// Positions the objective's `anchor` term pulls nodes towards.
+    #[wasm_bindgen(skip)]
+    pub anchors: OrdMap<NodeIndex, NodeGeo>,
+    /// When set, the optimizers move whole orbits of it at once.
+    #[wasm_bindgen(skip)]
+    pub symmetry: Option<Symmetry>,
     node_id_to_graph_index: BiMap<NodeDataId, NodeIndex>,
 }
 
+/// The shape drawn through `path`, whose first and last points are the
+/// edge's ends.
+fn path_shape(path: &Option<Vec<(f64, f64)>>) -> EdgeShape {
+    return match path {
+        Some(points) if points.len() > 2 => EdgeShape::Polyline(
+            points[1..points.len() - 1]
+                .iter()
+                .map(|(x, y)| Coordinate { x: *x, y: *y })
+                .collect(),
+        ),
+        _ => EdgeShape::Straight,
+    };
+}
+
 impl GraphLayout {
     pub fn node_data(&self, idx: NodeIndex) -> Option<NodeData> {
         let node_geo = self.node_geo(idx);
@@ -90,66 +192,251 @@ impl GraphLayout {
             Some(geo) => Some(NodeData {
                 x: Some(geo.x),
                 y: Some(geo.y),
+                group: self.graph[idx].group,
+                chroma: self.graph[idx].chroma,
                 id: *self.node_id_to_graph_index.get_by_right(&idx).unwrap(),
             }),
             _ => None,
         };
     }
-}
 
-#[wasm_bindgen]
-impl GraphLayout {
-    #[wasm_bindgen(constructor)]
-    pub fn new(nodes: JsValue, edges: JsValue) -> GraphLayout {
-        console_log::init_with_level(Level::Debug);
+    pub fn node_index(&self, id: &NodeDataId) -> Option<NodeIndex> {
+        return self.node_id_to_graph_index.get_by_left(id).copied();
+    }
+
+    pub fn node_id(&self, idx: NodeIndex) -> Option<NodeDataId> {
+        return self.node_id_to_graph_index.get_by_right(&idx).copied();
+    }
+
+    pub fn all_nodes_data(&self) -> Vec<NodeData> {
+        return self
+            .graph
+            .node_indices()
+            .filter_map(|idx| self.node_data(idx))
+            .collect();
+    }
+
+    /// Node positions as `x, y` pairs, in node index order.
+    pub fn positions_vec(&self) -> Vec<f64> {
+        return self
+            .graph
+            .node_indices()
+            .flat_map(|idx| match self.node_geo(idx) {
+                Some(geo) => vec![geo.x, geo.y],
+                None => vec![std::f64::NAN, std::f64::NAN],
+            })
+            .collect();
+    }
+
+    /// Moves nodes to positions laid out as in `positions_vec`, through
+    /// `place_nodes_at`. Pairs that aren't finite leave their node where it
+    /// is.
+    pub fn set_positions(&mut self, positions: &[f64]) {
+        let moves: Vec<(NodeIndex, NodeGeo)> = self
+            .graph
+            .node_indices()
+            .zip(positions.chunks(2))
+            .filter_map(|(idx, xy)| match xy {
+                [x, y] if x.is_finite() && y.is_finite() => {
+                    Some((idx, Coordinate { x: *x, y: *y }))
+                }
+                _ => None,
+            })
+            .collect();
+        self.place_nodes_at(moves);
+    }
+
+    pub fn edge_data(&self, idx: EdgeIndex) -> Option<EdgeData> {
+        let (a, b) = self.graph.edge_endpoints(idx)?;
+        let weight = self.graph.edge_weight(idx)?;
+        let path = match weight.shape {
+            EdgeShape::Straight => None,
+            _ => self
+                .edge_geo(idx)
+                .map(|geo| geo.points().iter().map(|c| (c.x, c.y)).collect()),
+        };
+        return Some(EdgeData {
+            from: *self.node_id_to_graph_index.get_by_right(&a)?,
+            to: *self.node_id_to_graph_index.get_by_right(&b)?,
+            multiplicity: Some(weight.multiplicity),
+            reciprocal: Some(weight.reciprocal),
+            path: path,
+        });
+    }
+
+    pub fn from_graph_data(
+        data: GraphData,
+        options: LayoutOptions,
+    ) -> GraphLayout {
         let mut layout = GraphLayout {
-            graph: StableGraph::<NodeData, ()>::new(),
+            graph: LayoutGraph::default(),
             node_id_to_graph_index: BiMap::new(),
             graph_geo: GraphGeo::new(),
+            node_positions: OrdMap::new(),
+            history: History::default(),
+            transition: None,
+            anchors: OrdMap::new(),
+            symmetry: None,
+            options: options,
         };
+        let unplaced: Vec<(NodeDataId, Option<f64>, Option<f64>)> = data
+            .nodes
+            .iter()
+            .filter(|n| n.x.is_none() || n.y.is_none())
+            .map(|n| (n.id, n.x, n.y))
+            .collect();
+        for node_data in data.nodes {
+            layout.add_node_data(node_data)
+        }
+        for edge_data in data.edges {
+            layout.add_edge_data(edge_data)
+        }
+        // The placement strategy fills in missing coordinates. A node with
+        // only one of them keeps it.
+        let given: HashMap<NodeIndex, (Option<f64>, Option<f64>)> = unplaced
+            .iter()
+            .filter_map(|(id, x, y)| Some((layout.node_index(id)?, (*x, *y))))
+            .collect();
+        if !given.is_empty() {
+            let nodes: Vec<NodeIndex> = given.keys().copied().collect();
+            let moves = layout
+                .initial_positions(&nodes, &options.placement)
+                .into_iter()
+                .map(|(idx, p)| {
+                    let (x, y) = given[&idx];
+                    (
+                        idx,
+                        Coordinate {
+                            x: x.unwrap_or(p.x),
+                            y: y.unwrap_or(p.y),
+                        },
+                    )
+                })
+                .collect();
+            layout.place_nodes_at(moves);
+        }
+        return layout;
+    }
 
-        let nodes: Result<Vec<NodeData>, _> = JsValue::into_serde(&nodes);
-
-        match nodes {
-            Ok(nodes_data) => {
-                for node_data in nodes_data {
-                    layout.add_node_data(node_data)
-                }
+    /// Nodes grouped by connected component, ignoring edge direction, in
+    /// order of each component's first node.
+    pub fn connected_components(&self) -> Vec<Vec<NodeIndex>> {
+        let mut seen: HashSet<NodeIndex> = HashSet::new();
+        let mut components = vec![];
+        for start in self.graph.node_indices() {
+            if !seen.insert(start) {
+                continue;
             }
-            Err(_) => {
-                // todo: logging
+            let mut component = vec![start];
+            let mut next = 0;
+            while next < component.len() {
+                let n = component[next];
+                next += 1;
+                for m in self.graph.neighbors_undirected(n) {
+                    if seen.insert(m) {
+                        component.push(m);
+                    }
+                }
             }
-        };
+            components.push(component);
+        }
+        return components;
+    }
 
-        let edges: Result<Vec<EdgeData>, _> = JsValue::into_serde(&edges);
+    /// Edges that own a geometric segment, i.e. everything except directed
+    /// edges that are drawn as another edge's segment.
+    pub fn segment_edge_indices(&self) -> impl Iterator<Item = EdgeIndex> + '_ {
+        return self
+            .graph
+            .edge_indices()
+            .filter(move |idx| self.graph[*idx].segment_of.is_none());
+    }
+}
 
-        match edges {
-            Ok(edges_data) => {
-                for edge_data in edges_data {
-                    layout.add_edge_data(edge_data)
-                }
-            }
-            Err(_) => {
-                // todo: logging
-            }
-        };
+#[wasm_bindgen]
+impl GraphLayout {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        nodes: JsValue,
+        edges: JsValue,
+        options: JsValue,
+    ) -> Result<GraphLayout, JsValue> {
+        console_log::init_with_level(Level::Debug);
 
-        return layout;
+        // todo: logging
+        let nodes: Result<Vec<NodeData>, _> = JsValue::into_serde(&nodes);
+        let edges: Result<Vec<EdgeData>, _> = JsValue::into_serde(&edges);
+        let options: LayoutOptions = settings_from_js(&options)?;
+
+        return Ok(GraphLayout::from_graph_data(
+            GraphData {
+                nodes: nodes.unwrap_or_default(),
+                edges: edges.unwrap_or_default(),
+            },
+            options,
+        ));
     }
 
     fn add_node_data(&mut self, node_data: NodeData) {
         let idx = self.graph.add_node(node_data);
         self.node_id_to_graph_index.insert(node_data.id, idx);
         let node_geo: NodeGeo = node_data.into();
-        self.set_node_geo(idx, node_geo);
+        self.place_node(idx, node_geo);
     }
 
     fn add_edge_data(&mut self, edge: EdgeData) {
-        self.graph.add_edge(
-            *self.node_id_to_graph_index.get_by_left(&edge.from).unwrap(),
-            *self.node_id_to_graph_index.get_by_left(&edge.to).unwrap(),
-            (),
-        );
+        let from =
+            *self.node_id_to_graph_index.get_by_left(&edge.from).unwrap();
+        let to = *self.node_id_to_graph_index.get_by_left(&edge.to).unwrap();
+        let existing = self
+            .graph
+            .edges_directed(from, Outgoing)
+            .filter(|r| r.target() == to)
+            .chain(
+                self.graph
+                    .edges_directed(from, Incoming)
+                    .filter(|r| r.source() == to),
+            )
+            .map(|r| r.id())
+            .find(|idx| self.graph[*idx].segment_of.is_none());
+        let multiplicity = edge.multiplicity.unwrap_or(1);
+
+        match (self.options.edge_mode, existing) {
+            (EdgeMode::Undirected, Some(idx)) => {
+                let reversed =
+                    self.graph.edge_endpoints(idx) == Some((to, from));
+                let weight = &mut self.graph[idx];
+                weight.multiplicity += multiplicity;
+                weight.reciprocal |= reversed || edge.reciprocal == Some(true);
+            }
+            (EdgeMode::Directed, Some(idx)) => {
+                self.graph.add_edge(
+                    from,
+                    to,
+                    EdgeWeight {
+                        multiplicity: multiplicity,
+                        reciprocal: edge.reciprocal.unwrap_or_default(),
+                        segment_of: Some(idx),
+                        shape: EdgeShape::Straight,
+                    },
+                );
+            }
+            (_, None) => {
+                let idx = self.graph.add_edge(
+                    from,
+                    to,
+                    EdgeWeight {
+                        multiplicity: multiplicity,
+                        reciprocal: edge.reciprocal.unwrap_or_default(),
+                        segment_of: None,
+                        shape: path_shape(&edge.path),
+                    },
+                );
+                if let Some(edge_geo) = self.edge_geo(idx) {
+                    self.graph_geo.insert_edge(idx, edge_geo);
+                }
+            }
+        };
     }
 
     pub fn tree_facts(&self) {
@@ -195,20 +482,17 @@ impl GraphLayout {
     pub fn sum_of_charges(&self) -> f64 {
         self.graph
             .node_indices()
-            .map(|n| self.graph[n])
-            .into_iter()
+            .filter_map(|n| self.node_data(n))
             .combinations(2)
             .map(|c| Some(c[0].point()?.euclidean_distance(&c[1].point()?)))
             .filter_map(|d| d)
-            .map(|d| 1.0 / d.powf(2.0))
+            .map(|d| charge(d * d))
             .sum()
     }
 
     pub fn count_edges_intersections(&self, log_info: Option<bool>) -> usize {
         let count = self
-            .graph
-            .edge_indices()
-            .into_iter()
+            .segment_edge_indices()
             .filter(|edge| self.count_edge_intersections(*edge) > 0)
             .count();
 
@@ -224,7 +508,13 @@ impl GraphLayout {
             .edges_geo()
             .iter()
             .combinations(2)
-            .map(|c| if c[0].intersects(c[1]) { 1 } else { 0 })
+            .map(|c| {
+                if crosses(c[0], c[1], self.grid_power()) {
+                    1
+                } else {
+                    0
+                }
+            })
             .sum();
 
         if log_info.unwrap_or_default() {
@@ -234,13 +524,35 @@ impl GraphLayout {
         return count;
     }
 
-    pub fn nodes_data(&self) -> JsValue {
-        let result: Vec<NodeData> = self
+    /// A fresh copy of `positions_vec`; later moves don't show up in it.
+    pub fn positions(&self) -> Float64Array {
+        return Float64Array::from(&self.positions_vec()[..]);
+    }
+
+    pub fn ids(&self) -> Array {
+        return self
             .graph
             .node_indices()
-            .map(|idx| self.node_data(idx))
-            .into_iter()
-            .flatten()
+            .filter_map(|idx| self.node_id_to_graph_index.get_by_right(&idx))
+            .map(|id| JsValue::from_str(id.as_str()))
+            .collect();
+    }
+
+    /// Copies the buffer out of JS memory and applies it with
+    /// `set_positions`.
+    pub fn set_positions_from_buffer(&mut self, buffer: &Float64Array) {
+        self.set_positions(&buffer.to_vec());
+    }
+
+    pub fn nodes_data(&self) -> JsValue {
+        let result: Vec<NodeData> = self.all_nodes_data();
+        JsValue::from_serde(&result).unwrap()
+    }
+
+    pub fn edges_data(&self) -> JsValue {
+        let result: Vec<EdgeData> = self
+            .segment_edge_indices()
+            .filter_map(|idx| self.edge_data(idx))
             .collect();
         JsValue::from_serde(&result).unwrap()
     }
@@ -251,14 +563,14 @@ impl GraphLayout {
 
         for idx in node_indices.into_iter() {
             match self.graph_geo.nodes.clone().get_by_left(&idx) {
-                Some(Node(_, pos)) => self.set_node_geo(
+                Some(Node(_, pos)) => self.place_node(
                     idx,
                     Coordinate {
                         x: pos.x + random_drift(),
                         y: pos.y + random_drift(),
                     },
                 ),
-                _ => self.set_node_geo(
+                _ => self.place_node(
                     idx,
                     Coordinate {
                         x: random_drift(),
@@ -269,3 +581,83 @@ impl GraphLayout {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{edge, graph_with, node};
+
+    fn scale_graph(edge_mode: EdgeMode) -> GraphLayout {
+        return graph_with(
+            vec![
+                node("a", 0., 0.),
+                node("b", 1., 0.),
+                node("c", 0., 1.),
+                node("d", 1., 1.),
+            ],
+            vec![
+                edge("a", "b"),
+                edge("b", "a"),
+                edge("a", "b"),
+                edge("c", "d"),
+            ],
+            LayoutOptions {
+                edge_mode: edge_mode,
+                ..LayoutOptions::default()
+            },
+        );
+    }
+
+    #[test]
+    fn test_undirected_merges_reciprocal_edges() {
+        let layout = scale_graph(EdgeMode::Undirected);
+        assert_eq!(layout.graph.edge_count(), 2);
+        let weights: Vec<EdgeWeight> = layout
+            .graph
+            .edge_indices()
+            .map(|e| layout.graph[e].clone())
+            .collect();
+        assert_eq!(weights[0].multiplicity, 3);
+        assert!(weights[0].reciprocal);
+        assert_eq!(weights[1].multiplicity, 1);
+        assert_eq!(layout.count_graph_intersections(None), 0);
+    }
+
+    #[test]
+    fn test_directed_shares_segments() {
+        let layout = scale_graph(EdgeMode::Directed);
+        assert_eq!(layout.graph.edge_count(), 4);
+        assert_eq!(layout.segment_edge_indices().count(), 2);
+        assert_eq!(layout.graph_geo.edges.len(), 2);
+        assert_eq!(layout.count_edges_intersections(None), 0);
+    }
+
+    #[test]
+    fn test_positions_round_trip() {
+        let mut layout = scale_graph(EdgeMode::Undirected);
+        assert_eq!(
+            layout.positions_vec(),
+            vec![0., 0., 1., 0., 0., 1., 1., 1.]
+        );
+
+        let moved = vec![5., 6., 7., 8., std::f64::NAN, 0., 9., 10.];
+        layout.set_positions(&moved);
+        assert_eq!(
+            layout.positions_vec(),
+            vec![5., 6., 7., 8., 0., 1., 9., 10.]
+        );
+        let (a, d) = (
+            layout.node_data(NodeIndex::new(0)).unwrap(),
+            layout.node_data(NodeIndex::new(3)).unwrap(),
+        );
+        assert_eq!((a.x, a.y), (Some(5.), Some(6.)));
+        assert_eq!((d.x, d.y), (Some(9.), Some(10.)));
+
+        // A short buffer leaves the remaining nodes alone.
+        layout.set_positions(&[1., 2.]);
+        assert_eq!(
+            layout.positions_vec(),
+            vec![1., 2., 7., 8., 0., 1., 9., 10.]
+        );
+    }
+}
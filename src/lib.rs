@@ -1,10 +1,33 @@
 #![warn(missing_debug_implemntations, rust_2018_idioms, missing_docs)]
 
+mod circular;
+mod clusters;
 mod geometry;
+mod history;
 mod intersections;
+mod layered;
 mod layout;
+mod multilevel;
+mod optimize;
+mod packing;
+mod pitch;
+mod placement;
+mod planarity;
+mod radial;
+mod routing;
+mod spectral;
+mod symmetry;
 mod tabu;
+#[cfg(test)]
+mod test_utils;
+mod transitions;
 mod utils;
+mod voice_leading;
+mod worker;
+
+pub use layout::{EdgeData, GraphData, GraphLayout, LayoutOptions, NodeData};
+pub use optimize::objective::LayoutObjective;
+pub use optimize::{LayoutMetrics, Optimizer, OptimizerSettings};
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
@@ -0,0 +1,410 @@
+use crate::geometry::tree::Envelope;
+use crate::geometry::NodeGeo;
+use crate::layout::{
+    EdgeData, EdgeMode, GraphData, GraphLayout, LayoutOptions, NodeData,
+    NodeDataId,
+};
+use crate::optimize::objective::LayoutObjective;
+use crate::spectral::SpectralSettings;
+use crate::utils::settings_from_js;
+use geo::{Coordinate, Point};
+use petgraph::graph::NodeIndex;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MultilevelSettings {
+    pub edge_length: f64,
+    /// Coarsening stops once a level has at most this many nodes.
+    pub min_nodes: usize,
+    /// Refinement iterations run on every level.
+    pub iterations: usize,
+    /// Nodes further apart than this many edge lengths don't repel.
+    pub repulsion_radius: f64,
+    pub seed: u64,
+    /// When set, the finest level gets `iterations` more passes of random
+    /// moves that are kept only if they lower this objective.
+    pub objective: Option<LayoutObjective>,
+}
+
+impl Default for MultilevelSettings {
+    fn default() -> Self {
+        return MultilevelSettings {
+            edge_length: 50.0,
+            min_nodes: 20,
+            iterations: 50,
+            repulsion_radius: 3.0,
+            seed: 0,
+            objective: None,
+        };
+    }
+}
+
+/// A coarser copy of a layout's graph, and the node each of the finer
+/// layout's nodes was merged into.
+pub struct Level {
+    pub layout: GraphLayout,
+    pub parent: HashMap<NodeIndex, NodeIndex>,
+}
+
+fn coarse_id(i: usize) -> NodeDataId {
+    return NodeDataId::from_str_truncate(&i.to_string());
+}
+
+impl GraphLayout {
+    /// Merges the endpoints of a maximal matching, visiting nodes from the
+    /// lowest degree up and pairing each with its lowest-degree unmatched
+    /// neighbour, so that hubs aren't swallowed early.
+    pub fn coarsen(&self) -> Level {
+        let degree = |n: NodeIndex| self.graph.neighbors_undirected(n).count();
+        let mut nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        nodes.sort_by_key(|n| (degree(*n), *n));
+
+        let mut coarse_of: HashMap<NodeIndex, usize> = HashMap::new();
+        let mut count = 0;
+        for n in nodes {
+            if coarse_of.contains_key(&n) {
+                continue;
+            }
+            let partner = self
+                .graph
+                .neighbors_undirected(n)
+                .filter(|m| *m != n && !coarse_of.contains_key(m))
+                .min_by_key(|m| (degree(*m), *m));
+            coarse_of.insert(n, count);
+            if let Some(m) = partner {
+                coarse_of.insert(m, count);
+            }
+            count += 1;
+        }
+
+        let mut pairs: HashSet<(usize, usize)> = HashSet::new();
+        for e in self.segment_edge_indices() {
+            if let Some((a, b)) = self.graph.edge_endpoints(e) {
+                let (ca, cb) = (coarse_of[&a], coarse_of[&b]);
+                if ca != cb {
+                    pairs.insert((ca.min(cb), ca.max(cb)));
+                }
+            }
+        }
+        let mut pairs: Vec<(usize, usize)> = pairs.into_iter().collect();
+        pairs.sort();
+        let layout = GraphLayout::from_graph_data(
+            GraphData {
+                nodes: (0..count)
+                    .map(|i| NodeData {
+                        id: coarse_id(i),
+                        x: Some(0.0),
+                        y: Some(0.0),
+                        group: None,
+                        chroma: None,
+                    })
+                    .collect(),
+                edges: pairs
+                    .into_iter()
+                    .map(|(a, b)| EdgeData {
+                        from: coarse_id(a),
+                        to: coarse_id(b),
+                        multiplicity: None,
+                        reciprocal: None,
+                        path: None,
+                    })
+                    .collect(),
+            },
+            LayoutOptions {
+                edge_mode: EdgeMode::Undirected,
+                ..LayoutOptions::default()
+            },
+        );
+        let parent = coarse_of
+            .into_iter()
+            .filter_map(|(n, c)| Some((n, layout.node_index(&coarse_id(c))?)))
+            .collect();
+        return Level {
+            layout: layout,
+            parent: parent,
+        };
+    }
+
+    /// Coarser and coarser levels, finest first, until a level is small
+    /// enough or stops shrinking.
+    pub fn coarsen_hierarchy(&self, min_nodes: usize) -> Vec<Level> {
+        let mut levels: Vec<Level> = vec![];
+        loop {
+            let current = levels.last().map_or(self, |level| &level.layout);
+            let size = current.graph.node_count();
+            if size <= min_nodes {
+                break;
+            }
+            let next = current.coarsen();
+            if next.layout.graph.node_count() as f64 > 0.9 * size as f64 {
+                break;
+            }
+            levels.push(next);
+        }
+        return levels;
+    }
+
+    /// Force-directed refinement: springs along edges, and repulsion from
+    /// nodes found nearby in the R-tree. Each node moves at most
+    /// `temperature`, which cools linearly to zero.
+    pub fn refine(&mut self, settings: &MultilevelSettings, temperature: f64) {
+        let k = settings.edge_length;
+        let reach = settings.repulsion_radius * k;
+        let nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        for i in 0..settings.iterations {
+            let limit =
+                temperature * (1.0 - i as f64 / settings.iterations as f64);
+            let moves: Vec<(NodeIndex, NodeGeo)> = nodes
+                .iter()
+                .filter_map(|v| {
+                    let p = *self.node_geo(*v)?;
+                    let mut force: NodeGeo = Coordinate { x: 0.0, y: 0.0 };
+                    let around = Envelope::from_corners(
+                        Point::new(p.x - reach, p.y - reach),
+                        Point::new(p.x + reach, p.y + reach),
+                    );
+                    for (u, q) in self.graph_geo.nodes_in_envelope(&around) {
+                        if u == v {
+                            continue;
+                        }
+                        let d = p - *q;
+                        let distance = d.x.hypot(d.y);
+                        if distance < 1e-9 {
+                            // Split stacked nodes apart in a fixed direction.
+                            let angle = (u.index() as f64) * 2.0 * PI / 7.0;
+                            force = force
+                                + Coordinate {
+                                    x: angle.cos(),
+                                    y: angle.sin(),
+                                } * k;
+                        } else if distance < reach {
+                            force = force + d * (k * k / distance / distance);
+                        }
+                    }
+                    for u in self.graph.neighbors_undirected(*v) {
+                        let q = match self.node_geo(u) {
+                            Some(q) if u != *v => *q,
+                            _ => continue,
+                        };
+                        let d = p - q;
+                        force = force - d * (d.x.hypot(d.y) / k);
+                    }
+                    let length = force.x.hypot(force.y);
+                    if length < 1e-9 {
+                        return None;
+                    }
+                    Some((*v, p + force * (length.min(limit) / length)))
+                })
+                .collect();
+            self.place_nodes_at(moves);
+        }
+    }
+
+    /// Tries a random move of up to `step` for every node, keeping the ones
+    /// that lower the objective. Does nothing unless `step` is positive and
+    /// finite.
+    fn polish(
+        &mut self,
+        objective: &LayoutObjective,
+        step: f64,
+        rng: &mut SmallRng,
+    ) {
+        if !(step > 0.0 && step.is_finite()) {
+            return;
+        }
+        let mut nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        nodes.sort();
+        for n in nodes {
+            let p = match self.node_geo(n) {
+                Some(p) => *p,
+                None => continue,
+            };
+            let to = p + Coordinate {
+                x: rng.gen_range(-step, step),
+                y: rng.gen_range(-step, step),
+            };
+            if self.move_delta(objective, n, to) < 0.0 {
+                self.move_node(n, to);
+            }
+        }
+    }
+
+    /// Lays out the coarsest level spectrally, then repeatedly copies
+    /// positions to the next finer level, spreading merged nodes around
+    /// where their coarse node was, and refines. Any objective in the
+    /// settings is applied last, to the finest level.
+    pub fn multilevel_layout_with(&mut self, settings: &MultilevelSettings) {
+        let mut rng = SmallRng::seed_from_u64(settings.seed);
+        let mut levels = self.coarsen_hierarchy(settings.min_nodes);
+        let spectral = SpectralSettings {
+            edge_length: settings.edge_length,
+            seed: settings.seed,
+            ..SpectralSettings::default()
+        };
+        let coarsest = levels.last_mut().map_or(&mut *self, |l| &mut l.layout);
+        coarsest.spectral_layout_with(&spectral);
+        coarsest.refine(settings, settings.edge_length);
+
+        while let Some(level) = levels.pop() {
+            let coarse = level.layout;
+            let finer = levels.last_mut().map_or(&mut *self, |l| &mut l.layout);
+            let mut seen: HashSet<NodeIndex> = HashSet::new();
+            let mut nodes: Vec<NodeIndex> =
+                finer.graph.node_indices().collect();
+            nodes.sort();
+            let mut moves = vec![];
+            for n in nodes {
+                let parent = level.parent[&n];
+                let center = match coarse.node_geo(parent) {
+                    Some(p) => *p,
+                    None => continue,
+                };
+                let position = if seen.insert(parent) {
+                    center
+                } else {
+                    let angle = rng.gen_range(0.0, 2.0 * PI);
+                    center
+                        + Coordinate {
+                            x: angle.cos(),
+                            y: angle.sin(),
+                        } * (settings.edge_length / 3.0)
+                };
+                moves.push((n, position));
+            }
+            finer.place_nodes_at(moves);
+            finer.refine(settings, settings.edge_length / 2.0);
+        }
+
+        if let Some(objective) = settings.objective {
+            // The objective may refer to the finest level's own nodes, so
+            // it's only used there.
+            for i in 0..settings.iterations {
+                let cooling = 1.0 - i as f64 / settings.iterations as f64;
+                let step = settings.edge_length / 2.0 * cooling;
+                self.polish(&objective, step.max(1e-3), &mut rng);
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Lays out the whole graph with the multilevel scheme, as one undo
+    /// step. Suited to graphs far too big for the single-level optimizers.
+    pub fn multilevel_layout(
+        &mut self,
+        settings: JsValue,
+    ) -> Result<(), JsValue> {
+        let settings: MultilevelSettings = settings_from_js(&settings)?;
+        self.checkpoint_with_label("multilevel layout");
+        self.multilevel_layout_with(&settings);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{edge, graph, node};
+
+    fn grid(side: usize) -> GraphLayout {
+        let name = |i: usize, j: usize| format!("{},{}", i, j);
+        let mut edges = vec![];
+        for i in 0..side {
+            for j in 0..side {
+                for (di, dj) in vec![(1, 0), (0, 1)] {
+                    if i + di < side && j + dj < side {
+                        edges.push(edge(name(i, j), name(i + di, j + dj)));
+                    }
+                }
+            }
+        }
+        return graph(
+            (0..side * side)
+                .map(|k| node(name(k / side, k % side), 0.0, 0.0))
+                .collect(),
+            edges,
+        );
+    }
+
+    #[test]
+    fn test_multilevel_grid_graph() {
+        let mut layout = grid(12);
+        let settings = MultilevelSettings::default();
+        let levels = layout.coarsen_hierarchy(settings.min_nodes);
+        assert!(levels.len() >= 2);
+        for pair in levels.windows(2) {
+            assert!(
+                pair[1].layout.graph.node_count()
+                    < pair[0].layout.graph.node_count()
+            );
+        }
+
+        layout.multilevel_layout_with(&settings);
+        let positions: Vec<NodeGeo> =
+            layout.node_positions.values().copied().collect();
+        for (i, p) in positions.iter().enumerate() {
+            for q in positions.iter().skip(i + 1) {
+                assert!((p.x - q.x).hypot(p.y - q.y) > 1.0);
+            }
+        }
+        let lengths: Vec<f64> = layout
+            .segment_edge_indices()
+            .filter_map(|e| layout.edge_endpoints_geo(e))
+            .map(|(a, b)| (a.x - b.x).hypot(a.y - b.y))
+            .collect();
+        let average = lengths.iter().sum::<f64>() / lengths.len() as f64;
+        assert!(average > 25.0 && average < 100.0, "average {}", average);
+    }
+
+    #[test]
+    fn test_multilevel_objective_polish() {
+        let objective = LayoutObjective {
+            charge: 0.0,
+            edge_length: 1.0,
+            ..LayoutObjective::default()
+        };
+        let plain = MultilevelSettings::default();
+        let polished = MultilevelSettings {
+            objective: Some(objective),
+            ..plain
+        };
+        let (mut a, mut b) = (grid(8), grid(8));
+        a.multilevel_layout_with(&plain);
+        b.multilevel_layout_with(&polished);
+        let total = |l: &GraphLayout| l.objective_breakdown(&objective).total;
+        assert!(total(&b) < total(&a), "{} >= {}", total(&b), total(&a));
+
+        // An empty step leaves everything where it is.
+        let before = b.positions_vec();
+        let mut rng = SmallRng::seed_from_u64(7);
+        for step in vec![0.0, -1.0, f64::NAN] {
+            b.polish(&objective, step, &mut rng);
+        }
+        assert_eq!(b.positions_vec(), before);
+    }
+
+    #[test]
+    fn test_multilevel_keeps_grid_cells_unique() {
+        // Cells wider than an edge, so refinement keeps pushing nodes into
+        // each other's cells.
+        let mut layout = grid(8);
+        layout.snap_to_grid(-6);
+        layout.multilevel_layout_with(&MultilevelSettings::default());
+        let cells: HashSet<(i64, i64)> = layout
+            .node_positions
+            .values()
+            .map(|p| {
+                assert_eq!((p.x % 64.0, p.y % 64.0), (0.0, 0.0));
+                (p.x as i64, p.y as i64)
+            })
+            .collect();
+        assert_eq!(cells.len(), 64);
+    }
+}
@@ -0,0 +1,358 @@
+use crate::geometry::grid::grid_spacing;
+use crate::geometry::{charge, EdgeGeo, NodeGeo};
+use crate::intersections::edge_intersects_edges;
+use crate::layout::GraphLayout;
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use js_sys::Function;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::{Incoming, Outgoing};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rstar::RTreeObject;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+pub mod annealing;
+pub mod objective;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod parallel;
+
+use annealing::AnnealingSettings;
+use objective::LayoutObjective;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DescentSettings {
+    /// Largest distance a node is moved along each axis in one step.
+    pub step_size: f64,
+    pub objective: LayoutObjective,
+}
+
+impl Default for DescentSettings {
+    fn default() -> Self {
+        return DescentSettings {
+            step_size: 10.0,
+            objective: LayoutObjective::default(),
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "algorithm", rename_all = "snake_case", deny_unknown_fields)]
+pub enum OptimizerSettings {
+    /// Moves one random node at a time, keeping the move only if it doesn't
+    /// make the layout worse.
+    Descent(DescentSettings),
+    /// Simulated annealing: also accepts some worsening moves, less often as
+    /// the temperature falls.
+    Annealing(AnnealingSettings),
+}
+
+impl Default for OptimizerSettings {
+    fn default() -> Self {
+        return OptimizerSettings::Descent(DescentSettings::default());
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct LayoutMetrics {
+    pub crossings: usize,
+    pub charge: f64,
+}
+
+/// What happened in a single optimizer iteration, passed to step hooks.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct StepReport {
+    pub iteration: usize,
+    pub accepted: bool,
+    /// Change in energy of the proposed move, whether or not it was kept.
+    pub delta: f64,
+    pub temperature: Option<f64>,
+    pub radius: Option<f64>,
+}
+
+pub struct Optimizer {
+    pub settings: OptimizerSettings,
+    /// Iterations run so far, which drives any cooling schedule.
+    pub iteration: usize,
+    rng: SmallRng,
+}
+
+impl Optimizer {
+    pub fn new(settings: OptimizerSettings, seed: u64) -> Optimizer {
+        return Optimizer {
+            settings: settings,
+            iteration: 0,
+            rng: SmallRng::seed_from_u64(seed),
+        };
+    }
+
+    /// Runs one iteration, returning whether the layout changed.
+    pub fn step(&mut self, layout: &mut GraphLayout) -> bool {
+        return self.step_report(layout).accepted;
+    }
+
+    pub fn step_report(&mut self, layout: &mut GraphLayout) -> StepReport {
+        let iteration = self.iteration;
+        let ((accepted, delta), temperature, radius) = match self.settings {
+            OptimizerSettings::Descent(settings) => {
+                (self.descent_step(layout, settings), None, None)
+            }
+            OptimizerSettings::Annealing(settings) => (
+                self.annealing_step(layout, settings),
+                Some(settings.temperature(iteration)),
+                Some(settings.radius(iteration)),
+            ),
+        };
+        self.iteration += 1;
+        return StepReport {
+            iteration: iteration,
+            accepted: accepted,
+            delta: delta,
+            temperature: temperature,
+            radius: radius,
+        };
+    }
+
+    /// Runs up to `iterations` steps, calling `on_step` after each one and
+    /// stopping early if it returns false. Returns how many steps changed
+    /// the layout.
+    pub fn run<F>(
+        &mut self,
+        layout: &mut GraphLayout,
+        iterations: usize,
+        mut on_step: F,
+    ) -> usize
+    where
+        F: FnMut(&StepReport) -> bool,
+    {
+        let mut changed = 0;
+        for _ in 0..iterations {
+            let report = self.step_report(layout);
+            if report.accepted {
+                changed += 1;
+            }
+            if !on_step(&report) {
+                break;
+            }
+        }
+        return changed;
+    }
+
+    /// Picks a random node and a random nearby position for it. In grid
+    /// mode the position is a free cell up to `step` away, rounded up to at
+    /// least one cell. Otherwise a step that isn't positive and finite
+    /// proposes nothing.
+    pub fn propose(
+        &mut self,
+        layout: &GraphLayout,
+        step: f64,
+    ) -> Option<(NodeIndex, NodeGeo)> {
+        let count = layout.graph.node_indices().count();
+        if count == 0 {
+            return None;
+        }
+        let idx = layout
+            .graph
+            .node_indices()
+            .nth(self.rng.gen_range(0, count))?;
+        if let Some(grid_power) = layout.grid_power() {
+            let cells = (step / grid_spacing(grid_power))
+                .round()
+                .max(1.0)
+                .min(i32::MAX as f64) as i64;
+            let (i, j) = (
+                self.rng.gen_range(-cells, cells + 1),
+                self.rng.gen_range(-cells, cells + 1),
+            );
+            return Some((idx, layout.grid_step(idx, (i, j), grid_power)?));
+        }
+        if !(step > 0.0 && step.is_finite()) {
+            return None;
+        }
+        let before = *layout.node_geo(idx)?;
+        return Some((
+            idx,
+            Coordinate {
+                x: before.x + self.rng.gen_range(-step, step),
+                y: before.y + self.rng.gen_range(-step, step),
+            },
+        ));
+    }
+
+    fn descent_step(
+        &mut self,
+        layout: &mut GraphLayout,
+        settings: DescentSettings,
+    ) -> (bool, f64) {
+        let (idx, after) = match self.propose(layout, settings.step_size) {
+            Some(candidate) => candidate,
+            None => return (false, 0.0),
+        };
+        let delta = layout.move_delta(&settings.objective, idx, after);
+        if delta > 0.0 {
+            return (false, delta);
+        }
+        layout.move_node(idx, after);
+        return (true, delta);
+    }
+}
+
+impl GraphLayout {
+    pub fn incident_edges(&self, idx: NodeIndex) -> Vec<EdgeIndex> {
+        return self
+            .graph
+            .edges_directed(idx, Outgoing)
+            .chain(self.graph.edges_directed(idx, Incoming))
+            .filter(|r| r.weight().segment_of.is_none())
+            .map(|r| r.id())
+            .collect();
+    }
+
+    fn moved_edge_geo(
+        &self,
+        edge: EdgeIndex,
+        idx: NodeIndex,
+        position: NodeGeo,
+    ) -> Option<EdgeGeo> {
+        let (a, b) = self.graph.edge_endpoints(edge)?;
+        let at = |n: NodeIndex| {
+            if n == idx {
+                Some(position)
+            } else {
+                self.node_geo(n).copied()
+            }
+        };
+        return Some(EdgeGeo::with_shape(
+            at(a)?,
+            at(b)?,
+            &self.graph[edge].shape,
+        ));
+    }
+
+    /// Crossings the node's edges would have with the rest of the layout if
+    /// the node were at `position`.
+    pub fn crossings_at(&self, idx: NodeIndex, position: NodeGeo) -> usize {
+        let incident = self.incident_edges(idx);
+        return incident
+            .iter()
+            .filter_map(|e| self.moved_edge_geo(*e, idx, position))
+            .map(|geo| {
+                let envelope = geo.envelope();
+                edge_intersects_edges(
+                    geo,
+                    self.graph_geo.edges_in_envelope(&envelope),
+                    self.grid_power(),
+                )
+                .filter(|(other, _)| !incident.contains(other))
+                .count()
+            })
+            .sum();
+    }
+
+    /// The node's share of `sum_of_charges` if it were at `position`.
+    pub fn charge_at(&self, idx: NodeIndex, position: NodeGeo) -> f64 {
+        return self
+            .graph
+            .node_indices()
+            .filter(|n| *n != idx)
+            .filter_map(|n| self.node_geo(n))
+            .map(|p| {
+                let (dx, dy) = (p.x - position.x, p.y - position.y);
+                charge(dx * dx + dy * dy)
+            })
+            .sum();
+    }
+
+    pub fn metrics(&self) -> LayoutMetrics {
+        return LayoutMetrics {
+            crossings: self.count_graph_intersections(None),
+            charge: self.sum_of_charges(),
+        };
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Runs an optimizer on the calling thread as a single undo step,
+    /// returning how many iterations changed the layout. `on_step`, if
+    /// given, is called with each step's report and can return `false` to
+    /// stop early.
+    pub fn run_optimizer(
+        &mut self,
+        settings: JsValue,
+        iterations: usize,
+        seed: Option<u32>,
+        on_step: Option<Function>,
+    ) -> Result<usize, JsValue> {
+        let settings: OptimizerSettings = settings_from_js(&settings)?;
+        let mut optimizer =
+            Optimizer::new(settings, seed.unwrap_or_default() as u64);
+        self.checkpoint_with_label("optimizer");
+        return Ok(optimizer.run(self, iterations, |report| match &on_step {
+            Some(f) => {
+                let report = JsValue::from_serde(report).unwrap();
+                f.call1(&JsValue::NULL, &report)
+                    .map_or(true, |result| result != JsValue::FALSE)
+            }
+            None => true,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{edge, graph, node};
+    use serde::de::value::{Error, MapDeserializer};
+
+    #[test]
+    fn test_empty_steps_propose_nothing() {
+        let mut layout = graph(
+            vec![node("a", 0., 0.), node("b", 10., 10.)],
+            vec![edge("a", "b")],
+        );
+        let before = layout.positions_vec();
+        for step in vec![0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let mut descent = Optimizer::new(
+                OptimizerSettings::Descent(DescentSettings {
+                    step_size: step,
+                    ..DescentSettings::default()
+                }),
+                7,
+            );
+            assert_eq!(descent.run(&mut layout, 10, |_| true), 0);
+        }
+        assert_eq!(layout.positions_vec(), before);
+
+        // The radius shrinks to nothing after the first step.
+        let mut annealing = Optimizer::new(
+            OptimizerSettings::Annealing(AnnealingSettings {
+                radius_decay: 0.0,
+                min_radius: 0.0,
+                ..AnnealingSettings::default()
+            }),
+            7,
+        );
+        annealing.run(&mut layout, 1, |_| true);
+        let after = layout.positions_vec();
+        assert_eq!(annealing.run(&mut layout, 10, |_| true), 0);
+        assert_eq!(layout.positions_vec(), after);
+    }
+
+    #[test]
+    fn test_settings_reject_unknown_fields() {
+        let parse = |fields: Vec<(&str, &str)>| {
+            OptimizerSettings::deserialize(MapDeserializer::<_, Error>::new(
+                fields.into_iter(),
+            ))
+        };
+        assert!(parse(vec![("algorithm", "annealing")]).is_ok());
+        assert!(parse(vec![("algoritm", "annealing")]).is_err());
+        assert!(parse(vec![("algorithm", "anealing")]).is_err());
+        assert!(parse(vec![("algorithm", "descent")]).is_ok());
+        assert!(parse(vec![("algorithm", "descent"), ("step", "1")]).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use super::objective::LayoutObjective;
+use super::Optimizer;
+use crate::layout::GraphLayout;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum CoolingSchedule {
+    /// Multiplies the temperature by `rate` every iteration.
+    Geometric { rate: f64 },
+    /// Lowers the temperature by `decrement` every iteration.
+    Linear { decrement: f64 },
+    /// `initial / (1 + ln(1 + iteration))`, which cools slowly enough to
+    /// keep escaping local minima on long runs.
+    Logarithmic,
+}
+
+impl CoolingSchedule {
+    pub fn temperature(&self, initial: f64, iteration: usize) -> f64 {
+        let i = iteration as f64;
+        return match *self {
+            CoolingSchedule::Geometric { rate } => initial * rate.powf(i),
+            CoolingSchedule::Linear { decrement } => initial - decrement * i,
+            CoolingSchedule::Logarithmic => initial / (1.0 + i.ln_1p()),
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnnealingSettings {
+    pub objective: LayoutObjective,
+    pub initial_temperature: f64,
+    /// Temperature below which only improving moves are accepted.
+    pub min_temperature: f64,
+    pub schedule: CoolingSchedule,
+    /// Largest distance a node is moved along each axis in the first step.
+    pub initial_radius: f64,
+    /// The move radius is multiplied by this every iteration.
+    pub radius_decay: f64,
+    pub min_radius: f64,
+}
+
+impl Default for AnnealingSettings {
+    fn default() -> Self {
+        return AnnealingSettings {
+            objective: LayoutObjective::default(),
+            initial_temperature: 10.0,
+            min_temperature: 1e-3,
+            schedule: CoolingSchedule::Geometric { rate: 0.995 },
+            initial_radius: 50.0,
+            radius_decay: 0.999,
+            min_radius: 1.0,
+        };
+    }
+}
+
+impl AnnealingSettings {
+    pub fn temperature(&self, iteration: usize) -> f64 {
+        return self
+            .schedule
+            .temperature(self.initial_temperature, iteration)
+            .max(0.0);
+    }
+
+    pub fn radius(&self, iteration: usize) -> f64 {
+        return (self.initial_radius
+            * self.radius_decay.powf(iteration as f64))
+        .max(self.min_radius);
+    }
+}
+
+impl Optimizer {
+    /// Moves a random node within the current radius, keeping the move if
+    /// it helps, or with probability `exp(-delta / temperature)` if not.
+    pub(super) fn annealing_step(
+        &mut self,
+        layout: &mut GraphLayout,
+        settings: AnnealingSettings,
+    ) -> (bool, f64) {
+        let radius = settings.radius(self.iteration);
+        let temperature = settings.temperature(self.iteration);
+        let (idx, after) = match self.propose(layout, radius) {
+            Some(candidate) => candidate,
+            None => return (false, 0.0),
+        };
+        let delta = layout.move_delta(&settings.objective, idx, after);
+        let accept = delta <= 0.0
+            || (temperature > settings.min_temperature
+                && self.rng.gen::<f64>() < (-delta / temperature).exp());
+        if !accept {
+            return (false, delta);
+        }
+        layout.move_node(idx, after);
+        return (true, delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::OptimizerSettings;
+    use crate::test_utils::{edge, graph, node};
+
+    #[test]
+    fn test_annealing_cools_and_untangles() {
+        let settings = AnnealingSettings::default();
+        assert!(settings.temperature(1000) < settings.temperature(0));
+        assert!(settings.radius(1000) < settings.radius(0));
+        assert_eq!(settings.radius(1_000_000), settings.min_radius);
+
+        let mut layout = graph(
+            vec![
+                node("a", 0., 0.),
+                node("b", 100., 100.),
+                node("c", 0., 100.),
+                node("d", 100., 0.),
+            ],
+            vec![edge("a", "b"), edge("c", "d")],
+        );
+        assert_eq!(layout.metrics().crossings, 1);
+
+        let mut optimizer = Optimizer::new(
+            OptimizerSettings::Annealing(AnnealingSettings {
+                objective: LayoutObjective {
+                    charge: 0.0,
+                    ..LayoutObjective::default()
+                },
+                ..settings
+            }),
+            7,
+        );
+        let mut temperatures = vec![];
+        optimizer.run(&mut layout, 2000, |report| {
+            temperatures.push(report.temperature.unwrap());
+            true
+        });
+        assert_eq!(temperatures.len(), 2000);
+        assert!(temperatures[1999] < temperatures[0]);
+        assert_eq!(layout.metrics().crossings, 0);
+    }
+}
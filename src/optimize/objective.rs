@@ -0,0 +1,540 @@
+use crate::clusters::ClusterSettings;
+use crate::geometry::{EdgeGeo, NodeGeo};
+use crate::layout::GraphLayout;
+use crate::routing::inflate;
+use crate::utils::settings_from_js;
+use geo::algorithm::euclidean_distance::EuclideanDistance;
+use geo::Point;
+use petgraph::graph::NodeIndex;
+use rstar::RTreeObject;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+
+/// Weights of the terms that make up a layout's energy. Descent, annealing
+/// and the final pass of a multilevel layout minimize the weighted sum; a
+/// zero weight skips the term entirely.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutObjective {
+    pub crossings: f64,
+    /// Nodes lying within `overlap_distance` of an edge they're not on.
+    pub node_edge_overlaps: f64,
+    pub charge: f64,
+    /// How far node distances are from `ideal_length` times the number of
+    /// hops between them.
+    pub stress: f64,
+    /// Squared differences between edge lengths and `ideal_length`.
+    pub edge_length: f64,
+    /// Spread of the edge lengths relative to their mean.
+    pub edge_length_uniformity: f64,
+    /// How far the smallest angle between each node's edges falls short of
+    /// an even split.
+    pub angular_resolution: f64,
+    /// Area of the nodes' bounding box.
+    pub area: f64,
+    /// Squared distances of anchored nodes from their anchors.
+    pub anchor: f64,
+    /// Squared distances of grouped nodes from their group's centroid.
+    pub cluster_attraction: f64,
+    /// Area shared by the regions around different groups.
+    pub cluster_overlap: f64,
+    pub clusters: ClusterSettings,
+    pub overlap_distance: f64,
+    pub ideal_length: f64,
+}
+
+impl Default for LayoutObjective {
+    fn default() -> Self {
+        return LayoutObjective {
+            crossings: 1.0,
+            node_edge_overlaps: 0.0,
+            charge: 1.0,
+            stress: 0.0,
+            edge_length: 0.0,
+            edge_length_uniformity: 0.0,
+            angular_resolution: 0.0,
+            area: 0.0,
+            anchor: 0.0,
+            cluster_attraction: 0.0,
+            cluster_overlap: 0.0,
+            clusters: ClusterSettings::default(),
+            overlap_distance: 5.0,
+            ideal_length: 50.0,
+        };
+    }
+}
+
+/// Unweighted values of each term.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ObjectiveTerms {
+    pub crossings: f64,
+    pub node_edge_overlaps: f64,
+    pub charge: f64,
+    pub stress: f64,
+    pub edge_length: f64,
+    pub edge_length_uniformity: f64,
+    pub angular_resolution: f64,
+    pub area: f64,
+    pub anchor: f64,
+    pub cluster_attraction: f64,
+    pub cluster_overlap: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ObjectiveBreakdown {
+    pub total: f64,
+    pub terms: ObjectiveTerms,
+    pub weighted: ObjectiveTerms,
+}
+
+impl LayoutObjective {
+    pub fn weigh(&self, terms: &ObjectiveTerms) -> ObjectiveTerms {
+        return ObjectiveTerms {
+            crossings: self.crossings * terms.crossings,
+            node_edge_overlaps: self.node_edge_overlaps
+                * terms.node_edge_overlaps,
+            charge: self.charge * terms.charge,
+            stress: self.stress * terms.stress,
+            edge_length: self.edge_length * terms.edge_length,
+            edge_length_uniformity: self.edge_length_uniformity
+                * terms.edge_length_uniformity,
+            angular_resolution: self.angular_resolution
+                * terms.angular_resolution,
+            area: self.area * terms.area,
+            anchor: self.anchor * terms.anchor,
+            cluster_attraction: self.cluster_attraction
+                * terms.cluster_attraction,
+            cluster_overlap: self.cluster_overlap * terms.cluster_overlap,
+        };
+    }
+
+    pub fn total(&self, terms: &ObjectiveTerms) -> f64 {
+        let w = self.weigh(terms);
+        return w.crossings
+            + w.node_edge_overlaps
+            + w.charge
+            + w.stress
+            + w.edge_length
+            + w.edge_length_uniformity
+            + w.angular_resolution
+            + w.area
+            + w.anchor
+            + w.cluster_attraction
+            + w.cluster_overlap;
+    }
+}
+
+fn distance(a: NodeGeo, b: NodeGeo) -> f64 {
+    let (dx, dy) = (a.x - b.x, a.y - b.y);
+    return (dx * dx + dy * dy).sqrt();
+}
+
+fn near(geo: &EdgeGeo, p: NodeGeo, reach: f64) -> bool {
+    let p = Point::from(p);
+    return geo
+        .segments()
+        .iter()
+        .any(|s| p.euclidean_distance(s) < reach);
+}
+
+/// Squared coefficient of variation, zero for fewer than two values.
+fn spread(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = values.iter().sum::<f64>() / n;
+    if mean == 0.0 {
+        return 0.0;
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    return variance / (mean * mean);
+}
+
+impl GraphLayout {
+    /// The node's position, or `position` if it's the node being moved.
+    fn position_with(
+        &self,
+        n: NodeIndex,
+        moved: Option<(NodeIndex, NodeGeo)>,
+    ) -> Option<NodeGeo> {
+        return match moved {
+            Some((idx, position)) if idx == n => Some(position),
+            _ => self.node_geo(n).copied(),
+        };
+    }
+
+    /// Hop counts from `idx` to every node reachable from it.
+    pub fn hop_distances(&self, idx: NodeIndex) -> HashMap<NodeIndex, usize> {
+        let mut hops = HashMap::new();
+        let mut queue = VecDeque::new();
+        hops.insert(idx, 0);
+        queue.push_back(idx);
+        while let Some(n) = queue.pop_front() {
+            let next = hops[&n] + 1;
+            for m in self.graph.neighbors_undirected(n) {
+                if !hops.contains_key(&m) {
+                    hops.insert(m, next);
+                    queue.push_back(m);
+                }
+            }
+        }
+        return hops;
+    }
+
+    fn stress_from(
+        &self,
+        idx: NodeIndex,
+        moved: Option<(NodeIndex, NodeGeo)>,
+        ideal: f64,
+        others: impl Fn(NodeIndex) -> bool,
+    ) -> f64 {
+        let from = match self.position_with(idx, moved) {
+            Some(p) => p,
+            None => return 0.0,
+        };
+        return self
+            .hop_distances(idx)
+            .into_iter()
+            .filter(|(n, hops)| *hops > 0 && others(*n) && ideal > 0.0)
+            .filter_map(|(n, hops)| {
+                let target = ideal * hops as f64;
+                let actual = distance(from, self.position_with(n, moved)?);
+                Some(((actual - target) / target).powi(2))
+            })
+            .sum();
+    }
+
+    fn angular_shortfall(
+        &self,
+        idx: NodeIndex,
+        moved: Option<(NodeIndex, NodeGeo)>,
+    ) -> f64 {
+        let center = match self.position_with(idx, moved) {
+            Some(p) => p,
+            None => return 0.0,
+        };
+        let mut neighbors: Vec<NodeIndex> =
+            self.graph.neighbors_undirected(idx).collect();
+        neighbors.sort();
+        neighbors.dedup();
+        let mut angles: Vec<f64> = neighbors
+            .into_iter()
+            .filter_map(|n| self.position_with(n, moved))
+            .map(|p| (p.y - center.y).atan2(p.x - center.x))
+            .collect();
+        if angles.len() < 2 {
+            return 0.0;
+        }
+        angles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let wrap = angles[0] + 2.0 * PI - angles[angles.len() - 1];
+        let smallest =
+            angles.windows(2).map(|w| w[1] - w[0]).fold(wrap, f64::min);
+        let even = 2.0 * PI / angles.len() as f64;
+        return (even - smallest).max(0.0) / even;
+    }
+
+    fn edge_lengths(&self, moved: Option<(NodeIndex, NodeGeo)>) -> Vec<f64> {
+        return self
+            .segment_edge_indices()
+            .filter_map(|e| {
+                let (a, b) = self.graph.edge_endpoints(e)?;
+                Some(distance(
+                    self.position_with(a, moved)?,
+                    self.position_with(b, moved)?,
+                ))
+            })
+            .collect();
+    }
+
+    /// Nodes near the node's edges plus edges near the node, if the node
+    /// were at `position`.
+    fn overlaps_at(
+        &self,
+        idx: NodeIndex,
+        position: NodeGeo,
+        reach: f64,
+    ) -> usize {
+        let incident = self.incident_edges(idx);
+        let near_node = self
+            .graph_geo
+            .edges_in_envelope(&inflate(
+                Point::from(position).envelope(),
+                reach,
+            ))
+            .filter(|(e, _)| !incident.contains(e))
+            .filter(|(_, geo)| near(geo, position, reach))
+            .count();
+        let near_edges: usize = incident
+            .iter()
+            .filter_map(|e| {
+                let (a, b) = self.graph.edge_endpoints(*e)?;
+                let geo = self.moved_edge_geo(*e, idx, position)?;
+                let region = inflate(geo.envelope(), reach);
+                Some(
+                    self.graph_geo
+                        .nodes_in_envelope(&region)
+                        .filter(|(n, _)| **n != a && **n != b)
+                        .filter(|(_, p)| near(&geo, **p, reach))
+                        .count(),
+                )
+            })
+            .sum();
+        return near_node + near_edges;
+    }
+
+    /// The terms that can change when `idx` moves, with the node at
+    /// `position`. Pairwise terms only count pairs involving the node, so
+    /// the difference between two positions equals the change in the full
+    /// objective.
+    pub fn objective_terms_at(
+        &self,
+        objective: &LayoutObjective,
+        idx: NodeIndex,
+        position: NodeGeo,
+    ) -> ObjectiveTerms {
+        let moved = Some((idx, position));
+        let mut terms = ObjectiveTerms::default();
+        if objective.crossings != 0.0 {
+            terms.crossings = self.crossings_at(idx, position) as f64;
+        }
+        if objective.node_edge_overlaps != 0.0 {
+            terms.node_edge_overlaps =
+                self.overlaps_at(idx, position, objective.overlap_distance)
+                    as f64;
+        }
+        if objective.charge != 0.0 {
+            terms.charge = self.charge_at(idx, position);
+        }
+        if objective.stress != 0.0 {
+            terms.stress =
+                self.stress_from(idx, moved, objective.ideal_length, |_| true);
+        }
+        if objective.edge_length != 0.0 {
+            terms.edge_length = self
+                .graph
+                .neighbors_undirected(idx)
+                .filter_map(|n| self.node_geo(n))
+                .map(|p| {
+                    (distance(*p, position) - objective.ideal_length).powi(2)
+                })
+                .sum();
+        }
+        if objective.edge_length_uniformity != 0.0 {
+            terms.edge_length_uniformity = spread(&self.edge_lengths(moved));
+        }
+        if objective.angular_resolution != 0.0 {
+            terms.angular_resolution = std::iter::once(idx)
+                .chain(self.graph.neighbors_undirected(idx))
+                .map(|n| self.angular_shortfall(n, moved))
+                .sum();
+        }
+        if objective.area != 0.0 {
+            terms.area = self.area_at(idx, position);
+        }
+        if objective.anchor != 0.0 {
+            terms.anchor = self.anchor_distance(idx, position);
+        }
+        if objective.cluster_attraction != 0.0 {
+            terms.cluster_attraction =
+                self.cluster_attraction_at(idx, position);
+        }
+        if objective.cluster_overlap != 0.0 {
+            terms.cluster_overlap =
+                self.cluster_overlap_with(&objective.clusters, moved);
+        }
+        return terms;
+    }
+
+    /// How much moving a node would change the weighted objective; negative
+    /// means the move is an improvement.
+    pub fn objective_delta(
+        &self,
+        objective: &LayoutObjective,
+        idx: NodeIndex,
+        to: NodeGeo,
+    ) -> f64 {
+        let from = match self.node_geo(idx) {
+            Some(geo) => *geo,
+            None => return 0.0,
+        };
+        return objective.total(&self.objective_terms_at(objective, idx, to))
+            - objective.total(&self.objective_terms_at(objective, idx, from));
+    }
+
+    /// Squared distance of `position` from the node's anchor, if it has one.
+    pub fn anchor_distance(&self, idx: NodeIndex, position: NodeGeo) -> f64 {
+        return match self.anchors.get(&idx) {
+            Some(anchor) => {
+                (position.x - anchor.x).powi(2)
+                    + (position.y - anchor.y).powi(2)
+            }
+            None => 0.0,
+        };
+    }
+
+    /// Area of the bounding box of every node, with the node at `position`.
+    pub fn area_at(&self, idx: NodeIndex, position: NodeGeo) -> f64 {
+        let mut points = self.node_positions.iter().map(|(n, p)| {
+            if *n == idx {
+                position
+            } else {
+                *p
+            }
+        });
+        let first = match points.next() {
+            Some(p) => p,
+            None => return 0.0,
+        };
+        let (min, max) = points.fold((first, first), |(min, max), p| {
+            (
+                NodeGeo {
+                    x: min.x.min(p.x),
+                    y: min.y.min(p.y),
+                },
+                NodeGeo {
+                    x: max.x.max(p.x),
+                    y: max.y.max(p.y),
+                },
+            )
+        });
+        return (max.x - min.x) * (max.y - min.y);
+    }
+
+    /// Every term for the whole layout.
+    pub fn objective_terms(
+        &self,
+        objective: &LayoutObjective,
+    ) -> ObjectiveTerms {
+        let nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        let mut terms = ObjectiveTerms::default();
+        terms.crossings = self.count_graph_intersections(None) as f64;
+        terms.charge = self.sum_of_charges();
+        terms.node_edge_overlaps = self
+            .segment_edge_indices()
+            .filter_map(|e| {
+                let (a, b) = self.graph.edge_endpoints(e)?;
+                let geo = self.edge_geo(e)?;
+                let reach = objective.overlap_distance;
+                let region = inflate(geo.envelope(), reach);
+                Some(
+                    self.graph_geo
+                        .nodes_in_envelope(&region)
+                        .filter(|(n, _)| **n != a && **n != b)
+                        .filter(|(_, p)| near(&geo, **p, reach))
+                        .count() as f64,
+                )
+            })
+            .sum();
+        terms.stress = nodes
+            .iter()
+            .map(|n| {
+                self.stress_from(*n, None, objective.ideal_length, |m| m > *n)
+            })
+            .sum();
+        let lengths = self.edge_lengths(None);
+        terms.edge_length = lengths
+            .iter()
+            .map(|l| (l - objective.ideal_length).powi(2))
+            .sum();
+        terms.edge_length_uniformity = spread(&lengths);
+        terms.angular_resolution =
+            nodes.iter().map(|n| self.angular_shortfall(*n, None)).sum();
+        terms.area = match nodes.first() {
+            Some(n) => match self.node_geo(*n) {
+                Some(p) => self.area_at(*n, *p),
+                None => 0.0,
+            },
+            None => 0.0,
+        };
+        terms.anchor = nodes
+            .iter()
+            .filter_map(|n| Some(self.anchor_distance(*n, *self.node_geo(*n)?)))
+            .sum();
+        terms.cluster_attraction = self.cluster_attraction();
+        terms.cluster_overlap =
+            self.cluster_overlap_with(&objective.clusters, None);
+        return terms;
+    }
+
+    pub fn objective_breakdown(
+        &self,
+        objective: &LayoutObjective,
+    ) -> ObjectiveBreakdown {
+        let terms = self.objective_terms(objective);
+        return ObjectiveBreakdown {
+            total: objective.total(&terms),
+            terms: terms,
+            weighted: objective.weigh(&terms),
+        };
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// The weighted objective for the current layout, with each term's raw
+    /// and weighted value.
+    pub fn evaluate_objective(
+        &self,
+        objective: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let objective: LayoutObjective = settings_from_js(&objective)?;
+        let breakdown = self.objective_breakdown(&objective);
+        return Ok(JsValue::from_serde(&breakdown).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{edge, graph, id, node};
+    use geo::Coordinate;
+    use im::OrdMap;
+
+    #[test]
+    fn test_move_delta_matches_full_objective() {
+        let mut layout = graph(
+            vec![
+                node("a", 0., 0.),
+                node("b", 100., 100.),
+                node("c", 0., 100.),
+                node("d", 100., 0.),
+                node("e", 52., 48.),
+            ],
+            vec![
+                edge("a", "b"),
+                edge("c", "d"),
+                edge("a", "c"),
+                edge("d", "e"),
+            ],
+        );
+        let objective = LayoutObjective {
+            crossings: 1.0,
+            node_edge_overlaps: 2.0,
+            charge: 100.0,
+            stress: 0.5,
+            edge_length: 0.001,
+            edge_length_uniformity: 3.0,
+            angular_resolution: 1.5,
+            area: 0.0001,
+            anchor: 0.01,
+            cluster_attraction: 0.0,
+            cluster_overlap: 0.0,
+            clusters: ClusterSettings::default(),
+            overlap_distance: 5.0,
+            ideal_length: 60.0,
+        };
+        let b = layout.node_index(&id("b")).unwrap();
+        layout.set_anchors(OrdMap::unit(b, Coordinate { x: 90., y: 110. }));
+        let before = layout.objective_breakdown(&objective);
+        assert_eq!(before.terms.node_edge_overlaps, 2.0);
+
+        let to = Coordinate { x: 40., y: 130. };
+        let delta = layout.objective_delta(&objective, b, to);
+        layout.set_node_geo(b, to);
+        let after = layout.objective_breakdown(&objective);
+        assert!((after.total - before.total - delta).abs() < 1e-9);
+    }
+}
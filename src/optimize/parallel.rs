@@ -0,0 +1,204 @@
+use super::{Optimizer, OptimizerSettings};
+use crate::geometry::tree::Envelope;
+use crate::geometry::NodeGeo;
+use crate::layout::GraphLayout;
+use ordered_float::OrderedFloat;
+use petgraph::graph::NodeIndex;
+use rayon::prelude::*;
+use rstar::Envelope as _;
+use rstar::{RTreeObject, AABB};
+use std::collections::HashSet;
+
+impl GraphLayout {
+    /// The area a move can affect: everything its node's edges cover before
+    /// and after the move.
+    fn move_region(&self, idx: NodeIndex, to: NodeGeo) -> Option<Envelope> {
+        let from = *self.node_geo(idx)?;
+        let mut region = AABB::from_corners(from.into(), to.into());
+        for e in self.incident_edges(idx) {
+            for position in vec![from, to] {
+                if let Some(geo) = self.moved_edge_geo(e, idx, position) {
+                    region.merge(&geo.envelope());
+                }
+            }
+        }
+        return Some(region);
+    }
+}
+
+impl Optimizer {
+    /// Proposes `batch_size` moves, scores them all in parallel against the
+    /// current layout, then applies the best ones that don't touch the same
+    /// nodes or regions, in order. Returns how many moves were applied.
+    ///
+    /// Proposals come from the optimizer's seeded generator and are ranked
+    /// by score and then by proposal order, so results only depend on the
+    /// seed and not on how rayon schedules the work. With a symmetry set,
+    /// the parallel scores only rank the moves; each is scored again for
+    /// its whole orbit with `move_delta` before `move_node` applies it.
+    pub fn step_batch(
+        &mut self,
+        layout: &mut GraphLayout,
+        batch_size: usize,
+    ) -> usize {
+        let settings = match self.settings {
+            OptimizerSettings::Descent(settings) => settings,
+            // Annealing depends on accepting moves one at a time.
+            OptimizerSettings::Annealing(_) => {
+                return (0..batch_size).filter(|_| self.step(layout)).count();
+            }
+        };
+        let candidates: Vec<(NodeIndex, NodeGeo)> = (0..batch_size)
+            .filter_map(|_| self.propose(layout, settings.step_size))
+            .collect();
+
+        let snapshot: &GraphLayout = layout;
+        let mut scored: Vec<(usize, f64)> = candidates
+            .par_iter()
+            .enumerate()
+            .map(|(i, (idx, to))| {
+                (i, snapshot.objective_delta(&settings.objective, *idx, *to))
+            })
+            .filter(|(_, delta)| *delta < 0.0 || snapshot.symmetry.is_some())
+            .collect();
+        scored.sort_by_key(|(i, delta)| (OrderedFloat(*delta), *i));
+
+        let mut touched: HashSet<NodeIndex> = HashSet::new();
+        let mut regions: Vec<Envelope> = vec![];
+        let mut applied = 0;
+        for (i, _) in scored {
+            let (idx, to) = candidates[i];
+            let moves = layout.orbit_moves(idx, to);
+            let clashes = moves.iter().any(|(n, _)| {
+                touched.contains(n)
+                    || layout
+                        .graph
+                        .neighbors_undirected(*n)
+                        .any(|m| touched.contains(&m))
+            });
+            if clashes {
+                continue;
+            }
+            let moved_regions: Vec<Envelope> = moves
+                .iter()
+                .filter_map(|(n, p)| layout.move_region(*n, *p))
+                .collect();
+            if moved_regions
+                .iter()
+                .any(|m| regions.iter().any(|r| r.intersects(m)))
+            {
+                continue;
+            }
+            // Pairs of accepted moves still interact through their charge,
+            // so check the move again now that earlier ones have landed.
+            if layout.move_delta(&settings.objective, idx, to) >= 0.0 {
+                continue;
+            }
+            layout.move_node(idx, to);
+            for (n, _) in moves {
+                touched.insert(n);
+                touched.extend(layout.graph.neighbors_undirected(n));
+            }
+            regions.extend(moved_regions);
+            applied += 1;
+        }
+        return applied;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{edge, graph, id, node};
+
+    fn wheel() -> GraphLayout {
+        let nodes = (0..12)
+            .map(|i| {
+                node(
+                    i,
+                    ((i * 7) % 12) as f64 * 10.0,
+                    ((i * 5) % 12) as f64 * 10.0,
+                )
+            })
+            .collect();
+        let edges = (0..12)
+            .flat_map(|i| vec![edge(i, (i + 1) % 12), edge(i, (i + 5) % 12)])
+            .collect();
+        return graph(nodes, edges);
+    }
+
+    #[test]
+    fn test_batches_are_deterministic_and_improving() {
+        let run = || {
+            let mut layout = wheel();
+            let mut optimizer = Optimizer::new(Default::default(), 7);
+            for _ in 0..20 {
+                optimizer.step_batch(&mut layout, 16);
+            }
+            layout
+        };
+        let energy = |l: &GraphLayout| {
+            l.count_graph_intersections(None) as f64 + l.sum_of_charges()
+        };
+
+        let (first, second) = (run(), run());
+        let positions = |l: &GraphLayout| -> Vec<(f64, f64)> {
+            l.all_nodes_data()
+                .iter()
+                .map(|n| (n.x.unwrap(), n.y.unwrap()))
+                .collect()
+        };
+        assert_eq!(positions(&first), positions(&second));
+        assert!(energy(&first) <= energy(&wheel()));
+    }
+
+    #[test]
+    fn test_crossings_reach_beyond_the_moved_edges() {
+        use geo::Coordinate;
+
+        // A short edge from node 0 to node 1 and a long edge across it
+        // whose bounding box is far bigger than the short edge's.
+        let layout = graph(
+            vec![
+                node(0, 0.0, 0.0),
+                node(1, 2.0, 0.0),
+                node(2, 5.0, -1000.0),
+                node(3, 5.0, 1000.0),
+            ],
+            vec![edge(0, 1), edge(2, 3)],
+        );
+        let moved = layout.node_index(&id(1)).unwrap();
+        let at = |x: f64| Coordinate { x: x, y: 0.0 };
+        assert_eq!(layout.crossings_at(moved, at(2.0)), 0);
+        assert_eq!(layout.crossings_at(moved, at(10.0)), 1);
+    }
+
+    #[test]
+    fn test_batches_keep_symmetry() {
+        use crate::symmetry::{SymmetrySettings, SymmetrySource};
+
+        let mut layout = wheel();
+        let turn = SymmetrySettings {
+            source: SymmetrySource::Permutation {
+                pairs: (0..12)
+                    .map(|i| (i.to_string(), ((i + 1) % 12).to_string()))
+                    .collect(),
+            },
+            ..SymmetrySettings::default()
+        };
+        assert!(layout.symmetric_layout_with(&turn));
+        let mut optimizer = Optimizer::new(Default::default(), 7);
+        let applied: usize =
+            (0..20).map(|_| optimizer.step_batch(&mut layout, 16)).sum();
+        assert!(applied > 0);
+
+        let symmetry = layout.symmetry.clone().unwrap();
+        let orbit = &symmetry.orbits[0];
+        let first = *layout.node_geo(orbit[0]).unwrap();
+        for (idx, p) in symmetry.orbit_positions(orbit[0], first) {
+            let actual = layout.node_geo(idx).unwrap();
+            assert!((actual.x - p.x).abs() < 1e-6);
+            assert!((actual.y - p.y).abs() < 1e-6);
+        }
+    }
+}
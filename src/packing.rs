@@ -0,0 +1,582 @@
+use crate::circular::CircularSettings;
+use crate::geometry::NodeGeo;
+use crate::layered::LayeredSettings;
+use crate::layout::{GraphData, GraphLayout};
+use crate::multilevel::MultilevelSettings;
+use crate::spectral::SpectralSettings;
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use wasm_bindgen::prelude::*;
+
+/// How each connected component is laid out before packing.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "algorithm", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ComponentLayout {
+    /// Keeps each component's current shape.
+    Current,
+    Spectral(SpectralSettings),
+    Multilevel(MultilevelSettings),
+    Circular(CircularSettings),
+    Layered(LayeredSettings),
+}
+
+impl Default for ComponentLayout {
+    fn default() -> Self {
+        return ComponentLayout::Current;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Packing {
+    /// Bounding boxes in rows.
+    Rectangle,
+    /// Components as sets of grid cells, fitted into each other's gaps
+    /// (Freivalds et al.).
+    Polyomino,
+}
+
+impl Default for Packing {
+    fn default() -> Self {
+        return Packing::Polyomino;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PackingSettings {
+    pub layout: ComponentLayout,
+    pub packing: Packing,
+    /// Width over height the packed layout aims for, between 0.01 and 100.
+    /// Anything else, including 0 or NaN, counts as 1.
+    pub aspect_ratio: f64,
+    /// Least space between components.
+    pub gap: f64,
+    /// Polyomino cell size. 0 picks one giving each component about a
+    /// hundred cells.
+    pub cell: f64,
+}
+
+impl Default for PackingSettings {
+    fn default() -> Self {
+        return PackingSettings {
+            layout: ComponentLayout::default(),
+            packing: Packing::default(),
+            aspect_ratio: 1.0,
+            gap: 20.0,
+            cell: 0.0,
+        };
+    }
+}
+
+impl PackingSettings {
+    /// `aspect_ratio`, or 1 if it's out of range.
+    pub fn aspect(&self) -> f64 {
+        return if self.aspect_ratio >= 0.01 && self.aspect_ratio <= 100.0 {
+            self.aspect_ratio
+        } else {
+            1.0
+        };
+    }
+}
+
+/// A laid-out component: its nodes' positions and the points its edges pass
+/// through, all relative to wherever its layout put them.
+struct Piece {
+    nodes: Vec<(NodeIndex, NodeGeo)>,
+    points: Vec<NodeGeo>,
+    min: NodeGeo,
+    max: NodeGeo,
+}
+
+impl Piece {
+    fn size(&self) -> NodeGeo {
+        return self.max - self.min;
+    }
+}
+
+/// Places boxes of the given sizes in rows, tallest first, trying several
+/// row widths and keeping the packing whose area, stretched to the target
+/// aspect ratio, is smallest. Returns each box's corner.
+pub fn pack_rectangles(
+    sizes: &[NodeGeo],
+    gap: f64,
+    aspect: f64,
+) -> Vec<NodeGeo> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|a, b| sizes[*b].y.partial_cmp(&sizes[*a].y).unwrap());
+    let widest = sizes.iter().map(|s| s.x).fold(0.0, f64::max);
+    let total: f64 = sizes.iter().map(|s| s.x + gap).sum();
+
+    let shelf = |width: f64| {
+        let mut corners = vec![Coordinate { x: 0.0, y: 0.0 }; sizes.len()];
+        let (mut x, mut y, mut row, mut right) = (0.0, 0.0, 0.0, 0.0);
+        for i in order.iter() {
+            let size = sizes[*i];
+            if x > 0.0 && x + size.x > width {
+                x = 0.0;
+                y += row + gap;
+                row = 0.0;
+            }
+            corners[*i] = Coordinate { x: x, y: y };
+            x += size.x + gap;
+            row = f64::max(row, size.y);
+            right = f64::max(right, x - gap);
+        }
+        let extent = Coordinate {
+            x: right,
+            y: y + row,
+        };
+        (corners, extent)
+    };
+    let score = |extent: NodeGeo| {
+        let (w, h) = (extent.x.max(1e-9), extent.y.max(1e-9));
+        let stretch = (w / h) / aspect;
+        w * h * stretch.max(1.0 / stretch)
+    };
+    return (0..=32)
+        .map(|k| widest + (total - widest).max(0.0) * k as f64 / 32.0)
+        .map(shelf)
+        .min_by(|a, b| score(a.1).partial_cmp(&score(b.1)).unwrap())
+        .map(|(corners, _)| corners)
+        .unwrap_or_default();
+}
+
+/// Grid cells within `pad` of the piece's points, relative to the cell
+/// holding its centre.
+fn cells(piece: &Piece, cell: f64, pad: f64) -> HashSet<(i64, i64)> {
+    let center = (piece.min + piece.max) / 2.0;
+    let index = |v: f64| (v / cell).floor() as i64;
+    let mut covered = HashSet::new();
+    for p in piece.points.iter() {
+        let d = *p - center;
+        for i in index(d.x - pad)..=index(d.x + pad) {
+            for j in index(d.y - pad)..=index(d.y + pad) {
+                covered.insert((i, j));
+            }
+        }
+    }
+    return covered;
+}
+
+/// Fits the pieces' cells together, biggest first, each at the free offset
+/// nearest the origin in a metric stretched to the target aspect ratio.
+/// Returns each piece's offset in cells.
+fn pack_polyominoes(
+    polyominoes: &[HashSet<(i64, i64)>],
+    aspect: f64,
+) -> Vec<(i64, i64)> {
+    let mut order: Vec<usize> = (0..polyominoes.len()).collect();
+    order.sort_by_key(|i| std::cmp::Reverse(polyominoes[*i].len()));
+    let (ax, ay) = (aspect.sqrt(), 1.0 / aspect.sqrt());
+    let mut occupied: HashSet<(i64, i64)> = HashSet::new();
+    let mut offsets = vec![(0, 0); polyominoes.len()];
+    for i in order {
+        let fits = |(dx, dy): (i64, i64)| {
+            polyominoes[i]
+                .iter()
+                .all(|(x, y)| !occupied.contains(&(x + dx, y + dy)))
+        };
+        let mut found = None;
+        let mut ring = 0;
+        while found.is_none() {
+            // Offsets on this ring of the stretched metric, nearest first.
+            let (rx, ry) = (
+                ((ring + 1) as f64 * ax).ceil() as i64,
+                ((ring + 1) as f64 * ay).ceil() as i64,
+            );
+            let mut candidates: Vec<(f64, (i64, i64))> = vec![];
+            for dx in -rx..=rx {
+                for dy in -ry..=ry {
+                    let metric =
+                        (dx.abs() as f64 / ax).max(dy.abs() as f64 / ay);
+                    if metric >= ring as f64 && metric < (ring + 1) as f64 {
+                        let round = (dx * dx + dy * dy) as f64;
+                        candidates.push((metric + round * 1e-9, (dx, dy)));
+                    }
+                }
+            }
+            candidates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            found = candidates.into_iter().map(|(_, d)| d).find(|d| fits(*d));
+            ring += 1;
+        }
+        let (dx, dy) = found.unwrap();
+        occupied.extend(polyominoes[i].iter().map(|(x, y)| (x + dx, y + dy)));
+        offsets[i] = (dx, dy);
+    }
+    return offsets;
+}
+
+impl GraphLayout {
+    /// A separate layout of just `nodes` and the edges among them, with the
+    /// same options, positions and edge shapes.
+    pub fn component_layout(&self, nodes: &[NodeIndex]) -> GraphLayout {
+        let inside: HashSet<NodeIndex> = nodes.iter().copied().collect();
+        let edges: Vec<_> = self
+            .graph
+            .edge_indices()
+            .filter(|e| match self.graph.edge_endpoints(*e) {
+                Some((a, b)) => inside.contains(&a) && inside.contains(&b),
+                None => false,
+            })
+            .collect();
+        let mut component = GraphLayout::from_graph_data(
+            GraphData {
+                nodes: nodes
+                    .iter()
+                    .filter_map(|n| self.node_data(*n))
+                    .collect(),
+                edges: edges
+                    .iter()
+                    .filter_map(|e| self.edge_data(*e))
+                    .collect(),
+            },
+            self.options,
+        );
+        for e in edges {
+            if let Some((idx, _)) = self.matching_edge(&component, e) {
+                component.set_edge_shape(idx, self.graph[e].shape.clone());
+            }
+        }
+        return component;
+    }
+
+    /// The segment-owning edge of `other` joining the same nodes as `edge`,
+    /// and whether it runs the other way.
+    fn matching_edge(
+        &self,
+        other: &GraphLayout,
+        edge: petgraph::graph::EdgeIndex,
+    ) -> Option<(petgraph::graph::EdgeIndex, bool)> {
+        let (a, b) = self.graph.edge_endpoints(edge)?;
+        let (a, b) = (
+            other.node_index(&self.node_id(a)?)?,
+            other.node_index(&self.node_id(b)?)?,
+        );
+        let found = other.graph.find_edge_undirected(a, b)?.0;
+        let owner = other.graph[found].segment_of.unwrap_or(found);
+        let (from, _) = other.graph.edge_endpoints(owner)?;
+        return Some((owner, from != a));
+    }
+
+    /// Lays out each connected component on its own, then packs them
+    /// without overlaps around the layout's current centre.
+    pub fn pack_components_with(&mut self, settings: &PackingSettings) {
+        let components = self.connected_components();
+        if components.is_empty() {
+            return;
+        }
+        let center = self.placement_center(&HashSet::new());
+        let mut layouts: Vec<GraphLayout> = vec![];
+        let mut pieces: Vec<Piece> = vec![];
+        for nodes in components.iter() {
+            let mut component = self.component_layout(nodes);
+            match settings.layout {
+                ComponentLayout::Current => {}
+                ComponentLayout::Spectral(s) => {
+                    component.spectral_layout_with(&s);
+                }
+                ComponentLayout::Multilevel(s) => {
+                    component.multilevel_layout_with(&s)
+                }
+                ComponentLayout::Circular(s) => {
+                    component.circular_layout_with(&s)
+                }
+                ComponentLayout::Layered(s) => {
+                    component.layered_layout_with(&s)
+                }
+            }
+            let positions: Vec<(NodeIndex, NodeGeo)> = nodes
+                .iter()
+                .filter_map(|n| {
+                    let idx = component.node_index(&self.node_id(*n)?)?;
+                    Some((*n, *component.node_geo(idx)?))
+                })
+                .collect();
+            let mut points: Vec<NodeGeo> =
+                positions.iter().map(|(_, p)| *p).collect();
+            for e in component.segment_edge_indices() {
+                if let Some(geo) = component.edge_geo(e) {
+                    let segments = geo.segments();
+                    for s in segments.iter() {
+                        // Enough samples that no cell along the edge is
+                        // skipped, whatever the cell size.
+                        let length = (s.dx().powi(2) + s.dy().powi(2)).sqrt();
+                        let steps = (length / (settings.gap / 2.0).max(1.0))
+                            .ceil()
+                            .max(1.0)
+                            as usize;
+                        for k in 0..=steps {
+                            let t = k as f64 / steps as f64;
+                            points.push(s.start + (s.end - s.start) * t);
+                        }
+                    }
+                }
+            }
+            let min = points.iter().fold(
+                Coordinate {
+                    x: f64::MAX,
+                    y: f64::MAX,
+                },
+                |m, p| Coordinate {
+                    x: m.x.min(p.x),
+                    y: m.y.min(p.y),
+                },
+            );
+            let max = points.iter().fold(
+                Coordinate {
+                    x: f64::MIN,
+                    y: f64::MIN,
+                },
+                |m, p| Coordinate {
+                    x: m.x.max(p.x),
+                    y: m.y.max(p.y),
+                },
+            );
+            pieces.push(Piece {
+                nodes: positions,
+                points: points,
+                min: min,
+                max: max,
+            });
+            layouts.push(component);
+        }
+
+        // Where each piece's own coordinates end up.
+        let shifts: Vec<NodeGeo> = match settings.packing {
+            Packing::Rectangle => {
+                let sizes: Vec<NodeGeo> =
+                    pieces.iter().map(|p| p.size()).collect();
+                pack_rectangles(&sizes, settings.gap, settings.aspect())
+                    .into_iter()
+                    .zip(pieces.iter())
+                    .map(|(corner, piece)| corner - piece.min)
+                    .collect()
+            }
+            Packing::Polyomino => {
+                let area: f64 = pieces
+                    .iter()
+                    .map(|p| {
+                        (p.size().x + settings.gap)
+                            * (p.size().y + settings.gap)
+                    })
+                    .sum();
+                let cell = if settings.cell > 0.0 {
+                    settings.cell
+                } else {
+                    (area / (100.0 * pieces.len() as f64)).sqrt().max(1.0)
+                };
+                let polyominoes: Vec<HashSet<(i64, i64)>> = pieces
+                    .iter()
+                    .map(|p| cells(p, cell, settings.gap / 2.0))
+                    .collect();
+                pack_polyominoes(&polyominoes, settings.aspect())
+                    .into_iter()
+                    .zip(pieces.iter())
+                    .map(|((dx, dy), piece)| {
+                        let offset = Coordinate {
+                            x: dx as f64 * cell,
+                            y: dy as f64 * cell,
+                        };
+                        offset - (piece.min + piece.max) / 2.0
+                    })
+                    .collect()
+            }
+        };
+
+        // Keep the packed layout centred where the graph was.
+        let count = pieces.iter().map(|p| p.nodes.len()).sum::<usize>().max(1);
+        let packed_center = pieces
+            .iter()
+            .zip(shifts.iter())
+            .flat_map(|(piece, shift)| {
+                piece.nodes.iter().map(move |(_, p)| *p + *shift)
+            })
+            .fold(Coordinate { x: 0.0, y: 0.0 }, |sum, p| sum + p)
+            / count as f64;
+        let recenter = center - packed_center;
+
+        let moved: Vec<(NodeIndex, NodeGeo)> = pieces
+            .iter()
+            .zip(shifts.iter())
+            .flat_map(|(piece, shift)| {
+                piece
+                    .nodes
+                    .iter()
+                    .map(move |(idx, p)| (*idx, *p + *shift + recenter))
+            })
+            .collect();
+        self.place_nodes_at(moved);
+        for (component, shift) in layouts.iter().zip(shifts) {
+            let edges: Vec<_> = self
+                .segment_edge_indices()
+                .filter(|e| match self.graph.edge_endpoints(*e) {
+                    Some((a, _)) => component
+                        .node_index(&self.node_id(a).unwrap())
+                        .is_some(),
+                    None => false,
+                })
+                .collect();
+            for e in edges {
+                let (idx, reversed) = match self.matching_edge(component, e) {
+                    Some(found) => found,
+                    None => continue,
+                };
+                let mut shape =
+                    component.graph[idx].shape.translated(shift + recenter);
+                if let (true, crate::geometry::EdgeShape::Polyline(bends)) =
+                    (reversed, &mut shape)
+                {
+                    bends.reverse();
+                }
+                self.set_edge_shape(e, shape);
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Lays out each connected component separately and packs them
+    /// together without overlaps, as one undo step.
+    pub fn pack_components(
+        &mut self,
+        settings: JsValue,
+    ) -> Result<(), JsValue> {
+        let settings: PackingSettings = settings_from_js(&settings)?;
+        self.checkpoint_with_label("pack components");
+        self.pack_components_with(&settings);
+        return Ok(());
+    }
+
+    /// Node ids grouped by connected component.
+    pub fn components(&self) -> JsValue {
+        let ids: Vec<Vec<String>> = self
+            .connected_components()
+            .into_iter()
+            .map(|nodes| {
+                nodes
+                    .into_iter()
+                    .filter_map(|n| Some(self.node_id(n)?.to_string()))
+                    .collect()
+            })
+            .collect();
+        return JsValue::from_serde(&ids).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::EdgeData;
+    use crate::test_utils::{edge, graph, id, node};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_packed_components_do_not_overlap() {
+        // A 6-cycle, a triangle, a path of four, two pairs and a loner.
+        let mut edges: Vec<EdgeData> =
+            (0..6).map(|i| edge(i, (i + 1) % 6)).collect();
+        edges.extend(vec![edge(6, 7), edge(7, 8), edge(8, 6)]);
+        edges.extend(vec![edge(9, 10), edge(10, 11), edge(11, 12)]);
+        edges.extend(vec![edge(13, 14), edge(15, 16)]);
+
+        for packing in [Packing::Rectangle, Packing::Polyomino].iter().copied()
+        {
+            let mut layout = graph(
+                (0..18).map(|i| node(i, 0.0, 0.0)).collect(),
+                edges.clone(),
+            );
+            assert_eq!(layout.connected_components().len(), 6);
+            let settings = PackingSettings {
+                layout: ComponentLayout::Circular(CircularSettings::default()),
+                packing: packing,
+                aspect_ratio: 1.5,
+                ..PackingSettings::default()
+            };
+            layout.pack_components_with(&settings);
+
+            let components = layout.connected_components();
+            let component_of: HashMap<NodeIndex, usize> = components
+                .iter()
+                .enumerate()
+                .flat_map(|(c, nodes)| nodes.iter().map(move |n| (*n, c)))
+                .collect();
+            let positions: Vec<(NodeIndex, NodeGeo)> = layout
+                .node_positions
+                .iter()
+                .map(|(idx, p)| (*idx, *p))
+                .collect();
+            for (a, p) in positions.iter() {
+                for (b, q) in positions.iter() {
+                    if component_of[a] != component_of[b] {
+                        let d = (p.x - q.x).abs().max((p.y - q.y).abs());
+                        assert!(d >= settings.gap - 1e-9);
+                    }
+                }
+            }
+            // Each component keeps its circular shape.
+            let cycle: Vec<NodeGeo> = (0..6)
+                .map(|i| {
+                    *layout
+                        .node_geo(layout.node_index(&id(i)).unwrap())
+                        .unwrap()
+                })
+                .collect();
+            let side = |a: NodeGeo, b: NodeGeo| {
+                ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+            };
+            let first = side(cycle[0], cycle[1]);
+            for i in 0..6 {
+                assert!(
+                    (side(cycle[i], cycle[(i + 1) % 6]) - first).abs() < 1e-6
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_packing_keeps_grid_cells_unique() {
+        // Five triangles, drawn smaller than a grid cell.
+        let edges: Vec<EdgeData> = (0..5)
+            .flat_map(|t| {
+                (0..3).map(move |i| edge(3 * t + i, 3 * t + (i + 1) % 3))
+            })
+            .collect();
+        let mut layout =
+            graph((0..15).map(|i| node(i, 0.0, 0.0)).collect(), edges);
+        layout.snap_to_grid(-6);
+        layout.pack_components_with(&PackingSettings::default());
+        let cells: HashSet<(i64, i64)> = layout
+            .node_positions
+            .values()
+            .map(|p| {
+                assert_eq!((p.x % 64.0, p.y % 64.0), (0.0, 0.0));
+                (p.x as i64, p.y as i64)
+            })
+            .collect();
+        assert_eq!(cells.len(), 15);
+    }
+
+    #[test]
+    fn test_bad_aspect_ratios_fall_back_to_square() {
+        for aspect in vec![0.0, -2.0, std::f64::NAN, std::f64::INFINITY, 1e9] {
+            let settings = PackingSettings {
+                aspect_ratio: aspect,
+                ..PackingSettings::default()
+            };
+            assert_eq!(settings.aspect(), 1.0);
+        }
+        let square: Vec<HashSet<(i64, i64)>> = (0..4)
+            .map(|_| vec![(0, 0), (0, 1), (1, 0), (1, 1)].into_iter().collect())
+            .collect();
+        let settings = PackingSettings {
+            aspect_ratio: std::f64::NAN,
+            ..PackingSettings::default()
+        };
+        assert_eq!(pack_polyominoes(&square, settings.aspect()).len(), 4);
+    }
+}
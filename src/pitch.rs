@@ -0,0 +1,373 @@
+use crate::geometry::NodeGeo;
+use crate::layout::GraphLayout;
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use im::OrdMap;
+use petgraph::graph::NodeIndex;
+use serde::de::Deserializer;
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+
+/// A set of pitch classes 0-11, bit `p` standing for pitch class `p`.
+/// Serialized as the ascending list of its pitch classes.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub struct PitchClassSet(pub u16);
+
+impl PitchClassSet {
+    pub fn from_pitch_classes<I: IntoIterator<Item = i64>>(
+        pitch_classes: I,
+    ) -> PitchClassSet {
+        return PitchClassSet(
+            pitch_classes
+                .into_iter()
+                .fold(0, |bits, p| bits | 1 << p.rem_euclid(12)),
+        );
+    }
+
+    /// Parses a comma-separated list of pitch classes, like the ids in
+    /// `graph-data.json`.
+    pub fn parse(text: &str) -> Option<PitchClassSet> {
+        let pitch_classes: Result<Vec<i64>, _> =
+            text.split(',').map(|p| p.trim().parse::<i64>()).collect();
+        return pitch_classes.ok().map(PitchClassSet::from_pitch_classes);
+    }
+
+    pub fn contains(&self, pitch_class: usize) -> bool {
+        return self.0 & 1 << pitch_class != 0;
+    }
+
+    pub fn pitch_classes(&self) -> Vec<usize> {
+        return (0..12).filter(|p| self.contains(*p)).collect();
+    }
+
+    /// The `k`th coefficient of the discrete Fourier transform of the set's
+    /// 12-bin indicator vector, as a point in the complex plane.
+    pub fn dft(&self, k: usize) -> NodeGeo {
+        return self.pitch_classes().into_iter().fold(
+            Coordinate { x: 0.0, y: 0.0 },
+            |sum, p| {
+                let angle = -2.0 * PI * (k * p) as f64 / 12.0;
+                Coordinate {
+                    x: sum.x + angle.cos(),
+                    y: sum.y + angle.sin(),
+                }
+            },
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        return self.0.count_ones() as usize;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.0 == 0;
+    }
+
+    pub fn complement(&self) -> PitchClassSet {
+        return PitchClassSet(!self.0 & 0xfff);
+    }
+
+    pub fn transpose(&self, semitones: usize) -> PitchClassSet {
+        let n = semitones % 12;
+        return PitchClassSet((self.0 << n | self.0 >> (12 - n)) & 0xfff);
+    }
+
+    /// Reflects every pitch class `p` to `-p`.
+    pub fn invert(&self) -> PitchClassSet {
+        return PitchClassSet::from_pitch_classes(
+            self.pitch_classes().into_iter().map(|p| -(p as i64)),
+        );
+    }
+
+    pub fn intervals(&self) -> Intervals {
+        return Intervals(match self.pitch_classes().first() {
+            Some(lowest) => self.transpose(12 - lowest),
+            None => *self,
+        });
+    }
+
+    /// A canonical member of the set's class: the one with the smallest
+    /// bits among its transpositions, and its inversions' if `inversion`.
+    pub fn set_class(&self, inversion: bool) -> PitchClassSet {
+        let inverted = self.invert();
+        return (0..12)
+            .flat_map(|n| {
+                let mut forms = vec![self.transpose(n)];
+                if inversion {
+                    forms.push(inverted.transpose(n));
+                }
+                forms
+            })
+            .min()
+            .unwrap_or(*self);
+    }
+
+    /// Smallest total number of semitones the voices of one set have to
+    /// move to reach the other, if both have as many pitch classes. Sorted
+    /// around the circle, some rotation of one set's order against the
+    /// other's is always an optimal pairing.
+    pub fn voice_leading_distance(
+        &self,
+        other: &PitchClassSet,
+    ) -> Option<usize> {
+        let (a, b) = (self.pitch_classes(), other.pitch_classes());
+        if a.len() != b.len() {
+            return None;
+        }
+        let step = |p: usize, q: usize| {
+            let d = (p + 12 - q) % 12;
+            d.min(12 - d)
+        };
+        return (0..a.len().max(1))
+            .map(|r| {
+                (0..a.len())
+                    .map(|i| step(a[i], b[(i + r) % b.len()]))
+                    .sum::<usize>()
+            })
+            .min();
+    }
+}
+
+impl std::fmt::Display for PitchClassSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pitch_classes: Vec<String> =
+            self.pitch_classes().iter().map(|p| p.to_string()).collect();
+        return write!(f, "{}", pitch_classes.join(","));
+    }
+}
+
+/// The steps between consecutive pitch classes of a set going up from its
+/// lowest, the last wrapping round to the octave. Kept as the transposition
+/// of the set that starts on 0, and serialized as the list of steps.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub struct Intervals(pub PitchClassSet);
+
+impl Intervals {
+    pub fn steps(&self) -> Vec<usize> {
+        let pitch_classes = self.0.pitch_classes();
+        return pitch_classes
+            .iter()
+            .enumerate()
+            .map(|(i, p)| match pitch_classes.get(i + 1) {
+                Some(next) => next - p,
+                None => 12 - p,
+            })
+            .collect();
+    }
+}
+
+impl Serialize for Intervals {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        return serializer.collect_seq(self.steps());
+    }
+}
+
+impl<'de> Deserialize<'de> for Intervals {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Intervals, D::Error> {
+        let steps = Vec::<i64>::deserialize(deserializer)?;
+        let starts = steps.iter().scan(0, |p, step| {
+            let start = *p;
+            *p += step;
+            Some(start)
+        });
+        return Ok(Intervals(PitchClassSet::from_pitch_classes(starts)));
+    }
+}
+
+impl Serialize for PitchClassSet {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        return serializer.collect_seq(self.pitch_classes());
+    }
+}
+
+impl<'de> Deserialize<'de> for PitchClassSet {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<PitchClassSet, D::Error> {
+        let pitch_classes = Vec::<i64>::deserialize(deserializer)?;
+        return Ok(PitchClassSet::from_pitch_classes(pitch_classes));
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChromaSettings {
+    /// Which Fourier coefficient, 1-6, to place nodes by. The 5th puts sets
+    /// around the circle of fifths.
+    pub coefficient: usize,
+    /// Distance per unit of the coefficient's magnitude.
+    pub scale: f64,
+    /// Radius of the small circle nodes with equal coefficients are spread
+    /// around.
+    pub spread: f64,
+    /// Also anchors nodes at these positions, for the objective's `anchor`
+    /// term to pull them back towards.
+    pub anchor: bool,
+}
+
+impl Default for ChromaSettings {
+    fn default() -> Self {
+        return ChromaSettings {
+            coefficient: 5,
+            scale: 50.0,
+            spread: 5.0,
+            anchor: true,
+        };
+    }
+}
+
+impl GraphLayout {
+    /// The node's pitch-class set: its `chroma` if given, otherwise its id
+    /// read as comma-separated pitch classes.
+    pub fn pitch_class_set(&self, idx: NodeIndex) -> Option<PitchClassSet> {
+        let data = self.graph.node_weight(idx)?;
+        return data.chroma.or_else(|| PitchClassSet::parse(&data.id));
+    }
+
+    /// Positions at each pitch-class set's DFT coefficient. Nodes without a
+    /// set are left out.
+    pub fn chroma_positions(
+        &self,
+        settings: &ChromaSettings,
+    ) -> Vec<(NodeIndex, NodeGeo)> {
+        let mut by_value: HashMap<(i64, i64), Vec<(NodeIndex, NodeGeo)>> =
+            HashMap::new();
+        for idx in self.graph.node_indices() {
+            if let Some(set) = self.pitch_class_set(idx) {
+                let value = set.dft(settings.coefficient) * settings.scale;
+                // Rounded, so coefficients equal up to float error share a
+                // key.
+                let key = (
+                    (value.x * 1e6).round() as i64,
+                    (value.y * 1e6).round() as i64,
+                );
+                by_value.entry(key).or_default().push((idx, value));
+            }
+        }
+        let mut positions: Vec<(NodeIndex, NodeGeo)> = by_value
+            .into_iter()
+            .flat_map(|(_, mut shared)| {
+                shared.sort_by_key(|(idx, _)| *idx);
+                let count = shared.len();
+                shared.into_iter().enumerate().map(move |(i, (idx, p))| {
+                    if count == 1 {
+                        return (idx, p);
+                    }
+                    let angle = 2.0 * PI * i as f64 / count as f64;
+                    let offset = Coordinate {
+                        x: angle.cos(),
+                        y: angle.sin(),
+                    };
+                    (idx, p + offset * settings.spread)
+                })
+            })
+            .collect();
+        positions.sort_by_key(|(idx, _)| *idx);
+        return positions;
+    }
+
+    pub fn chroma_layout_with(&mut self, settings: &ChromaSettings) {
+        let positions = self.chroma_positions(settings);
+        if settings.anchor {
+            self.anchors = positions.iter().copied().collect();
+        }
+        for (idx, position) in positions {
+            self.place_node(idx, position);
+        }
+    }
+
+    pub fn set_anchors(&mut self, anchors: OrdMap<NodeIndex, NodeGeo>) {
+        self.anchors = anchors;
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Places each node with a pitch-class set at one of its Fourier
+    /// coefficients, as one undo step.
+    pub fn chroma_layout(&mut self, settings: JsValue) -> Result<(), JsValue> {
+        let settings: ChromaSettings = settings_from_js(&settings)?;
+        self.checkpoint_with_label("chroma layout");
+        self.chroma_layout_with(&settings);
+        return Ok(());
+    }
+
+    pub fn clear_anchors(&mut self) {
+        self.anchors = OrdMap::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::NodeData;
+    use crate::optimize::objective::LayoutObjective;
+    use crate::test_utils::{edge, graph, id, node};
+
+    #[test]
+    fn test_chroma_coordinates() {
+        let major = PitchClassSet::parse("0,2,4,5,7,9,11").unwrap();
+        assert_eq!(major.0, 0b1010_1011_0101);
+        assert_eq!(major.pitch_classes(), vec![0, 2, 4, 5, 7, 9, 11]);
+        // The whole-tone scale has no fifths component at all.
+        let whole_tone = PitchClassSet::parse("0,2,4,6,8,10").unwrap();
+        let f5 = whole_tone.dft(5);
+        assert!(f5.x.abs() < 1e-9 && f5.y.abs() < 1e-9);
+
+        let pitched = |name: &str, chroma: Option<Vec<i64>>| NodeData {
+            chroma: chroma.map(PitchClassSet::from_pitch_classes),
+            ..node(name, 0.0, 0.0)
+        };
+        let mut layout = graph(
+            vec![
+                pitched("0,2,4,5,7,9,11", None),
+                pitched("g major", Some(vec![7, 9, 11, 0, 2, 4, 6])),
+                pitched("0,4,8", None),
+                pitched("1,5,9", None),
+                pitched("unpitched", None),
+            ],
+            vec![edge("0,2,4,5,7,9,11", "g major")],
+        );
+        let settings = ChromaSettings::default();
+        layout.chroma_layout_with(&settings);
+        let at = |layout: &GraphLayout, name: &str| {
+            let idx = layout.node_index(&id(name));
+            *layout.node_geo(idx.unwrap()).unwrap()
+        };
+        // Transposing by a fifth turns the 5th coefficient by a twelfth.
+        let (c, g) = (at(&layout, "0,2,4,5,7,9,11"), at(&layout, "g major"));
+        let turn = g.y.atan2(g.x) - c.y.atan2(c.x);
+        assert!((turn.rem_euclid(2.0 * PI) - 2.0 * PI / 12.0).abs() < 1e-9);
+        // Both augmented triads have a zero 5th coefficient, so they're
+        // spread apart around the origin.
+        let (a, b) = (at(&layout, "0,4,8"), at(&layout, "1,5,9"));
+        assert!((a.x - b.x).abs() + (a.y - b.y).abs() > settings.spread);
+        assert_eq!(at(&layout, "unpitched"), Coordinate { x: 0.0, y: 0.0 });
+
+        // Anchored nodes are pulled back towards their coefficient.
+        let idx = layout.node_index(&id("g major")).unwrap();
+        let objective = LayoutObjective {
+            anchor: 1.0,
+            ..LayoutObjective::default()
+        };
+        let away = g + Coordinate { x: 30.0, y: 0.0 };
+        let cost = |layout: &GraphLayout, p| {
+            layout.objective_terms_at(&objective, idx, p).anchor
+        };
+        assert_eq!(cost(&layout, g), 0.0);
+        assert!((cost(&layout, away) - 900.0).abs() < 1e-9);
+        layout.clear_anchors();
+        assert_eq!(cost(&layout, away), 0.0);
+    }
+}
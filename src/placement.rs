@@ -0,0 +1,270 @@
+use crate::geometry::NodeGeo;
+use crate::layout::GraphLayout;
+use crate::spectral::SpectralSettings;
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use petgraph::graph::NodeIndex;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PlacementStrategy {
+    /// Uniformly at random in a square.
+    Random,
+    /// Evenly spaced around a circle, in node order.
+    Circle,
+    /// Laplacian eigenvectors, scaled to fit the square.
+    Spectral,
+}
+
+impl Default for PlacementStrategy {
+    fn default() -> Self {
+        return PlacementStrategy::Circle;
+    }
+}
+
+/// How nodes without coordinates are placed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlacementSettings {
+    pub strategy: PlacementStrategy,
+    /// Side of the square, or diameter of the circle, nodes are placed in.
+    pub size: f64,
+    pub seed: u64,
+}
+
+impl Default for PlacementSettings {
+    fn default() -> Self {
+        return PlacementSettings {
+            strategy: PlacementStrategy::default(),
+            size: 100.0,
+            seed: 0,
+        };
+    }
+}
+
+/// Scales points to fit a `size` square centered on `center`, keeping their
+/// aspect ratio. Returns `None` if they're all in the same place.
+fn fit(
+    points: &[(NodeIndex, NodeGeo)],
+    center: NodeGeo,
+    size: f64,
+) -> Option<Vec<(NodeIndex, NodeGeo)>> {
+    let xs = points.iter().map(|(_, p)| p.x);
+    let ys = points.iter().map(|(_, p)| p.y);
+    let (min_x, max_x) = (
+        xs.clone().fold(f64::MAX, f64::min),
+        xs.fold(f64::MIN, f64::max),
+    );
+    let (min_y, max_y) = (
+        ys.clone().fold(f64::MAX, f64::min),
+        ys.fold(f64::MIN, f64::max),
+    );
+    let extent = (max_x - min_x).max(max_y - min_y);
+    if !(extent > 0.0) {
+        return None;
+    }
+    let middle = Coordinate {
+        x: (min_x + max_x) / 2.0,
+        y: (min_y + max_y) / 2.0,
+    };
+    return Some(
+        points
+            .iter()
+            .map(|(idx, p)| (*idx, center + (*p - middle) * (size / extent)))
+            .collect(),
+    );
+}
+
+impl GraphLayout {
+    /// Where to center newly placed nodes: the centroid of every other
+    /// node, or the origin.
+    pub fn placement_center(&self, nodes: &HashSet<NodeIndex>) -> NodeGeo {
+        let placed: Vec<NodeGeo> = self
+            .node_positions
+            .iter()
+            .filter(|(idx, _)| !nodes.contains(idx))
+            .map(|(_, p)| *p)
+            .collect();
+        if placed.is_empty() {
+            return Coordinate { x: 0.0, y: 0.0 };
+        }
+        let sum = placed
+            .iter()
+            .fold(Coordinate { x: 0.0, y: 0.0 }, |sum, p| sum + *p);
+        return sum / placed.len() as f64;
+    }
+
+    fn circle_positions(
+        &self,
+        nodes: &[NodeIndex],
+        center: NodeGeo,
+        size: f64,
+    ) -> Vec<(NodeIndex, NodeGeo)> {
+        if nodes.len() == 1 {
+            return vec![(nodes[0], center)];
+        }
+        let step = 2.0 * PI / nodes.len() as f64;
+        return nodes
+            .iter()
+            .enumerate()
+            .map(|(i, idx)| {
+                let angle = i as f64 * step;
+                let offset = Coordinate {
+                    x: angle.cos(),
+                    y: angle.sin(),
+                };
+                (*idx, center + offset * (size / 2.0))
+            })
+            .collect();
+    }
+
+    pub fn initial_positions(
+        &self,
+        nodes: &[NodeIndex],
+        settings: &PlacementSettings,
+    ) -> Vec<(NodeIndex, NodeGeo)> {
+        let set: HashSet<NodeIndex> = nodes.iter().copied().collect();
+        let center = self.placement_center(&set);
+        let size = settings.size;
+        return match settings.strategy {
+            PlacementStrategy::Random => {
+                let mut rng = SmallRng::seed_from_u64(settings.seed);
+                nodes
+                    .iter()
+                    .map(|idx| {
+                        let offset = Coordinate {
+                            x: rng.gen_range(-0.5, 0.5),
+                            y: rng.gen_range(-0.5, 0.5),
+                        };
+                        (*idx, center + offset * size)
+                    })
+                    .collect()
+            }
+            PlacementStrategy::Circle => {
+                self.circle_positions(nodes, center, size)
+            }
+            PlacementStrategy::Spectral => {
+                let spectral: Vec<(NodeIndex, NodeGeo)> = self
+                    .spectral_positions(&SpectralSettings {
+                        seed: settings.seed,
+                        ..SpectralSettings::default()
+                    })
+                    .0
+                    .into_iter()
+                    .filter(|(idx, _)| set.contains(idx))
+                    .collect();
+                fit(&spectral, center, size).unwrap_or_else(|| {
+                    self.circle_positions(nodes, center, size)
+                })
+            }
+        };
+    }
+
+    pub fn place_nodes_with(
+        &mut self,
+        nodes: &[NodeIndex],
+        settings: &PlacementSettings,
+    ) {
+        for (idx, position) in self.initial_positions(nodes, settings) {
+            self.place_node(idx, position);
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Replaces every node's position using a placement strategy, as one
+    /// undo step.
+    pub fn place_nodes(&mut self, settings: JsValue) -> Result<(), JsValue> {
+        let settings: PlacementSettings = settings_from_js(&settings)?;
+        let nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        self.checkpoint_with_label("place nodes");
+        self.place_nodes_with(&nodes, &settings);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{LayoutOptions, NodeData};
+    use crate::test_utils::{edge, graph_with, id, node, unplaced};
+
+    #[test]
+    fn test_places_missing_nodes_apart() {
+        let ids = vec!["a", "b", "c", "d", "e"];
+        for strategy in vec![
+            PlacementStrategy::Random,
+            PlacementStrategy::Circle,
+            PlacementStrategy::Spectral,
+        ] {
+            let layout = graph_with(
+                vec![
+                    node("a", 500., 500.),
+                    unplaced("b"),
+                    unplaced("c"),
+                    unplaced("d"),
+                    unplaced("e"),
+                ],
+                ids.windows(2).map(|w| edge(w[0], w[1])).collect(),
+                LayoutOptions {
+                    placement: PlacementSettings {
+                        strategy: strategy,
+                        ..PlacementSettings::default()
+                    },
+                    ..LayoutOptions::default()
+                },
+            );
+            let positions: Vec<NodeGeo> =
+                layout.node_positions.values().copied().collect();
+            assert_eq!(positions[0], Coordinate { x: 500., y: 500. });
+            for (i, p) in positions.iter().enumerate().skip(1) {
+                assert!((p.x - 500.).abs() <= 50. && (p.y - 500.).abs() <= 50.);
+                for q in positions.iter().skip(i + 1) {
+                    assert!(p != q, "{:?} stacked nodes", strategy);
+                }
+            }
+            assert!(layout.sum_of_charges().is_finite());
+        }
+    }
+    #[test]
+    fn test_keeps_a_partly_given_position() {
+        for strategy in vec![
+            PlacementStrategy::Random,
+            PlacementStrategy::Circle,
+            PlacementStrategy::Spectral,
+        ] {
+            let layout = graph_with(
+                vec![
+                    node("a", 500., 500.),
+                    NodeData {
+                        x: Some(480.),
+                        ..unplaced("b")
+                    },
+                    NodeData {
+                        y: Some(520.),
+                        ..unplaced("c")
+                    },
+                ],
+                vec![edge("a", "b"), edge("b", "c")],
+                LayoutOptions {
+                    placement: PlacementSettings {
+                        strategy: strategy,
+                        ..PlacementSettings::default()
+                    },
+                    ..LayoutOptions::default()
+                },
+            );
+            let b = layout.node_index(&id("b")).unwrap();
+            let c = layout.node_index(&id("c")).unwrap();
+            assert_eq!(layout.node_positions[&b].x, 480., "{:?}", strategy);
+            assert_eq!(layout.node_positions[&c].y, 520., "{:?}", strategy);
+        }
+    }
+}
@@ -0,0 +1,1140 @@
+use crate::geometry::grid::{grid_spacing, snap};
+use crate::geometry::{EdgeShape, NodeGeo};
+use crate::layout::{GraphLayout, NodeDataId};
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+
+type Edge = (usize, usize);
+
+/// A combinatorial embedding: each node's neighbours in clockwise order.
+#[derive(Clone, Debug)]
+pub struct Embedding {
+    rotation: Vec<Vec<usize>>,
+}
+
+impl Embedding {
+    fn new(n: usize) -> Self {
+        return Embedding {
+            rotation: vec![vec![]; n],
+        };
+    }
+
+    fn len(&self) -> usize {
+        return self.rotation.len();
+    }
+
+    pub fn neighbors(&self, v: usize) -> &[usize] {
+        return &self.rotation[v];
+    }
+
+    pub fn has_edge(&self, v: usize, w: usize) -> bool {
+        return self.rotation[v].contains(&w);
+    }
+
+    fn position(&self, v: usize, w: usize) -> usize {
+        return self.rotation[v].iter().position(|u| *u == w).unwrap();
+    }
+
+    fn add_first(&mut self, v: usize, w: usize) {
+        self.rotation[v].insert(0, w);
+    }
+
+    /// Adds `w` to `v`'s neighbours right after `reference`, clockwise, or
+    /// last without one.
+    fn add_cw(&mut self, v: usize, w: usize, reference: Option<usize>) {
+        let at = match reference {
+            Some(r) => self.position(v, r) + 1,
+            None => self.rotation[v].len(),
+        };
+        self.rotation[v].insert(at, w);
+    }
+
+    /// Adds `w` to `v`'s neighbours right before `reference`.
+    fn add_ccw(&mut self, v: usize, w: usize, reference: usize) {
+        let at = self.position(v, reference);
+        self.rotation[v].insert(at, w);
+    }
+
+    /// The neighbour of `v` before `w`, clockwise.
+    fn ccw(&self, v: usize, w: usize) -> usize {
+        let around = &self.rotation[v];
+        let i = self.position(v, w);
+        return around[(i + around.len() - 1) % around.len()];
+    }
+
+    /// The half-edge after `(v, w)` along the face on its left.
+    fn next_face_half_edge(&self, v: usize, w: usize) -> Edge {
+        return (w, self.ccw(w, v));
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Interval {
+    low: Option<Edge>,
+    high: Option<Edge>,
+}
+
+impl Interval {
+    fn is_empty(&self) -> bool {
+        return self.low.is_none() && self.high.is_none();
+    }
+}
+
+/// Two sets of return edges that have to go on opposite sides. Each pair
+/// gets an id so the stack's bottom can be recognised after it has been
+/// popped and pushed back.
+#[derive(Clone, Copy, Default)]
+struct ConflictPair {
+    id: usize,
+    left: Interval,
+    right: Interval,
+}
+
+impl ConflictPair {
+    fn swap(&mut self) {
+        std::mem::swap(&mut self.left, &mut self.right);
+    }
+}
+
+/// The left-right planarity test (de Fraysseix and Rosenstiehl, as
+/// described by Brandes), which builds an embedding as it goes.
+struct LeftRight<'a> {
+    adjacency: &'a [Vec<usize>],
+    height: Vec<Option<usize>>,
+    parent_edge: Vec<Option<Edge>>,
+    oriented: Vec<Vec<usize>>,
+    lowpt: HashMap<Edge, usize>,
+    lowpt2: HashMap<Edge, usize>,
+    nesting_depth: HashMap<Edge, isize>,
+    ordered: Vec<Vec<usize>>,
+    reference: HashMap<Edge, Edge>,
+    side: HashMap<Edge, isize>,
+    stack: Vec<ConflictPair>,
+    pairs: usize,
+    stack_bottom: HashMap<Edge, Option<usize>>,
+    lowpt_edge: HashMap<Edge, Edge>,
+    left_ref: Vec<usize>,
+    right_ref: Vec<usize>,
+    embedding: Embedding,
+}
+
+impl<'a> LeftRight<'a> {
+    fn new(adjacency: &'a [Vec<usize>]) -> Self {
+        let n = adjacency.len();
+        return LeftRight {
+            adjacency: adjacency,
+            height: vec![None; n],
+            parent_edge: vec![None; n],
+            oriented: vec![vec![]; n],
+            lowpt: HashMap::new(),
+            lowpt2: HashMap::new(),
+            nesting_depth: HashMap::new(),
+            ordered: vec![],
+            reference: HashMap::new(),
+            side: HashMap::new(),
+            stack: vec![],
+            pairs: 0,
+            stack_bottom: HashMap::new(),
+            lowpt_edge: HashMap::new(),
+            left_ref: vec![0; n],
+            right_ref: vec![0; n],
+            embedding: Embedding::new(n),
+        };
+    }
+
+    fn run(mut self) -> Option<Embedding> {
+        let n = self.adjacency.len();
+        let m = self.adjacency.iter().map(|a| a.len()).sum::<usize>() / 2;
+        if n > 2 && m > 3 * n - 6 {
+            return None;
+        }
+        // Where each search resumes in a node's neighbours. Every node is
+        // in one search, so the searches can share it.
+        let mut next = vec![0; n];
+        let mut roots = vec![];
+        for v in 0..n {
+            if self.height[v].is_none() {
+                self.height[v] = Some(0);
+                roots.push(v);
+                self.orient(v, &mut next);
+            }
+        }
+        self.order_by_nesting_depth();
+        let mut next = vec![0; n];
+        for v in roots.iter() {
+            if !self.test(*v, &mut next) {
+                return None;
+            }
+        }
+
+        let edges: Vec<Edge> = self.nesting_depth.keys().copied().collect();
+        for e in edges {
+            let sign = self.sign(e);
+            *self.nesting_depth.get_mut(&e).unwrap() *= sign;
+        }
+        self.order_by_nesting_depth();
+        for v in 0..n {
+            let mut previous = None;
+            for w in self.ordered[v].clone() {
+                self.embedding.add_cw(v, w, previous);
+                previous = Some(w);
+            }
+        }
+        let mut next = vec![0; n];
+        for v in roots {
+            self.embed(v, &mut next);
+        }
+        return Some(self.embedding);
+    }
+
+    fn order_by_nesting_depth(&mut self) {
+        let mut ordered = self.oriented.clone();
+        for (v, around) in ordered.iter_mut().enumerate() {
+            around.sort_by_key(|w| self.nesting_depth[&(v, *w)]);
+        }
+        self.ordered = ordered;
+    }
+
+    fn lowpt_of(&self, e: Option<Edge>) -> usize {
+        return self.lowpt[&e.unwrap()];
+    }
+
+    fn conflicting(&self, interval: &Interval, e: Edge) -> bool {
+        return match interval.high {
+            Some(high) => self.lowpt[&high] > self.lowpt[&e],
+            None => false,
+        };
+    }
+
+    fn lowest(&self, pair: &ConflictPair) -> usize {
+        if pair.left.is_empty() {
+            return self.lowpt_of(pair.right.low);
+        }
+        if pair.right.is_empty() {
+            return self.lowpt_of(pair.left.low);
+        }
+        return usize::min(
+            self.lowpt_of(pair.left.low),
+            self.lowpt_of(pair.right.low),
+        );
+    }
+
+    fn new_pair(&mut self) -> ConflictPair {
+        self.pairs += 1;
+        return ConflictPair {
+            id: self.pairs,
+            ..ConflictPair::default()
+        };
+    }
+
+    /// Orients the graph along a depth-first search from `root`, working out
+    /// lowpoints and nesting depths. The search keeps its own stack, and
+    /// `next[v]` is where it resumes in `v`'s neighbours.
+    fn orient(&mut self, root: usize, next: &mut [usize]) {
+        let mut dfs = vec![root];
+        let mut descended: HashSet<Edge> = HashSet::new();
+        while let Some(v) = dfs.pop() {
+            let e = self.parent_edge[v];
+            let height = self.height[v].unwrap();
+            while next[v] < self.adjacency[v].len() {
+                let w = self.adjacency[v][next[v]];
+                let vw = (v, w);
+                if !descended.contains(&vw) {
+                    if self.oriented[v].contains(&w)
+                        || self.oriented[w].contains(&v)
+                    {
+                        next[v] += 1;
+                        continue;
+                    }
+                    self.oriented[v].push(w);
+                    self.lowpt.insert(vw, height);
+                    self.lowpt2.insert(vw, height);
+                    match self.height[w] {
+                        None => {
+                            self.parent_edge[w] = Some(vw);
+                            self.height[w] = Some(height + 1);
+                            descended.insert(vw);
+                            dfs.push(v);
+                            dfs.push(w);
+                            break;
+                        }
+                        Some(h) => {
+                            self.lowpt.insert(vw, h);
+                        }
+                    }
+                }
+                let chordal = (self.lowpt2[&vw] < height) as isize;
+                self.nesting_depth
+                    .insert(vw, 2 * self.lowpt[&vw] as isize + chordal);
+                if let Some(e) = e {
+                    let (low, low2) = (self.lowpt[&vw], self.lowpt2[&vw]);
+                    if low < self.lowpt[&e] {
+                        self.lowpt2.insert(e, usize::min(self.lowpt[&e], low2));
+                        self.lowpt.insert(e, low);
+                    } else if low > self.lowpt[&e] {
+                        self.lowpt2.insert(e, usize::min(self.lowpt2[&e], low));
+                    } else {
+                        self.lowpt2
+                            .insert(e, usize::min(self.lowpt2[&e], low2));
+                    }
+                }
+                next[v] += 1;
+            }
+        }
+    }
+
+    /// Checks the constraints on the sides of the return edges, along the
+    /// ordered depth-first search from `root`, with an explicit stack.
+    fn test(&mut self, root: usize, next: &mut [usize]) -> bool {
+        let mut dfs = vec![root];
+        let mut descended: HashSet<Edge> = HashSet::new();
+        while let Some(v) = dfs.pop() {
+            let e = self.parent_edge[v];
+            let mut finished = true;
+            while next[v] < self.ordered[v].len() {
+                let w = self.ordered[v][next[v]];
+                let ei = (v, w);
+                if !descended.contains(&ei) {
+                    self.stack_bottom
+                        .insert(ei, self.stack.last().map(|p| p.id));
+                    if Some(ei) == self.parent_edge[w] {
+                        descended.insert(ei);
+                        dfs.push(v);
+                        dfs.push(w);
+                        finished = false;
+                        break;
+                    }
+                    self.lowpt_edge.insert(ei, ei);
+                    let mut pair = self.new_pair();
+                    pair.right = Interval {
+                        low: Some(ei),
+                        high: Some(ei),
+                    };
+                    self.stack.push(pair);
+                }
+                if self.lowpt[&ei] < self.height[v].unwrap() {
+                    let e = e.unwrap();
+                    if w == self.ordered[v][0] {
+                        self.lowpt_edge.insert(e, self.lowpt_edge[&ei]);
+                    } else if !self.add_constraints(ei, e) {
+                        return false;
+                    }
+                }
+                next[v] += 1;
+            }
+            if finished {
+                if let Some(e) = e {
+                    self.remove_back_edges(e);
+                }
+            }
+        }
+        return true;
+    }
+
+    fn add_constraints(&mut self, ei: Edge, e: Edge) -> bool {
+        let mut p = self.new_pair();
+        // Merge the return edges of ei into the right interval.
+        while let Some(mut q) = self.stack.pop() {
+            if !q.left.is_empty() {
+                q.swap();
+            }
+            if !q.left.is_empty() {
+                return false;
+            }
+            if self.lowpt_of(q.right.low) > self.lowpt[&e] {
+                if p.right.is_empty() {
+                    p.right = q.right;
+                } else if let (Some(low), Some(high)) =
+                    (p.right.low, q.right.high)
+                {
+                    self.reference.insert(low, high);
+                }
+                p.right.low = q.right.low;
+            } else {
+                self.reference
+                    .insert(q.right.low.unwrap(), self.lowpt_edge[&e]);
+            }
+            if self.stack.last().map(|p| p.id) == self.stack_bottom[&ei] {
+                break;
+            }
+        }
+        // Merge the conflicting return edges of earlier siblings into the
+        // left interval.
+        while let Some(top) = self.stack.last().copied() {
+            if !self.conflicting(&top.left, ei)
+                && !self.conflicting(&top.right, ei)
+            {
+                break;
+            }
+            let mut q = self.stack.pop().unwrap();
+            if self.conflicting(&q.right, ei) {
+                q.swap();
+            }
+            if self.conflicting(&q.right, ei) {
+                return false;
+            }
+            if let (Some(low), Some(high)) = (p.right.low, q.right.high) {
+                self.reference.insert(low, high);
+            }
+            if q.right.low.is_some() {
+                p.right.low = q.right.low;
+            }
+            if p.left.is_empty() {
+                p.left = q.left;
+            } else if let (Some(low), Some(high)) = (p.left.low, q.left.high) {
+                self.reference.insert(low, high);
+            }
+            p.left.low = q.left.low;
+        }
+        if !(p.left.is_empty() && p.right.is_empty()) {
+            self.stack.push(p);
+        }
+        return true;
+    }
+
+    fn remove_back_edges(&mut self, e: Edge) {
+        let u = e.0;
+        let height = self.height[u].unwrap();
+        while let Some(top) = self.stack.last().copied() {
+            if self.lowest(&top) != height {
+                break;
+            }
+            self.stack.pop();
+            if let Some(low) = top.left.low {
+                self.side.insert(low, -1);
+            }
+        }
+        if let Some(mut p) = self.stack.pop() {
+            // Trim the back edges ending at u off both intervals.
+            while let Some(high) = p.left.high.filter(|h| h.1 == u) {
+                p.left.high = self.reference.get(&high).copied();
+            }
+            if let (None, Some(low)) = (p.left.high, p.left.low) {
+                if let Some(right) = p.right.low {
+                    self.reference.insert(low, right);
+                }
+                self.side.insert(low, -1);
+                p.left.low = None;
+            }
+            while let Some(high) = p.right.high.filter(|h| h.1 == u) {
+                p.right.high = self.reference.get(&high).copied();
+            }
+            if let (None, Some(low)) = (p.right.high, p.right.low) {
+                if let Some(left) = p.left.low {
+                    self.reference.insert(low, left);
+                }
+                self.side.insert(low, -1);
+                p.right.low = None;
+            }
+            self.stack.push(p);
+        }
+        // The side of e is the side of a highest return edge.
+        if self.lowpt[&e] < height {
+            if let Some(top) = self.stack.last().copied() {
+                let (hl, hr) = (top.left.high, top.right.high);
+                let highest = match (hl, hr) {
+                    (Some(l), Some(r)) if self.lowpt[&l] > self.lowpt[&r] => hl,
+                    (Some(_), None) => hl,
+                    _ => hr,
+                };
+                if let Some(highest) = highest {
+                    self.reference.insert(e, highest);
+                }
+            }
+        }
+    }
+
+    /// The side of `e`, relative to the edges its reference chain leads
+    /// to. The chain is followed in a loop, and dropped, as it's resolved.
+    fn sign(&mut self, e: Edge) -> isize {
+        let mut chain = vec![e];
+        while let Some(reference) = self.reference.remove(chain.last().unwrap())
+        {
+            chain.push(reference);
+        }
+        let mut sign = *self.side.get(chain.last().unwrap()).unwrap_or(&1);
+        for e in chain.into_iter().rev().skip(1) {
+            let side = self.side.entry(e).or_insert(1);
+            *side *= sign;
+            sign = *side;
+        }
+        return sign;
+    }
+
+    fn embed(&mut self, root: usize, next: &mut [usize]) {
+        let mut dfs = vec![root];
+        while let Some(v) = dfs.pop() {
+            while next[v] < self.ordered[v].len() {
+                let w = self.ordered[v][next[v]];
+                next[v] += 1;
+                let ei = (v, w);
+                if Some(ei) == self.parent_edge[w] {
+                    self.embedding.add_first(w, v);
+                    self.left_ref[v] = w;
+                    self.right_ref[v] = w;
+                    dfs.push(v);
+                    dfs.push(w);
+                    break;
+                } else if self.side.get(&ei).copied().unwrap_or(1) == 1 {
+                    self.embedding.add_cw(w, v, Some(self.right_ref[w]));
+                } else {
+                    self.embedding.add_ccw(w, v, self.left_ref[w]);
+                    self.left_ref[w] = v;
+                }
+            }
+        }
+    }
+}
+
+/// A planar embedding of the simple graph with these neighbour lists, or
+/// None if it isn't planar.
+pub fn planar_embedding(adjacency: &[Vec<usize>]) -> Option<Embedding> {
+    return LeftRight::new(adjacency).run();
+}
+
+/// The most edges `kuratowski_edges` looks at, as it runs the planarity
+/// test once per edge.
+pub const KURATOWSKI_MAX_EDGES: usize = 500;
+
+/// The edges of a subdivision of K5 or K3,3 in a non-planar graph: what's
+/// left after dropping every edge the graph stays non-planar without. None
+/// if the graph is planar or has more than `KURATOWSKI_MAX_EDGES` edges.
+pub fn kuratowski_edges(adjacency: &[Vec<usize>]) -> Option<Vec<Edge>> {
+    let edges: Vec<Edge> = (0..adjacency.len())
+        .flat_map(|v| adjacency[v].iter().map(move |w| (v, *w)))
+        .filter(|(v, w)| v < w)
+        .collect();
+    if edges.len() > KURATOWSKI_MAX_EDGES
+        || planar_embedding(adjacency).is_some()
+    {
+        return None;
+    }
+    let mut kept: Vec<Vec<usize>> = adjacency.to_vec();
+    for (v, w) in edges {
+        kept[v].retain(|u| *u != w);
+        kept[w].retain(|u| *u != v);
+        if planar_embedding(&kept).is_some() {
+            kept[v].push(w);
+            kept[w].push(v);
+        }
+    }
+    return Some(
+        (0..kept.len())
+            .flat_map(|v| kept[v].iter().map(move |w| (v, *w)))
+            .filter(|(v, w)| v < w)
+            .collect(),
+    );
+}
+
+/// Walks the face left of `(v, w)`, adding an edge past any node it meets
+/// twice so the face becomes a simple cycle. Returns the face, or nothing
+/// if it has been walked already.
+fn make_biconnected(
+    embedding: &mut Embedding,
+    start: usize,
+    out: usize,
+    counted: &mut HashSet<Edge>,
+) -> Vec<usize> {
+    if !counted.insert((start, out)) {
+        return vec![];
+    }
+    let mut face = vec![start];
+    let mut on_face: HashSet<usize> = face.iter().copied().collect();
+    let (mut v1, mut v2) = (start, out);
+    let (_, mut v3) = embedding.next_face_half_edge(v1, v2);
+    while v2 != start || v3 != out {
+        if on_face.contains(&v2) {
+            embedding.add_cw(v1, v3, Some(v2));
+            embedding.add_ccw(v3, v1, v2);
+            counted.insert((v2, v3));
+            counted.insert((v3, v1));
+            v2 = v1;
+        } else {
+            on_face.insert(v2);
+            face.push(v2);
+        }
+        v1 = v2;
+        let next = embedding.next_face_half_edge(v2, v3);
+        v2 = next.0;
+        v3 = next.1;
+        counted.insert((v1, v2));
+    }
+    return face;
+}
+
+/// Splits the face left of `(v1, v2)` into triangles.
+fn triangulate_face(embedding: &mut Embedding, v1: usize, v2: usize) {
+    let (mut v1, mut v2) = (v1, v2);
+    let (_, mut v3) = embedding.next_face_half_edge(v1, v2);
+    let (_, mut v4) = embedding.next_face_half_edge(v2, v3);
+    if v1 == v2 || v1 == v3 {
+        return;
+    }
+    while v1 != v4 {
+        if embedding.has_edge(v1, v3) {
+            v1 = v2;
+            v2 = v3;
+            v3 = v4;
+        } else {
+            embedding.add_cw(v1, v3, Some(v2));
+            embedding.add_ccw(v3, v1, v2);
+            v2 = v3;
+            v3 = v4;
+        }
+        v4 = embedding.next_face_half_edge(v2, v3).1;
+    }
+}
+
+/// Adds edges until every face is a triangle. Returns the outer triangle,
+/// taken from the largest face before triangulating. Needs three nodes.
+fn triangulate(embedding: &mut Embedding) -> [usize; 3] {
+    let n = embedding.len();
+    // Join the components in a chain.
+    let mut component = vec![usize::MAX; n];
+    let mut firsts = vec![];
+    for v in 0..n {
+        if component[v] != usize::MAX {
+            continue;
+        }
+        firsts.push(v);
+        let mut stack = vec![v];
+        component[v] = v;
+        while let Some(u) = stack.pop() {
+            for w in embedding.neighbors(u).to_vec() {
+                if component[w] == usize::MAX {
+                    component[w] = v;
+                    stack.push(w);
+                }
+            }
+        }
+    }
+    for pair in firsts.windows(2) {
+        embedding.add_first(pair[0], pair[1]);
+        embedding.add_first(pair[1], pair[0]);
+    }
+
+    let mut counted = HashSet::new();
+    let mut faces: Vec<Vec<usize>> = vec![];
+    for v in 0..n {
+        for w in embedding.neighbors(v).to_vec() {
+            let face = make_biconnected(embedding, v, w, &mut counted);
+            if !face.is_empty() {
+                faces.push(face);
+            }
+        }
+    }
+    let outer = faces.iter().max_by_key(|face| face.len()).cloned().unwrap();
+    for face in faces.iter() {
+        triangulate_face(embedding, face[0], face[1]);
+    }
+    let (v1, v2) = (outer[0], outer[1]);
+    return [v1, v2, embedding.next_face_half_edge(v1, v2).1];
+}
+
+/// An order of the nodes of a triangulation starting with the outer
+/// triangle's `v1` and `v2` and ending with its third node, in which every
+/// node after the first two has at least two earlier neighbours, all on the
+/// outer boundary of the earlier ones, one after another. Built backwards,
+/// peeling off a boundary node without chords at each step.
+fn canonical_ordering(embedding: &Embedding, outer: [usize; 3]) -> Vec<usize> {
+    let n = embedding.len();
+    let [v1, v2, vn] = outer;
+    let mut present = vec![true; n];
+    let mut on_boundary = vec![false; n];
+    let mut boundary = vec![v1, vn, v2];
+    for v in boundary.iter() {
+        on_boundary[*v] = true;
+    }
+    let mut peeled = vec![];
+    while peeled.len() + 2 < n {
+        let i = (1..boundary.len() - 1)
+            .find(|i| {
+                let v = boundary[*i];
+                embedding
+                    .neighbors(v)
+                    .iter()
+                    .filter(|w| on_boundary[**w])
+                    .count()
+                    == 2
+            })
+            .unwrap();
+        let v = boundary[i];
+        let (a, b) = (boundary[i - 1], boundary[i + 1]);
+        // Of the two arcs around v between a and b, the one facing inwards
+        // holds the nodes that join the boundary.
+        let around = embedding.neighbors(v);
+        let (ia, ib) = (embedding.position(v, a), embedding.position(v, b));
+        let arc = |from: usize, to: usize| -> Vec<usize> {
+            let mut nodes = vec![];
+            let mut j = (from + 1) % around.len();
+            while j != to {
+                if present[around[j]] {
+                    nodes.push(around[j]);
+                }
+                j = (j + 1) % around.len();
+            }
+            nodes
+        };
+        let mut inner = arc(ia, ib);
+        if inner.is_empty() {
+            inner = arc(ib, ia);
+            inner.reverse();
+        }
+        present[v] = false;
+        on_boundary[v] = false;
+        for w in inner.iter() {
+            on_boundary[*w] = true;
+        }
+        boundary.splice(i..=i, inner);
+        peeled.push(v);
+    }
+    peeled.push(v2);
+    peeled.push(v1);
+    peeled.reverse();
+    return peeled;
+}
+
+/// Straight-line grid positions without crossings for the planar graph with
+/// these neighbour lists, by de Fraysseix, Pach and Pollack's shifting
+/// method on a triangulation of it. The grid is `2n - 4` wide and `n - 2`
+/// high, with y growing upwards. None if the graph isn't planar.
+pub fn planar_grid_positions(
+    adjacency: &[Vec<usize>],
+) -> Option<Vec<(i64, i64)>> {
+    let mut embedding = planar_embedding(adjacency)?;
+    let n = adjacency.len();
+    if n < 3 {
+        return Some((0..n).map(|v| (2 * v as i64, 0)).collect());
+    }
+    let outer = triangulate(&mut embedding);
+    let order = canonical_ordering(&embedding, outer);
+
+    let (mut x, mut y) = (vec![0i64; n], vec![0i64; n]);
+    let (v1, v2, v3) = (order[0], order[1], order[2]);
+    x[v2] = 2;
+    x[v3] = 1;
+    y[v3] = 1;
+    let mut contour = vec![v1, v3, v2];
+    // The nodes that move whenever each contour node does.
+    let mut dependents: Vec<Vec<usize>> = (0..n).map(|v| vec![v]).collect();
+    for vk in order.iter().skip(3).copied() {
+        let neighbors: HashSet<usize> =
+            embedding.neighbors(vk).iter().copied().collect();
+        let touching: Vec<usize> = (0..contour.len())
+            .filter(|j| neighbors.contains(&contour[*j]))
+            .collect();
+        let (p, q) = (touching[0], *touching.last().unwrap());
+        for (j, w) in contour.iter().enumerate().skip(p + 1) {
+            let shift = if j < q { 1 } else { 2 };
+            for u in dependents[*w].iter() {
+                x[*u] += shift;
+            }
+        }
+        let (wp, wq) = (contour[p], contour[q]);
+        x[vk] = (x[wp] + x[wq] + y[wq] - y[wp]) / 2;
+        y[vk] = (x[wq] - x[wp] + y[wp] + y[wq]) / 2;
+        let covered: Vec<usize> = contour[p + 1..q]
+            .iter()
+            .flat_map(|w| dependents[*w].clone())
+            .collect();
+        dependents[vk].extend(covered);
+        contour.splice(p + 1..q, vec![vk]);
+    }
+    return Some(x.into_iter().zip(y).collect());
+}
+
+/// The kind of Kuratowski graph a non-planar graph contains a subdivision
+/// of.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KuratowskiKind {
+    K5,
+    #[serde(rename = "K3,3")]
+    K33,
+}
+
+/// A subdivision of K5 or K3,3 in the graph, showing why it isn't planar.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Kuratowski {
+    pub kind: KuratowskiKind,
+    /// The branch nodes, where the subdivided paths meet.
+    pub branches: Vec<NodeDataId>,
+    pub edges: Vec<(NodeDataId, NodeDataId)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Planarity {
+    pub planar: bool,
+    pub kuratowski: Option<Kuratowski>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlanarSettings {
+    /// Distance between grid lines. In grid mode it's rounded to a multiple
+    /// of the layout grid's spacing.
+    pub spacing: f64,
+}
+
+impl Default for PlanarSettings {
+    fn default() -> Self {
+        return PlanarSettings { spacing: 40.0 };
+    }
+}
+
+impl GraphLayout {
+    /// A subdivision of K5 or K3,3 in the graph, or None if it's planar or
+    /// too big to look for one in; see `kuratowski_edges`.
+    pub fn kuratowski_subgraph(&self) -> Option<Kuratowski> {
+        let nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        let edges = kuratowski_edges(&self.adjacency(&nodes))?;
+        let mut degree: HashMap<usize, usize> = HashMap::new();
+        for (v, w) in edges.iter() {
+            *degree.entry(*v).or_insert(0) += 1;
+            *degree.entry(*w).or_insert(0) += 1;
+        }
+        let mut branches: Vec<usize> = degree
+            .into_iter()
+            .filter(|(_, d)| *d > 2)
+            .map(|(v, _)| v)
+            .collect();
+        branches.sort();
+        let id = |v: usize| self.node_id(nodes[v]).unwrap();
+        return Some(Kuratowski {
+            kind: if branches.len() == 5 {
+                KuratowskiKind::K5
+            } else {
+                KuratowskiKind::K33
+            },
+            branches: branches.into_iter().map(id).collect(),
+            edges: edges.into_iter().map(|(v, w)| (id(v), id(w))).collect(),
+        });
+    }
+
+    /// Whether the graph can be drawn without any crossings.
+    pub fn is_planar(&self) -> bool {
+        let nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        return planar_embedding(&self.adjacency(&nodes)).is_some();
+    }
+
+    /// Integer grid positions drawing the graph with straight edges and no
+    /// crossings, or None if it isn't planar.
+    pub fn planar_grid(&self) -> Option<Vec<(NodeIndex, i64, i64)>> {
+        let nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        let positions = planar_grid_positions(&self.adjacency(&nodes))?;
+        return Some(
+            nodes
+                .into_iter()
+                .zip(positions)
+                .map(|(idx, (x, y))| (idx, x, y))
+                .collect(),
+        );
+    }
+
+    /// Loads `planar_grid` into the layout, centred where the graph was,
+    /// with straight edges. Returns false, changing nothing, if the graph
+    /// isn't planar.
+    pub fn planar_layout_with(&mut self, settings: &PlanarSettings) -> bool {
+        return match self.planar_grid() {
+            Some(grid) => {
+                self.apply_planar_grid(grid, settings);
+                true
+            }
+            None => false,
+        };
+    }
+
+    /// Moves the nodes to `planar_grid`'s positions, centred where the
+    /// graph was, and straightens the edges.
+    pub fn apply_planar_grid(
+        &mut self,
+        grid: Vec<(NodeIndex, i64, i64)>,
+        settings: &PlanarSettings,
+    ) {
+        let mut center = self.placement_center(&HashSet::new());
+        let mut spacing = settings.spacing;
+        if let Some(grid_power) = self.grid_power() {
+            let unit = grid_spacing(grid_power);
+            spacing = unit * (spacing / unit).round().max(1.0);
+            center = snap(center, grid_power);
+        }
+        let (width, height) = grid.iter().fold((0, 0), |(w, h), (_, x, y)| {
+            (i64::max(w, *x), i64::max(h, *y))
+        });
+        for (idx, x, y) in grid {
+            let position: NodeGeo = Coordinate {
+                x: center.x + (x - width / 2) as f64 * spacing,
+                y: center.y + (height / 2 - y) as f64 * spacing,
+            };
+            self.set_node_geo(idx, position);
+        }
+        let segments: Vec<_> = self.segment_edge_indices().collect();
+        for idx in segments {
+            self.set_edge_shape(idx, EdgeShape::Straight);
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Whether the graph is planar and, if not and `kuratowski` is set, a
+    /// subdivision of K5 or K3,3 in it:
+    /// `{ planar, kuratowski: { kind, branches, edges } }`. Finding the
+    /// subdivision is slow, so it's left out past `KURATOWSKI_MAX_EDGES`.
+    pub fn planarity(&self, kuratowski: bool) -> JsValue {
+        let planar = self.is_planar();
+        return JsValue::from_serde(&Planarity {
+            planar: planar,
+            kuratowski: if planar || !kuratowski {
+                None
+            } else {
+                self.kuratowski_subgraph()
+            },
+        })
+        .unwrap();
+    }
+
+    /// Draws a planar graph with straight edges on a grid without any
+    /// crossings, as one undo step. Returns false, changing nothing, if the
+    /// graph isn't planar.
+    pub fn planar_layout(
+        &mut self,
+        settings: JsValue,
+    ) -> Result<bool, JsValue> {
+        let settings: PlanarSettings = settings_from_js(&settings)?;
+        let grid = match self.planar_grid() {
+            Some(grid) => grid,
+            None => return Ok(false),
+        };
+        self.checkpoint_with_label("planar layout");
+        self.apply_planar_grid(grid, &settings);
+        return Ok(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intersections::segments_intersect;
+    use crate::test_utils::{edge, graph, node};
+    use geo::Line;
+
+    fn adjacency(n: usize, edges: &[Edge]) -> Vec<Vec<usize>> {
+        let mut adjacency = vec![vec![]; n];
+        for (v, w) in edges.iter() {
+            adjacency[*v].push(*w);
+            adjacency[*w].push(*v);
+        }
+        return adjacency;
+    }
+
+    /// Every face of the embedding, as the nodes around it.
+    fn faces(embedding: &Embedding) -> Vec<Vec<usize>> {
+        let mut visited = HashSet::new();
+        let mut faces = vec![];
+        for v in 0..embedding.len() {
+            for w in embedding.neighbors(v).iter() {
+                let mut half_edge = (v, *w);
+                let mut face = vec![];
+                while visited.insert(half_edge) {
+                    face.push(half_edge.0);
+                    half_edge =
+                        embedding.next_face_half_edge(half_edge.0, half_edge.1);
+                }
+                if !face.is_empty() {
+                    faces.push(face);
+                }
+            }
+        }
+        return faces;
+    }
+
+    fn assert_planar_drawing(adjacency: &[Vec<usize>]) {
+        let positions = planar_grid_positions(adjacency).unwrap();
+        let n = adjacency.len() as i64;
+        let distinct: HashSet<(i64, i64)> = positions.iter().copied().collect();
+        assert_eq!(distinct.len(), positions.len());
+        assert!(positions
+            .iter()
+            .all(|(x, y)| *x >= 0 && *x <= 2 * n && *y >= 0 && *y <= n));
+        let point = |v: usize| Coordinate {
+            x: positions[v].0 as f64,
+            y: positions[v].1 as f64,
+        };
+        let edges: Vec<Edge> = (0..adjacency.len())
+            .flat_map(|v| adjacency[v].iter().map(move |w| (v, *w)))
+            .filter(|(v, w)| v < w)
+            .collect();
+        for (i, (a, b)) in edges.iter().enumerate() {
+            // No node sits on an edge it isn't an end of.
+            for v in 0..adjacency.len() {
+                if v != *a && v != *b {
+                    let (p, q, r) = (point(*a), point(*b), point(v));
+                    let cross =
+                        (q.x - p.x) * (r.y - p.y) - (q.y - p.y) * (r.x - p.x);
+                    let within = (r.x - p.x) * (r.x - q.x) <= 0.0
+                        && (r.y - p.y) * (r.y - q.y) <= 0.0;
+                    assert!(cross != 0.0 || !within);
+                }
+            }
+            for (c, d) in edges.iter().skip(i + 1) {
+                if a == c || a == d || b == c || b == d {
+                    continue;
+                }
+                assert!(!segments_intersect(
+                    &Line::new(point(*a), point(*b)),
+                    &Line::new(point(*c), point(*d)),
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_planarity_and_grid_drawing() {
+        let complete = |n: usize| {
+            let edges: Vec<Edge> = (0..n)
+                .flat_map(|v| (v + 1..n).map(move |w| (v, w)))
+                .collect();
+            adjacency(n, &edges)
+        };
+        assert!(planar_embedding(&complete(4)).is_some());
+        let k5 = kuratowski_edges(&complete(5)).unwrap();
+        assert_eq!(k5.len(), 10);
+        let k33: Vec<Edge> =
+            (0..3).flat_map(|v| (3..6).map(move |w| (v, w))).collect();
+        assert!(planar_embedding(&adjacency(6, &k33)).is_none());
+
+        // The Petersen graph has no node of degree four, so it can only
+        // contain a subdivided K3,3.
+        let mut petersen: Vec<Edge> = vec![];
+        for i in 0..5 {
+            petersen.push((i, (i + 1) % 5));
+            petersen.push((i, i + 5));
+            petersen.push((5 + i, 5 + (i + 2) % 5));
+        }
+        let found = kuratowski_edges(&adjacency(10, &petersen)).unwrap();
+        let found = adjacency(10, &found);
+        assert_eq!(found.iter().filter(|a| a.len() == 3).count(), 6);
+        assert!(found.iter().all(|a| a.len() != 1 && a.len() <= 3));
+
+        // A triangulated grid, with its faces' count checked by Euler's
+        // formula, a wheel and a forest with an isolated node.
+        let mut grid: Vec<Edge> = vec![];
+        for i in 0..5 {
+            for j in 0..5 {
+                let v = 5 * i + j;
+                if j < 4 {
+                    grid.push((v, v + 1));
+                }
+                if i < 4 {
+                    grid.push((v, v + 5));
+                }
+                if i < 4 && j < 4 {
+                    grid.push((v, v + 6));
+                }
+            }
+        }
+        let grid = adjacency(25, &grid);
+        let embedding = planar_embedding(&grid).unwrap();
+        assert_eq!(faces(&embedding).len(), 2 + 56 - 25);
+        assert_planar_drawing(&grid);
+        let wheel: Vec<Edge> =
+            (1..9).flat_map(|i| vec![(0, i), (i, i % 8 + 1)]).collect();
+        assert_planar_drawing(&adjacency(9, &wheel));
+        assert_planar_drawing(&adjacency(7, &[(0, 1), (1, 2), (1, 3), (4, 5)]));
+    }
+
+    #[test]
+    fn test_deep_searches() {
+        // A long ladder, which the depth-first searches walk to the end of
+        // and back, with its faces' count checked by Euler's formula.
+        let k = 20000;
+        let mut ladder: Vec<Edge> = (0..k).map(|i| (i, k + i)).collect();
+        for i in 0..k - 1 {
+            ladder.push((i, i + 1));
+            ladder.push((k + i, k + i + 1));
+        }
+        let ladder = adjacency(2 * k, &ladder);
+        let embedding = planar_embedding(&ladder).unwrap();
+        assert_eq!(faces(&embedding).len(), k);
+
+        // The same, with a K5 at its far end.
+        let mut ladder = ladder;
+        ladder.extend(vec![vec![]; 4]);
+        let k5 = [2 * k - 1, 2 * k, 2 * k + 1, 2 * k + 2, 2 * k + 3];
+        for v in k5.iter() {
+            for w in k5.iter().filter(|w| *w != v) {
+                ladder[*v].push(*w);
+            }
+        }
+        assert!(planar_embedding(&ladder).is_none());
+        assert!(kuratowski_edges(&ladder).is_none());
+    }
+
+    #[test]
+    fn test_planar_layout() {
+        let scattered = |edges: Vec<Edge>| {
+            let n = edges.iter().map(|(v, w)| v.max(w) + 1).max().unwrap();
+            graph(
+                (0..n)
+                    .map(|i| node(i, (i * i % 7) as f64, (i * 3 % 5) as f64))
+                    .collect(),
+                edges.into_iter().map(|(v, w)| edge(v, w)).collect(),
+            )
+        };
+
+        // The cube.
+        let mut layout = scattered(vec![
+            (0, 1),
+            (1, 2),
+            (2, 3),
+            (3, 0),
+            (4, 5),
+            (5, 6),
+            (6, 7),
+            (7, 4),
+            (0, 4),
+            (1, 5),
+            (2, 6),
+            (3, 7),
+        ]);
+        assert!(layout.is_planar());
+        assert!(layout.kuratowski_subgraph().is_none());
+        assert!(layout.planar_layout_with(&PlanarSettings::default()));
+        let segments: Vec<_> = layout.segment_edge_indices().collect();
+        for (i, a) in segments.iter().enumerate() {
+            for b in segments.iter().skip(i + 1) {
+                let (a, b) = (
+                    layout.graph.edge_endpoints(*a).unwrap(),
+                    layout.graph.edge_endpoints(*b).unwrap(),
+                );
+                if a.0 == b.0 || a.0 == b.1 || a.1 == b.0 || a.1 == b.1 {
+                    continue;
+                }
+                let line = |(v, w): (NodeIndex, NodeIndex)| {
+                    Line::new(
+                        *layout.node_geo(v).unwrap(),
+                        *layout.node_geo(w).unwrap(),
+                    )
+                };
+                assert!(!segments_intersect(&line(a), &line(b)));
+            }
+        }
+
+        // K3,3 is reported and left as it was.
+        let mut layout = scattered(
+            (0..3).flat_map(|v| (3..6).map(move |w| (v, w))).collect(),
+        );
+        let before = layout.positions_vec();
+        assert!(!layout.planar_layout_with(&PlanarSettings::default()));
+        assert_eq!(layout.positions_vec(), before);
+        assert!(!layout.is_planar());
+        let kuratowski = layout.kuratowski_subgraph().unwrap();
+        assert_eq!(kuratowski.kind, KuratowskiKind::K33);
+        assert_eq!(kuratowski.branches.len(), 6);
+        assert_eq!(kuratowski.edges.len(), 9);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::geometry::{EdgeGeo, EdgeShape, NodeGeo};
+use crate::history::Snapshot;
+use crate::intersections::crosses;
+use crate::layout::{GraphLayout, NodeDataId};
+use crate::transitions::Easing;
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use im::OrdMap;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RadialSettings {
+    /// Distance between consecutive rings.
+    pub ring_gap: f64,
+    /// Passes reordering each node's children by where their other
+    /// neighbours are.
+    pub sweeps: usize,
+    /// Frames to animate over when switching roots. 0 jumps straight there.
+    pub frames: usize,
+    pub easing: Easing,
+}
+
+impl Default for RadialSettings {
+    fn default() -> Self {
+        return RadialSettings {
+            ring_gap: 80.0,
+            sweeps: 8,
+            frames: 30,
+            easing: Easing::default(),
+        };
+    }
+}
+
+/// A breadth-first tree, by position in the layout's node list.
+struct RadialTree {
+    depth: Vec<Option<usize>>,
+    parent: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    leaves: Vec<usize>,
+}
+
+impl RadialTree {
+    fn new(adjacency: &[Vec<usize>], root: usize) -> RadialTree {
+        let n = adjacency.len();
+        let mut depth = vec![None; n];
+        let mut parent = vec![None; n];
+        let mut children = vec![vec![]; n];
+        let mut order = vec![];
+        depth[root] = Some(0);
+        let mut queue = VecDeque::from(vec![root]);
+        while let Some(v) = queue.pop_front() {
+            order.push(v);
+            for w in adjacency[v].iter() {
+                if depth[*w].is_none() {
+                    depth[*w] = depth[v].map(|d| d + 1);
+                    parent[*w] = Some(v);
+                    children[v].push(*w);
+                    queue.push_back(*w);
+                }
+            }
+        }
+        let mut leaves = vec![1; n];
+        for v in order.iter().rev() {
+            if !children[*v].is_empty() {
+                leaves[*v] = children[*v].iter().map(|c| leaves[*c]).sum();
+            }
+        }
+        return RadialTree {
+            depth: depth,
+            parent: parent,
+            children: children,
+            leaves: leaves,
+        };
+    }
+
+    /// Splits each node's wedge among its children in proportion to their
+    /// leaves, returning every node's angle and wedge start.
+    fn angles(&self, root: usize) -> (Vec<f64>, Vec<f64>) {
+        let n = self.depth.len();
+        let mut angle = vec![0.0; n];
+        let mut start = vec![0.0; n];
+        let mut stack = vec![(root, 0.0, 2.0 * PI)];
+        while let Some((v, from, width)) = stack.pop() {
+            angle[v] = from + width / 2.0;
+            start[v] = from;
+            let mut cursor = from;
+            for c in self.children[v].iter() {
+                let share = width * self.leaves[*c] as f64
+                    / self.leaves[v].max(1) as f64;
+                stack.push((*c, cursor, share));
+                cursor += share;
+            }
+        }
+        return (angle, start);
+    }
+}
+
+impl GraphLayout {
+    /// Straight-line crossings between edges without a common endpoint.
+    fn straight_crossings(
+        &self,
+        positions: &[NodeGeo],
+        edges: &[(usize, usize)],
+    ) -> usize {
+        let geo =
+            |(a, b): (usize, usize)| EdgeGeo::new(positions[a], positions[b]);
+        let mut count = 0;
+        for (i, (a, b)) in edges.iter().enumerate() {
+            for (c, d) in edges.iter().skip(i + 1) {
+                if a == c || a == d || b == c || b == d {
+                    continue;
+                }
+                if crosses(&geo((*a, *b)), &geo((*c, *d)), self.grid_power()) {
+                    count += 1;
+                }
+            }
+        }
+        return count;
+    }
+
+    /// Radial positions around where `root` is now: `root` in the middle and
+    /// every other node on the ring of its graph distance from it, inside
+    /// its parent's wedge, so the breadth-first tree is drawn without
+    /// crossings. Siblings are then ordered by the angles of their other
+    /// neighbours. Nodes that can't be reached go on one more ring outside.
+    pub fn radial_positions(
+        &self,
+        root: NodeIndex,
+        settings: &RadialSettings,
+    ) -> OrdMap<NodeIndex, NodeGeo> {
+        let nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        let root = match nodes.iter().position(|idx| *idx == root) {
+            Some(root) => root,
+            None => return OrdMap::new(),
+        };
+        let adjacency = self.adjacency(&nodes);
+        let edges: Vec<(usize, usize)> = adjacency
+            .iter()
+            .enumerate()
+            .flat_map(|(v, neighbors)| neighbors.iter().map(move |w| (v, *w)))
+            .filter(|(v, w)| v < w)
+            .collect();
+        let mut tree = RadialTree::new(&adjacency, root);
+        let outer = tree.depth.iter().flatten().max().copied().unwrap_or(0) + 1;
+        let unreachable: Vec<usize> = (0..nodes.len())
+            .filter(|v| tree.depth[*v].is_none())
+            .collect();
+        let center = match self.node_geo(nodes[root]) {
+            Some(position) => *position,
+            None => return OrdMap::new(),
+        };
+
+        let place = |angle: &[f64], tree: &RadialTree| -> Vec<NodeGeo> {
+            let mut positions = vec![center; nodes.len()];
+            for v in 0..nodes.len() {
+                let (ring, theta) = match tree.depth[v] {
+                    Some(depth) => (depth, angle[v]),
+                    None => {
+                        let k = unreachable.iter().position(|u| *u == v);
+                        let k = k.unwrap_or(0) as f64;
+                        (outer, 2.0 * PI * k / unreachable.len() as f64)
+                    }
+                };
+                let radius = ring as f64 * settings.ring_gap;
+                positions[v] = Coordinate {
+                    x: center.x + radius * theta.cos(),
+                    y: center.y + radius * theta.sin(),
+                };
+            }
+            positions
+        };
+
+        let (mut angle, mut start) = tree.angles(root);
+        let mut best = place(&angle, &tree);
+        let mut fewest = self.straight_crossings(&best, &edges);
+        for _ in 0..settings.sweeps {
+            if fewest == 0 {
+                break;
+            }
+            for v in 0..nodes.len() {
+                let mut keyed: Vec<(f64, usize)> = tree.children[v]
+                    .iter()
+                    .map(|c| {
+                        let (sin, cos) = adjacency[*c]
+                            .iter()
+                            .filter(|w| Some(**w) != tree.parent[*c])
+                            .filter(|w| tree.depth[**w].is_some())
+                            .fold((0.0, 0.0), |(s, c), w| {
+                                (s + angle[*w].sin(), c + angle[*w].cos())
+                            });
+                        let mean = if sin == 0.0 && cos == 0.0 {
+                            angle[*c]
+                        } else {
+                            sin.atan2(cos)
+                        };
+                        ((mean - start[v]).rem_euclid(2.0 * PI), *c)
+                    })
+                    .collect();
+                keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                tree.children[v] = keyed.into_iter().map(|(_, c)| c).collect();
+            }
+            let (next_angle, next_start) = tree.angles(root);
+            angle = next_angle;
+            start = next_start;
+            let positions = place(&angle, &tree);
+            let crossings = self.straight_crossings(&positions, &edges);
+            if crossings < fewest {
+                fewest = crossings;
+                best = positions;
+            }
+        }
+        return nodes.into_iter().zip(best).collect();
+    }
+
+    /// Switches to the radial layout around `root`, animated from the
+    /// current positions over `settings.frames`.
+    pub fn radial_layout_with(
+        &mut self,
+        root: NodeIndex,
+        settings: &RadialSettings,
+    ) {
+        let target = Snapshot {
+            label: "radial layout".to_string(),
+            positions: self.radial_positions(root, settings),
+        };
+        let segments: Vec<_> = self.segment_edge_indices().collect();
+        for idx in segments {
+            self.set_edge_shape(idx, EdgeShape::Straight);
+        }
+        if settings.frames == 0 {
+            self.checkpoint_with_label(&target.label);
+            self.restore_positions(&target.positions);
+        } else {
+            self.start_transition_to(&target, settings.frames, settings.easing);
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Lays the graph out in rings around the node `root`, as one undo
+    /// step. Unless `frames` is 0 this starts a transition; call
+    /// `next_transition_frame` to animate it.
+    pub fn radial_layout(
+        &mut self,
+        root: &str,
+        settings: JsValue,
+    ) -> Result<bool, JsValue> {
+        let settings: RadialSettings = settings_from_js(&settings)?;
+        let idx = match self.node_index(&NodeDataId::from_str_truncate(root)) {
+            Some(idx) => idx,
+            None => return Ok(false),
+        };
+        self.radial_layout_with(idx, &settings);
+        return Ok(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::EdgeData;
+    use crate::test_utils::{edge, graph, id, node};
+
+    #[test]
+    fn test_radial_rings_and_rerooting() {
+        let scattered = |i: usize| {
+            node(i, (i * 37 % 11) as f64 * 10.0, (i * 17 % 13) as f64 * 10.0)
+        };
+        // A binary tree on 0..7, a chord between two leaves and a stray 7.
+        let mut edges: Vec<EdgeData> =
+            (1..7).map(|i| edge((i - 1) / 2, i)).collect();
+        edges.push(edge(3, 5));
+        let mut layout = graph((0..8).map(scattered).collect(), edges);
+        let at = |layout: &GraphLayout, i: usize| {
+            *layout.node_geo(layout.node_index(&id(i)).unwrap()).unwrap()
+        };
+        let settings = RadialSettings::default();
+        let root = layout.node_index(&id(0)).unwrap();
+        let start = layout.node_positions.clone();
+        let center = at(&layout, 0);
+        let target = layout.radial_positions(root, &settings);
+        let ring = |i: usize| {
+            let p = target[&layout.node_index(&id(i)).unwrap()] - center;
+            (p.x * p.x + p.y * p.y).sqrt() / settings.ring_gap
+        };
+        let expected = vec![0.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0, 3.0];
+        for (i, r) in expected.iter().enumerate() {
+            assert!((ring(i) - r).abs() < 1e-9);
+        }
+
+        layout.radial_layout_with(root, &settings);
+        assert_eq!(layout.node_positions, start);
+        let mut frames = 0;
+        while layout.advance_transition() {
+            frames += 1;
+        }
+        assert_eq!(frames, settings.frames);
+        assert_eq!(layout.node_positions, target);
+
+        // Re-rooting at a leaf keeps it where it is, with the rest around it.
+        let before = at(&layout, 6);
+        let leaf = layout.node_index(&id(6)).unwrap();
+        layout.radial_layout_with(
+            leaf,
+            &RadialSettings {
+                frames: 0,
+                ..settings
+            },
+        );
+        assert_eq!(at(&layout, 6), before);
+        let p = at(&layout, 0) - before;
+        let distance = (p.x * p.x + p.y * p.y).sqrt();
+        assert!((distance - 2.0 * settings.ring_gap).abs() < 1e-9);
+        assert!(layout.undo());
+        assert_eq!(layout.node_positions, target);
+    }
+}
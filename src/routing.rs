@@ -0,0 +1,327 @@
+use crate::geometry::tree::Envelope;
+use crate::geometry::{EdgeShape, NodeGeo, MIN_DISTANCE};
+use crate::layout::GraphLayout;
+use geo::algorithm::euclidean_distance::EuclideanDistance;
+use geo::{Coordinate, Line, Point};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::{Incoming, Outgoing};
+use rstar::RTreeObject;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+pub mod bundling;
+pub mod orthogonal;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteStyle {
+    /// Bend points placed beside each node in the way.
+    Polyline,
+    /// A single quadratic Bézier bowing away from the nodes in the way.
+    Quadratic,
+}
+
+fn cross(a: NodeGeo, b: NodeGeo) -> f64 {
+    return a.x * b.y - a.y * b.x;
+}
+
+fn dot(a: NodeGeo, b: NodeGeo) -> f64 {
+    return a.x * b.x + a.y * b.y;
+}
+
+pub fn inflate(envelope: Envelope, amount: f64) -> Envelope {
+    let (lower, upper) = (envelope.lower(), envelope.upper());
+    return Envelope::from_corners(
+        Point::new(lower.x() - amount, lower.y() - amount),
+        Point::new(upper.x() + amount, upper.y() + amount),
+    );
+}
+
+/// A node lying within `clearance` of a straight edge, described by where it
+/// projects onto the edge (`t` in 0..1) and its signed distance to the left
+/// of the edge.
+struct Obstacle {
+    position: NodeGeo,
+    t: f64,
+    offset: f64,
+}
+
+impl GraphLayout {
+    fn edge_obstacles(&self, idx: EdgeIndex, clearance: f64) -> Vec<Obstacle> {
+        let (a, b) = match self.graph.edge_endpoints(idx) {
+            Some(endpoints) => endpoints,
+            None => return vec![],
+        };
+        let (start, end) = match self.edge_endpoints_geo(idx) {
+            Some(endpoints) => endpoints,
+            None => return vec![],
+        };
+        let chord = Line::new(start, end);
+        let delta = end - start;
+        let length = dot(delta, delta).sqrt();
+        if length == 0.0 {
+            return vec![];
+        }
+
+        let mut obstacles: Vec<Obstacle> = self
+            .graph_geo
+            .nodes_in_envelope(&inflate(chord.envelope(), clearance))
+            .filter(|(n, _)| **n != a && **n != b)
+            .filter(|(_, p)| {
+                Point::from(**p).euclidean_distance(&chord) < clearance
+            })
+            .map(|(_, p)| Obstacle {
+                position: *p,
+                t: dot(*p - start, delta) / (length * length),
+                offset: cross(delta, *p - start) / length,
+            })
+            .filter(|o| o.t > 0.0 && o.t < 1.0)
+            .collect();
+        obstacles.sort_by(|x, y| x.t.partial_cmp(&y.t).unwrap());
+        return obstacles;
+    }
+
+    /// Reshapes a single edge to steer around the nodes, other than its
+    /// endpoints, that lie within `clearance` of it. A polyline bends exactly
+    /// `clearance` beyond each such node, square to the edge, and a curve
+    /// bows at least that far out beside each one not too near its ends.
+    /// In between, the route can still pass closer than `clearance`.
+    pub fn route_edge(
+        &mut self,
+        idx: EdgeIndex,
+        clearance: f64,
+        style: RouteStyle,
+    ) {
+        let obstacles = self.edge_obstacles(idx, clearance);
+        let (start, end) = match self.edge_endpoints_geo(idx) {
+            Some(endpoints) => endpoints,
+            None => return,
+        };
+        let delta = end - start;
+        let length = dot(delta, delta).sqrt();
+
+        let shape = if obstacles.is_empty() {
+            EdgeShape::Straight
+        } else {
+            let normal = Coordinate {
+                x: -delta.y / length,
+                y: delta.x / length,
+            };
+            match style {
+                RouteStyle::Polyline => EdgeShape::Polyline(
+                    obstacles
+                        .iter()
+                        .map(|o| {
+                            let side = if o.offset > 0.0 { -1.0 } else { 1.0 };
+                            o.position + normal * (side * clearance)
+                        })
+                        .collect(),
+                ),
+                RouteStyle::Quadratic => {
+                    // Bow away from the side most of the obstacles are on.
+                    // A quadratic Bézier with its control point `h` away from
+                    // the chord deviates by `2t(1 - t)h` at parameter `t`.
+                    let side = if obstacles
+                        .iter()
+                        .map(|o| o.offset)
+                        .sum::<f64>()
+                        > 0.0
+                    {
+                        -1.0
+                    } else {
+                        1.0
+                    };
+                    let height = obstacles
+                        .iter()
+                        .map(|o| {
+                            let t = o.t.max(0.1).min(0.9);
+                            (clearance + side * o.offset)
+                                / (2.0 * t * (1.0 - t))
+                        })
+                        .fold(0.0, f64::max);
+                    EdgeShape::Quadratic(
+                        (start + end) * 0.5 + normal * (side * height),
+                    )
+                }
+            }
+        };
+
+        self.set_edge_shape(idx, shape);
+    }
+
+    /// Keeps the bends and control points of the edges at `idx` where they
+    /// were relative to their endpoints as it moves to `position`, turning
+    /// and scaling them with the segment between the endpoints. Orthogonal
+    /// routes may need routing again to stay axis-aligned. Points of edges
+    /// whose endpoints coincide just move by the endpoints' average move.
+    ///
+    /// Old positions come from `node_positions`, so this still works for
+    /// nodes that have been lifted off the grid to be placed again.
+    pub fn stretch_edge_shapes(&mut self, idx: NodeIndex, position: NodeGeo) {
+        match self.node_positions.get(&idx) {
+            Some(from) if *from != position => {}
+            _ => return,
+        };
+        let edges: Vec<EdgeIndex> = self
+            .graph
+            .edges_directed(idx, Outgoing)
+            .chain(self.graph.edges_directed(idx, Incoming))
+            .filter(|r| r.weight().segment_of.is_none())
+            .filter(|r| !r.weight().shape.is_straight())
+            .map(|r| r.id())
+            .collect();
+        for e in edges {
+            let (u, v) = match self.graph.edge_endpoints(e) {
+                Some(ends) => ends,
+                None => continue,
+            };
+            let (a, b) = match (
+                self.node_positions.get(&u),
+                self.node_positions.get(&v),
+            ) {
+                (Some(a), Some(b)) => (*a, *b),
+                _ => continue,
+            };
+            let (na, nb) = (
+                if u == idx { position } else { a },
+                if v == idx { position } else { b },
+            );
+            let (d, nd) = (b - a, nb - na);
+            let length = d.x * d.x + d.y * d.y;
+            let shape = if length < MIN_DISTANCE * MIN_DISTANCE {
+                let shift = ((na - a) + (nb - b)) / 2.0;
+                self.graph[e].shape.translated(shift)
+            } else {
+                // p = a + u d + v d⊥ before and na + u nd + v nd⊥ after.
+                self.graph[e].shape.map_points(|p| {
+                    let r = p - a;
+                    let u = (r.x * d.x + r.y * d.y) / length;
+                    let v = (r.y * d.x - r.x * d.y) / length;
+                    Coordinate {
+                        x: na.x + u * nd.x - v * nd.y,
+                        y: na.y + u * nd.y + v * nd.x,
+                    }
+                })
+            };
+            self.graph[e].shape = shape;
+        }
+    }
+
+    pub fn set_edge_shape(&mut self, idx: EdgeIndex, shape: EdgeShape) {
+        match self.graph.edge_weight_mut(idx) {
+            Some(weight) => weight.shape = shape,
+            None => return,
+        };
+        if let Some(edge_geo) = self.edge_geo(idx) {
+            self.graph_geo.insert_edge(idx, edge_geo);
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    pub fn route_edges(&mut self, clearance: f64, curved: Option<bool>) {
+        let style = if curved.unwrap_or_default() {
+            RouteStyle::Quadratic
+        } else {
+            RouteStyle::Polyline
+        };
+        let edges: Vec<EdgeIndex> = self.segment_edge_indices().collect();
+        for idx in edges {
+            self.route_edge(idx, clearance, style);
+        }
+    }
+
+    pub fn straighten_edges(&mut self) {
+        let edges: Vec<EdgeIndex> = self.segment_edge_indices().collect();
+        for idx in edges {
+            self.set_edge_shape(idx, EdgeShape::Straight);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{edge, graph, id, node};
+
+    fn blocked_edge() -> GraphLayout {
+        return graph(
+            vec![node("a", 0., 0.), node("b", 10., 0.), node("c", 4., 0.5)],
+            vec![edge("a", "b")],
+        );
+    }
+
+    #[test]
+    fn test_routes_clear_nodes() {
+        for style in vec![RouteStyle::Polyline, RouteStyle::Quadratic] {
+            let mut layout = blocked_edge();
+            let idx = layout.graph.edge_indices().next().unwrap();
+            let obstacle = Point::new(4., 0.5);
+            let clearance = 1.0;
+            assert!(
+                layout
+                    .edge_geo(idx)
+                    .unwrap()
+                    .chord()
+                    .euclidean_distance(&obstacle)
+                    < clearance
+            );
+
+            layout.route_edge(idx, clearance, style);
+            assert!(!layout.edge_geo(idx).unwrap().is_straight());
+            // The obstacle projects onto the edge at t = 0.4, and the route
+            // passes `clearance` beyond it there, below the edge.
+            let beside = match layout.graph[idx].shape {
+                EdgeShape::Polyline(ref bends) => {
+                    assert_eq!(bends.len(), 1);
+                    bends[0]
+                }
+                EdgeShape::Quadratic(control) => {
+                    // The start term drops out with `a` at the origin.
+                    let t = 0.4;
+                    let end = Coordinate { x: 10., y: 0. };
+                    control * (2.0 * t * (1.0 - t)) + end * (t * t)
+                }
+                _ => panic!("{:?} left the edge unrouted", style),
+            };
+            let distance = Point::from(beside).euclidean_distance(&obstacle);
+            assert!(beside.y < 0.0);
+            match style {
+                RouteStyle::Polyline => {
+                    assert!((distance - clearance).abs() < 1e-9)
+                }
+                RouteStyle::Quadratic => assert!(distance >= clearance - 1e-9),
+            }
+            assert_eq!(layout.graph_geo.edges.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_bends_follow_their_endpoints() {
+        let mut layout = blocked_edge();
+        let idx = layout.graph.edge_indices().next().unwrap();
+        layout.route_edge(idx, 1.0, RouteStyle::Polyline);
+        let before = layout.edge_geo(idx).unwrap().points();
+
+        // With `a` at the origin, doubling `b`'s distance from it doubles
+        // every bend's.
+        let b = id("b");
+        let b = layout.node_index(&b).unwrap();
+        layout.set_node_geo(b, Coordinate { x: 20., y: 0. });
+        let after = layout.edge_geo(idx).unwrap().points();
+        for (p, q) in before.iter().zip(after.iter()) {
+            assert!((*p * 2.0 - *q).x.abs() < 1e-9);
+            assert!((*p * 2.0 - *q).y.abs() < 1e-9);
+        }
+
+        // The path `edges_data` writes out loads back as the same bends.
+        let reloaded = graph(
+            layout.all_nodes_data(),
+            vec![layout.edge_data(idx).unwrap()],
+        );
+        let idx = reloaded.graph.edge_indices().next().unwrap();
+        assert_eq!(reloaded.edge_geo(idx).unwrap().points(), after);
+    }
+}
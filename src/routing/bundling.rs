@@ -0,0 +1,287 @@
+use super::{dot, inflate};
+use crate::geometry::{EdgeShape, NodeGeo};
+use crate::layout::GraphLayout;
+use crate::utils::settings_from_js;
+use geo::Line;
+use petgraph::graph::EdgeIndex;
+use rstar::RTreeObject;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+/// Parameters of force-directed edge bundling, following Holten & van Wijk,
+/// "Force-Directed Edge Bundling for Graph Visualization" (2009).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct BundlingSettings {
+    /// Spring constant holding each edge's control points together.
+    pub stiffness: f64,
+    /// Number of subdivide-then-relax cycles.
+    pub cycles: usize,
+    /// Distance moved per unit of force in the first cycle, halved after
+    /// every cycle.
+    pub initial_step: f64,
+    /// Relaxation iterations in the first cycle, multiplied by
+    /// `iteration_rate` after every cycle.
+    pub initial_iterations: usize,
+    pub iteration_rate: f64,
+    /// Edge pairs less compatible than this don't attract each other.
+    pub compatibility_threshold: f64,
+}
+
+impl Default for BundlingSettings {
+    fn default() -> Self {
+        return BundlingSettings {
+            stiffness: 0.1,
+            cycles: 6,
+            initial_step: 1.0,
+            initial_iterations: 90,
+            iteration_rate: 2.0 / 3.0,
+            compatibility_threshold: 0.6,
+        };
+    }
+}
+
+fn length(line: &Line<f64>) -> f64 {
+    let delta = line.delta();
+    return dot(delta, delta).sqrt();
+}
+
+fn midpoint(line: &Line<f64>) -> NodeGeo {
+    return (line.start + line.end) * 0.5;
+}
+
+fn distance(a: NodeGeo, b: NodeGeo) -> f64 {
+    let delta = b - a;
+    return dot(delta, delta).sqrt();
+}
+
+fn project(point: NodeGeo, line: &Line<f64>) -> NodeGeo {
+    let delta = line.delta();
+    let t = dot(point - line.start, delta) / dot(delta, delta);
+    return line.start + delta * t;
+}
+
+fn visibility(p: &Line<f64>, q: &Line<f64>) -> f64 {
+    let (i0, i1) = (project(q.start, p), project(q.end, p));
+    let span = distance(i0, i1);
+    if span == 0.0 {
+        return 0.0;
+    }
+    let mid = (i0 + i1) * 0.5;
+    return (1.0 - 2.0 * distance(midpoint(p), mid) / span).max(0.0);
+}
+
+/// The product of the angle, scale, position and visibility compatibility
+/// measures, in 0..1.
+pub fn compatibility(p: &Line<f64>, q: &Line<f64>) -> f64 {
+    let (lp, lq) = (length(p), length(q));
+    if lp == 0.0 || lq == 0.0 {
+        return 0.0;
+    }
+    let average = (lp + lq) / 2.0;
+    let angle = (dot(p.delta(), q.delta()) / (lp * lq)).abs();
+    let scale = 2.0 / (average / lp.min(lq) + lp.max(lq) / average);
+    let position = average / (average + distance(midpoint(p), midpoint(q)));
+    let visible = visibility(p, q).min(visibility(q, p));
+    return angle * scale * position * visible;
+}
+
+/// Resamples a polyline into `divisions + 1` points evenly spaced along its
+/// length, keeping the endpoints.
+fn subdivide(points: &[NodeGeo], divisions: usize) -> Vec<NodeGeo> {
+    let total: f64 = points.windows(2).map(|w| distance(w[0], w[1])).sum();
+    let last = points[points.len() - 1];
+    if total == 0.0 {
+        return vec![points[0]; divisions + 1];
+    }
+    let spacing = total / divisions as f64;
+
+    let mut result = vec![points[0]];
+    let mut walked = 0.0;
+    let mut target = spacing;
+    for w in points.windows(2) {
+        let segment = distance(w[0], w[1]);
+        while result.len() < divisions && walked + segment >= target {
+            let t = (target - walked) / segment;
+            result.push(w[0] + (w[1] - w[0]) * t);
+            target += spacing;
+        }
+        walked += segment;
+    }
+    while result.len() < divisions {
+        result.push(last);
+    }
+    result.push(last);
+    return result;
+}
+
+impl GraphLayout {
+    /// Pairs of compatible edges with their compatibility, found by querying
+    /// the R-tree around each edge rather than comparing every pair.
+    fn compatible_edges(
+        &self,
+        edges: &[EdgeIndex],
+        threshold: f64,
+    ) -> Vec<Vec<(usize, bool, f64)>> {
+        let position: HashMap<EdgeIndex, usize> =
+            edges.iter().enumerate().map(|(i, e)| (*e, i)).collect();
+        let chords: Vec<Option<Line<f64>>> = edges
+            .iter()
+            .map(|e| self.edge_geo(*e).map(|geo| geo.chord()))
+            .collect();
+
+        return chords
+            .iter()
+            .enumerate()
+            .map(|(i, chord)| match chord {
+                Some(p) => self
+                    .graph_geo
+                    .edges_in_envelope(&inflate(p.envelope(), length(p)))
+                    .filter_map(|(idx, _)| position.get(idx))
+                    .filter(|j| **j != i)
+                    .filter_map(|j| {
+                        let q = chords[*j]?;
+                        let c = compatibility(p, &q);
+                        if c < threshold {
+                            return None;
+                        }
+                        // Pair up control points from the same end when the
+                        // two edges point opposite ways.
+                        Some((*j, dot(p.delta(), q.delta()) < 0.0, c))
+                    })
+                    .collect(),
+                None => vec![],
+            })
+            .collect();
+    }
+
+    pub fn bundle_edges_with(&mut self, settings: BundlingSettings) {
+        let edges: Vec<EdgeIndex> = self.segment_edge_indices().collect();
+        let compatible =
+            self.compatible_edges(&edges, settings.compatibility_threshold);
+        let lengths: Vec<f64> = edges
+            .iter()
+            .map(|e| self.edge_geo(*e).map_or(0.0, |geo| length(&geo.chord())))
+            .collect();
+        let mut paths: Vec<Vec<NodeGeo>> = edges
+            .iter()
+            .map(|e| match self.edge_endpoints_geo(*e) {
+                Some((a, b)) => vec![a, b],
+                None => vec![],
+            })
+            .collect();
+
+        let mut divisions = 1;
+        let mut step = settings.initial_step;
+        let mut iterations = settings.initial_iterations as f64;
+        for _ in 0..settings.cycles {
+            divisions *= 2;
+            for path in paths.iter_mut().filter(|path| !path.is_empty()) {
+                *path = subdivide(path, divisions);
+            }
+
+            for _ in 0..iterations.round() as usize {
+                let forces: Vec<Vec<NodeGeo>> = paths
+                    .iter()
+                    .enumerate()
+                    .map(|(e, path)| {
+                        if path.is_empty() || lengths[e] == 0.0 {
+                            return vec![];
+                        }
+                        let spring = settings.stiffness
+                            / (lengths[e] * divisions as f64);
+                        (1..divisions)
+                            .map(|i| {
+                                let p = path[i];
+                                let mut force = (path[i - 1] - p
+                                    + (path[i + 1] - p))
+                                    * spring;
+                                for (other, flipped, c) in compatible[e].iter()
+                                {
+                                    let j = if *flipped {
+                                        divisions - i
+                                    } else {
+                                        i
+                                    };
+                                    let q = match paths[*other].get(j) {
+                                        Some(q) => *q,
+                                        None => continue,
+                                    };
+                                    let d = distance(p, q);
+                                    // Holten's electrostatic attraction of
+                                    // c / d, capped at halfway to q per step
+                                    // so that close points don't overshoot.
+                                    if d > 1e-6 {
+                                        let pull =
+                                            (*c / (d * d)).min(0.5 / step);
+                                        force = force + (q - p) * pull;
+                                    }
+                                }
+                                force * step
+                            })
+                            .collect()
+                    })
+                    .collect();
+
+                for (path, force) in paths.iter_mut().zip(forces) {
+                    for (i, f) in force.into_iter().enumerate() {
+                        path[i + 1] = path[i + 1] + f;
+                    }
+                }
+            }
+
+            step /= 2.0;
+            iterations *= settings.iteration_rate;
+        }
+
+        for (idx, path) in edges.into_iter().zip(paths) {
+            if path.len() > 2 {
+                let bends = path[1..path.len() - 1].to_vec();
+                self.set_edge_shape(idx, EdgeShape::Polyline(bends));
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    pub fn bundle_edges(&mut self, settings: JsValue) -> Result<(), JsValue> {
+        let settings: BundlingSettings = settings_from_js(&settings)?;
+        self.bundle_edges_with(settings);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{edge, graph, node};
+
+    #[test]
+    fn test_parallel_edges_attract() {
+        let mut layout = graph(
+            vec![
+                node("a", 0., 0.),
+                node("b", 100., 0.),
+                node("c", 100., 10.),
+                node("d", 0., 10.),
+            ],
+            // The second edge runs backwards to exercise flipped pairing.
+            vec![edge("a", "b"), edge("c", "d")],
+        );
+
+        layout.bundle_edges_with(BundlingSettings::default());
+
+        let middles: Vec<NodeGeo> = layout
+            .edges_geo()
+            .iter()
+            .map(|geo| {
+                let points = geo.points();
+                points[points.len() / 2]
+            })
+            .collect();
+        assert!(distance(middles[0], middles[1]) < 5.0);
+        assert!((middles[0].x - 50.0).abs() < 1.0);
+    }
+}
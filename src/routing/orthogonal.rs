@@ -0,0 +1,428 @@
+use crate::geometry::{EdgeShape, NodeGeo};
+use crate::layout::GraphLayout;
+use crate::utils::settings_from_js;
+use geo::{Coordinate, Line, Point};
+use ordered_float::OrderedFloat;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use rstar::{RTree, RTreeObject, AABB};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OrthogonalSettings {
+    /// Width and height of the box drawn around each node.
+    pub node_size: f64,
+    /// Extra space kept between routes and node boxes.
+    pub margin: f64,
+    /// Cost of a bend, in units of route length.
+    pub bend_penalty: f64,
+    /// Cost of crossing or running along an already routed edge.
+    pub crossing_penalty: f64,
+}
+
+impl Default for OrthogonalSettings {
+    fn default() -> Self {
+        return OrthogonalSettings {
+            node_size: 10.0,
+            margin: 5.0,
+            bend_penalty: 20.0,
+            crossing_penalty: 100.0,
+        };
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+enum Heading {
+    Start,
+    Horizontal,
+    Vertical,
+}
+
+/// The routing grid over a window around one edge: the window's sides plus
+/// the center and the coordinates just outside the box of every node whose
+/// box reaches into the window, in both axes.
+struct Grid {
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+}
+
+impl Grid {
+    fn point(&self, (i, j): (usize, usize)) -> NodeGeo {
+        return Coordinate {
+            x: self.xs[i],
+            y: self.ys[j],
+        };
+    }
+
+    fn locate(&self, p: NodeGeo) -> Option<(usize, usize)> {
+        let find = |values: &Vec<f64>, v: f64| {
+            values.iter().position(|x| (x - v).abs() < 1e-9)
+        };
+        return Some((find(&self.xs, p.x)?, find(&self.ys, p.y)?));
+    }
+
+    fn neighbors(
+        &self,
+        (i, j): (usize, usize),
+    ) -> Vec<((usize, usize), Heading)> {
+        let mut result = vec![];
+        if i > 0 {
+            result.push(((i - 1, j), Heading::Horizontal));
+        }
+        if i + 1 < self.xs.len() {
+            result.push(((i + 1, j), Heading::Horizontal));
+        }
+        if j > 0 {
+            result.push(((i, j - 1), Heading::Vertical));
+        }
+        if j + 1 < self.ys.len() {
+            result.push(((i, j + 1), Heading::Vertical));
+        }
+        return result;
+    }
+}
+
+fn sorted_unique(mut values: Vec<f64>) -> Vec<f64> {
+    values.sort_by_key(|v| OrderedFloat(*v));
+    values.dedup_by(|a, b| (*a - *b).abs() < 1e-9);
+    return values;
+}
+
+/// Whether two axis-aligned segments cross at interior points or overlap
+/// along the same line.
+fn conflicts(a: &Line<f64>, b: &Line<f64>) -> bool {
+    let range = |u: f64, v: f64| (u.min(v), u.max(v));
+    let (ax, ay) = (range(a.start.x, a.end.x), range(a.start.y, a.end.y));
+    let (bx, by) = (range(b.start.x, b.end.x), range(b.start.y, b.end.y));
+    let inside = |v: f64, (lo, hi): (f64, f64)| lo < v && v < hi;
+    let overlap = |(lo1, hi1): (f64, f64), (lo2, hi2): (f64, f64)| {
+        lo1.max(lo2) < hi1.min(hi2)
+    };
+    let a_horizontal = ay.0 == ay.1;
+    let b_horizontal = by.0 == by.1;
+    return match (a_horizontal, b_horizontal) {
+        (true, false) => inside(bx.0, ax) && inside(ay.0, by),
+        (false, true) => inside(ax.0, bx) && inside(by.0, ay),
+        (true, true) => ay.0 == by.0 && overlap(ax, bx),
+        (false, false) => ax.0 == bx.0 && overlap(ay, by),
+    };
+}
+
+/// Removes the points where a route carries on in the same direction,
+/// leaving only the bends.
+fn bends(path: &[NodeGeo]) -> Vec<NodeGeo> {
+    return path
+        .windows(3)
+        .filter(|w| {
+            let straight = (w[0].x == w[1].x && w[1].x == w[2].x)
+                || (w[0].y == w[1].y && w[1].y == w[2].y);
+            !straight
+        })
+        .map(|w| w[1])
+        .collect();
+}
+
+impl GraphLayout {
+    fn orthogonal_grid(
+        &self,
+        window: &AABB<Point<f64>>,
+        settings: &OrthogonalSettings,
+    ) -> Grid {
+        let reach = settings.node_size / 2.0 + settings.margin;
+        let (lower, upper) = (window.lower(), window.upper());
+        let reaching = AABB::from_corners(
+            Point::new(lower.x() - reach, lower.y() - reach),
+            Point::new(upper.x() + reach, upper.y() + reach),
+        );
+        let centers: Vec<NodeGeo> = self
+            .graph_geo
+            .nodes_in_envelope(&reaching)
+            .map(|(_, c)| *c)
+            .collect();
+        // Lines outside the window are dropped, so that every box a route
+        // could cut through has its lines on the grid.
+        let lines = |values: Vec<f64>, lo: f64, hi: f64| {
+            sorted_unique(
+                values
+                    .into_iter()
+                    .filter(|v| lo <= *v && *v <= hi)
+                    .chain(vec![lo, hi])
+                    .collect(),
+            )
+        };
+        return Grid {
+            xs: lines(
+                centers
+                    .iter()
+                    .flat_map(|c| vec![c.x - reach, c.x, c.x + reach])
+                    .collect(),
+                lower.x(),
+                upper.x(),
+            ),
+            ys: lines(
+                centers
+                    .iter()
+                    .flat_map(|c| vec![c.y - reach, c.y, c.y + reach])
+                    .collect(),
+                lower.y(),
+                upper.y(),
+            ),
+        };
+    }
+
+    /// Whether `p` lies inside some node's box. Points on a box's boundary
+    /// are left open so that routes can run along it.
+    fn in_node_box(&self, p: NodeGeo, reach: f64) -> bool {
+        let inside = reach - 1e-9;
+        let envelope = AABB::from_corners(
+            Point::new(p.x - inside, p.y - inside),
+            Point::new(p.x + inside, p.y + inside),
+        );
+        return self.graph_geo.nodes_in_envelope(&envelope).next().is_some();
+    }
+
+    /// Finds the cheapest route between two nodes through the grid with A*,
+    /// where each state remembers its heading so that bends can be charged.
+    fn orthogonal_route(
+        &self,
+        grid: &Grid,
+        routed: &RTree<Line<f64>>,
+        (a, b): (NodeIndex, NodeIndex),
+        settings: &OrthogonalSettings,
+    ) -> Option<Vec<NodeGeo>> {
+        let start = grid.locate(*self.node_geo(a)?)?;
+        let goal = grid.locate(*self.node_geo(b)?)?;
+        let goal_point = grid.point(goal);
+        let reach = settings.node_size / 2.0 + settings.margin;
+        let near = |p: NodeGeo, c: NodeGeo| {
+            (p.x - c.x).abs() < reach && (p.y - c.y).abs() < reach
+        };
+        let start_point = grid.point(start);
+        let open = |cell: (usize, usize)| {
+            let p = grid.point(cell);
+            near(p, start_point)
+                || near(p, goal_point)
+                || !self.in_node_box(p, reach)
+        };
+        // Cells are only tested against the node boxes once A* reaches them.
+        let mut passable: HashMap<(usize, usize), bool> = HashMap::new();
+        let estimate = |cell: (usize, usize)| {
+            let p = grid.point(cell);
+            (p.x - goal_point.x).abs() + (p.y - goal_point.y).abs()
+        };
+
+        type State = ((usize, usize), Heading);
+        let mut best: HashMap<State, f64> = HashMap::new();
+        let mut previous: HashMap<State, State> = HashMap::new();
+        let mut frontier = BinaryHeap::new();
+        best.insert((start, Heading::Start), 0.0);
+        frontier.push((
+            Reverse(OrderedFloat(estimate(start))),
+            start,
+            Heading::Start,
+        ));
+
+        while let Some((_, cell, heading)) = frontier.pop() {
+            let state = (cell, heading);
+            if cell == goal {
+                let mut path = vec![grid.point(cell)];
+                let mut current = state;
+                while let Some(prior) = previous.get(&current) {
+                    path.push(grid.point(prior.0));
+                    current = *prior;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let cost = best[&state];
+            for (next, next_heading) in grid.neighbors(cell) {
+                if !*passable.entry(next).or_insert_with(|| open(next)) {
+                    continue;
+                }
+                let step = Line::new(grid.point(cell), grid.point(next));
+                let length =
+                    (step.dx().abs() + step.dy().abs()).max(std::f64::EPSILON);
+                let bend = match (heading, next_heading) {
+                    (Heading::Start, _) => 0.0,
+                    (h, n) if h == n => 0.0,
+                    _ => settings.bend_penalty,
+                };
+                let conflicts = routed
+                    .locate_in_envelope_intersecting(&step.envelope())
+                    .filter(|other| conflicts(&step, other))
+                    .count() as f64;
+                let next_cost = cost
+                    + length
+                    + bend
+                    + conflicts * settings.crossing_penalty;
+                let next_state = (next, next_heading);
+                if best.get(&next_state).map_or(true, |c| next_cost < *c) {
+                    best.insert(next_state, next_cost);
+                    previous.insert(next_state, state);
+                    frontier.push((
+                        Reverse(OrderedFloat(next_cost + estimate(next))),
+                        next,
+                        next_heading,
+                    ));
+                }
+            }
+        }
+        return None;
+    }
+
+    /// Replaces every edge with an axis-aligned route around the node boxes,
+    /// shortest edges first, storing the bend points on the edges. Each edge
+    /// is routed on a grid around its own bounding box, which widens until a
+    /// route is found or it covers every node.
+    pub fn route_edges_orthogonal_with(
+        &mut self,
+        settings: OrthogonalSettings,
+    ) {
+        let reach = settings.node_size / 2.0 + settings.margin;
+        let (xs, ys): (Vec<f64>, Vec<f64>) =
+            self.node_positions.values().map(|p| (p.x, p.y)).unzip();
+        let extent = |values: &Vec<f64>| {
+            let lo = values.iter().copied().fold(std::f64::INFINITY, f64::min);
+            let hi = values.iter().copied().fold(-std::f64::INFINITY, f64::max);
+            hi - lo
+        };
+        let span = extent(&xs).max(extent(&ys)) + 2.0 * reach;
+        let mut edges: Vec<(EdgeIndex, f64)> = self
+            .segment_edge_indices()
+            .filter_map(|idx| {
+                let (a, b) = self.edge_endpoints_geo(idx)?;
+                Some((idx, (a.x - b.x).abs() + (a.y - b.y).abs()))
+            })
+            .collect();
+        edges.sort_by_key(|(_, distance)| OrderedFloat(*distance));
+
+        let mut routed: RTree<Line<f64>> = RTree::new();
+        for (idx, _) in edges {
+            let endpoints = match self.graph.edge_endpoints(idx) {
+                Some(endpoints) => endpoints,
+                None => continue,
+            };
+            let (start, end) = match self.edge_endpoints_geo(idx) {
+                Some(geo) => geo,
+                None => continue,
+            };
+            let mut grow = (2.0 * reach).max(1.0);
+            let path = loop {
+                let window = AABB::from_corners(
+                    Point::new(
+                        start.x.min(end.x) - grow,
+                        start.y.min(end.y) - grow,
+                    ),
+                    Point::new(
+                        start.x.max(end.x) + grow,
+                        start.y.max(end.y) + grow,
+                    ),
+                );
+                let grid = self.orthogonal_grid(&window, &settings);
+                let path =
+                    self.orthogonal_route(&grid, &routed, endpoints, &settings);
+                if path.is_some() || !(grow < span) {
+                    break path;
+                }
+                grow *= 2.0;
+            };
+            let path = path.unwrap_or_else(|| {
+                // Boxed in: fall back to an L through the corner.
+                let corner = Coordinate {
+                    x: end.x,
+                    y: start.y,
+                };
+                vec![start, corner, end]
+            });
+            for segment in path.windows(2) {
+                if segment[0] != segment[1] {
+                    routed.insert(Line::new(segment[0], segment[1]));
+                }
+            }
+            self.set_edge_shape(idx, EdgeShape::Polyline(bends(&path)));
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    pub fn route_edges_orthogonal(
+        &mut self,
+        settings: JsValue,
+    ) -> Result<(), JsValue> {
+        let settings: OrthogonalSettings = settings_from_js(&settings)?;
+        self.route_edges_orthogonal_with(settings);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{edge, graph, node};
+
+    #[test]
+    fn test_routes_around_boxes() {
+        let mut layout = graph(
+            vec![node("a", 0., 0.), node("b", 100., 0.), node("c", 50., 0.)],
+            vec![edge("a", "b")],
+        );
+        let settings = OrthogonalSettings::default();
+        layout.route_edges_orthogonal_with(settings);
+
+        let reach = settings.node_size / 2.0 + settings.margin;
+        let geo = layout.edges_geo()[0].clone();
+        assert!(!geo.is_straight());
+        for segment in geo.segments() {
+            assert!(segment.dx() == 0.0 || segment.dy() == 0.0);
+            let (lo, hi) = (
+                segment.start.x.min(segment.end.x),
+                segment.start.x.max(segment.end.x),
+            );
+            if segment.dy() == 0.0 && segment.start.y.abs() < reach {
+                assert!(hi <= 50.0 - reach || lo >= 50.0 + reach);
+            }
+        }
+    }
+
+    #[test]
+    fn test_grid_covers_only_the_window() {
+        let nodes = (0..100)
+            .map(|i| node(i, (i % 10) as f64 * 100., (i / 10) as f64 * 100.))
+            .collect();
+        let layout = graph(nodes, vec![]);
+        let settings = OrthogonalSettings::default();
+        let window =
+            AABB::from_corners(Point::new(-20., -20.), Point::new(120., 20.));
+        let grid = layout.orthogonal_grid(&window, &settings);
+        // The two nodes in the window, each with its center and box sides,
+        // and the window's own sides.
+        assert_eq!(grid.xs, vec![-20., -10., 0., 10., 90., 100., 110., 120.]);
+        assert_eq!(grid.ys, vec![-20., -10., 0., 10., 20.]);
+    }
+
+    #[test]
+    fn test_widens_the_grid_to_get_around_a_wall() {
+        let mut nodes = vec![node("a", 0., 0.), node("b", 100., 0.)];
+        nodes.extend((-10..=10).map(|i| node(i, 50., i as f64 * 10.)));
+        let mut layout = graph(nodes, vec![edge("a", "b")]);
+        let settings = OrthogonalSettings::default();
+        layout.route_edges_orthogonal_with(settings);
+
+        let reach = settings.node_size / 2.0 + settings.margin;
+        let geo = layout.edges_geo()[0].clone();
+        for segment in geo.segments() {
+            assert!(segment.dx() == 0.0 || segment.dy() == 0.0);
+            let (lo, hi) = (
+                segment.start.x.min(segment.end.x),
+                segment.start.x.max(segment.end.x),
+            );
+            if lo < 50.0 + reach && hi > 50.0 - reach {
+                assert!(segment.start.y.abs() >= 100.0 + reach);
+            }
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use crate::geometry::NodeGeo;
+use crate::layout::GraphLayout;
+use crate::packing::pack_rectangles;
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use petgraph::graph::NodeIndex;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpectralSettings {
+    /// Most power iterations run for each eigenvector.
+    pub max_iterations: usize,
+    /// An eigenvector has converged once its residual `|Mv - (v·Mv)v|`,
+    /// relative to the shift that makes `M` positive, is below this.
+    pub tolerance: f64,
+    /// Average edge length each component is scaled to.
+    pub edge_length: f64,
+    /// Space left between components.
+    pub gap: f64,
+    pub seed: u64,
+}
+
+impl Default for SpectralSettings {
+    fn default() -> Self {
+        return SpectralSettings {
+            max_iterations: 1000,
+            tolerance: 1e-6,
+            edge_length: 50.0,
+            gap: 50.0,
+            seed: 0,
+        };
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    return a.iter().zip(b).map(|(x, y)| x * y).sum();
+}
+
+fn normalize(v: &mut [f64]) {
+    let norm = dot(v, v).sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Removes the components of `v` along each of the unit vectors in
+/// `basis`.
+fn orthogonalize(v: &mut [f64], basis: &[Vec<f64>]) {
+    for b in basis {
+        let along = dot(v, b);
+        v.iter_mut().zip(b).for_each(|(x, y)| *x -= along * y);
+    }
+}
+
+/// Coordinates from the two smallest non-trivial eigenvectors of the
+/// Laplacian `L = D - A` of a connected graph given as neighbour lists.
+/// They're the largest eigenvectors of `M = cI - L`, found by power
+/// iteration while projecting out the constant vector and each earlier
+/// eigenvector. Also returns whether both converged within
+/// `max_iterations`; power iteration slows down as the gap between the
+/// eigenvalues closes, which it does in big sparse graphs.
+fn laplacian_coordinates(
+    adjacency: &[Vec<usize>],
+    settings: &SpectralSettings,
+    rng: &mut SmallRng,
+) -> (Vec<NodeGeo>, bool) {
+    let n = adjacency.len();
+    if n < 3 {
+        let points = (0..n)
+            .map(|i| Coordinate {
+                x: i as f64,
+                y: 0.0,
+            })
+            .collect();
+        return (points, true);
+    }
+    // Every Laplacian eigenvalue is at most twice the largest degree.
+    let shift =
+        2.0 * adjacency.iter().map(|a| a.len()).max().unwrap_or(0) as f64 + 1.0;
+    let apply = |v: &[f64]| -> Vec<f64> {
+        adjacency
+            .iter()
+            .enumerate()
+            .map(|(i, neighbors)| {
+                let laplacian = neighbors.len() as f64 * v[i]
+                    - neighbors.iter().map(|j| v[*j]).sum::<f64>();
+                shift * v[i] - laplacian
+            })
+            .collect()
+    };
+
+    let mut basis = vec![vec![1.0 / (n as f64).sqrt(); n]];
+    let mut converged = true;
+    for _ in 0..2 {
+        let mut v: Vec<f64> =
+            (0..n).map(|_| rng.gen_range(-1.0, 1.0)).collect();
+        orthogonalize(&mut v, &basis);
+        normalize(&mut v);
+        let mut residual = f64::INFINITY;
+        for _ in 0..settings.max_iterations {
+            let mut next = apply(&v);
+            orthogonalize(&mut next, &basis);
+            // Checked on the unnormalized product, so that eigenvalues that
+            // share an eigenspace converge as soon as `v` lies in it.
+            let rayleigh = dot(&v, &next);
+            residual = next
+                .iter()
+                .zip(v.iter())
+                .map(|(a, b)| (a - rayleigh * b).powi(2))
+                .sum::<f64>()
+                .sqrt()
+                / shift;
+            normalize(&mut next);
+            v = next;
+            if residual < settings.tolerance {
+                break;
+            }
+        }
+        converged &= residual < settings.tolerance;
+        basis.push(v);
+    }
+    let points = (0..n)
+        .map(|i| Coordinate {
+            x: basis[1][i],
+            y: basis[2][i],
+        })
+        .collect();
+    return (points, converged);
+}
+
+/// Scales coordinates so that the average edge length is `edge_length` and
+/// moves their bounding box's corner to the origin, returning its size.
+fn normalize_component(
+    points: &mut [NodeGeo],
+    adjacency: &[Vec<usize>],
+    edge_length: f64,
+) -> NodeGeo {
+    let lengths: Vec<f64> = adjacency
+        .iter()
+        .enumerate()
+        .flat_map(|(i, neighbors)| neighbors.iter().map(move |j| (i, *j)))
+        .map(|(i, j)| {
+            let d = points[i] - points[j];
+            (d.x * d.x + d.y * d.y).sqrt()
+        })
+        .collect();
+    let average = lengths.iter().sum::<f64>() / lengths.len().max(1) as f64;
+    let scale = if average > 0.0 {
+        edge_length / average
+    } else {
+        edge_length
+    };
+    let min = points.iter().fold(
+        Coordinate {
+            x: f64::MAX,
+            y: f64::MAX,
+        },
+        |m, p| Coordinate {
+            x: m.x.min(p.x),
+            y: m.y.min(p.y),
+        },
+    );
+    let mut size: NodeGeo = Coordinate { x: 0.0, y: 0.0 };
+    for p in points.iter_mut() {
+        *p = (*p - min) * scale;
+        size.x = size.x.max(p.x);
+        size.y = size.y.max(p.y);
+    }
+    return size;
+}
+
+impl GraphLayout {
+    /// Neighbour lists by position in `nodes`, ignoring self-loops and
+    /// nodes outside `nodes`.
+    pub fn adjacency(&self, nodes: &[NodeIndex]) -> Vec<Vec<usize>> {
+        let position: HashMap<NodeIndex, usize> =
+            nodes.iter().enumerate().map(|(i, n)| (*n, i)).collect();
+        return nodes
+            .iter()
+            .map(|n| {
+                let mut neighbors: Vec<usize> = self
+                    .graph
+                    .neighbors_undirected(*n)
+                    .filter(|m| m != n)
+                    .filter_map(|m| position.get(&m).copied())
+                    .collect();
+                neighbors.sort();
+                neighbors.dedup();
+                neighbors
+            })
+            .collect();
+    }
+
+    /// A spectral layout of each connected component, packed together with
+    /// `pack_rectangles`, and whether every eigenvector converged.
+    pub fn spectral_positions(
+        &self,
+        settings: &SpectralSettings,
+    ) -> (Vec<(NodeIndex, NodeGeo)>, bool) {
+        let mut rng = SmallRng::seed_from_u64(settings.seed);
+        let mut converged = true;
+        let components: Vec<(Vec<NodeIndex>, Vec<NodeGeo>, NodeGeo)> = self
+            .connected_components()
+            .into_iter()
+            .map(|nodes| {
+                let adjacency = self.adjacency(&nodes);
+                let (mut points, done) =
+                    laplacian_coordinates(&adjacency, settings, &mut rng);
+                converged &= done;
+                let size = normalize_component(
+                    &mut points,
+                    &adjacency,
+                    settings.edge_length,
+                );
+                (nodes, points, size)
+            })
+            .collect();
+        let sizes: Vec<NodeGeo> =
+            components.iter().map(|(_, _, size)| *size).collect();
+        let corners = pack_rectangles(&sizes, settings.gap, 1.0);
+        let result = components
+            .into_iter()
+            .zip(corners)
+            .flat_map(|((nodes, points, _), corner)| {
+                nodes
+                    .into_iter()
+                    .zip(points.into_iter().map(move |p| p + corner))
+            })
+            .collect();
+        return (result, converged);
+    }
+
+    /// Returns false if the eigenvectors hadn't converged after
+    /// `max_iterations`, in which case the layout is only approximate.
+    pub fn spectral_layout_with(
+        &mut self,
+        settings: &SpectralSettings,
+    ) -> bool {
+        let (positions, converged) = self.spectral_positions(settings);
+        for (idx, position) in positions {
+            self.place_node(idx, position);
+        }
+        return converged;
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Moves every node to its spectral layout position, as one undo step.
+    /// A good starting point for the optimizers. Returns false if the power
+    /// iteration didn't converge; more `max_iterations` or a looser
+    /// `tolerance` may help.
+    pub fn spectral_layout(
+        &mut self,
+        settings: JsValue,
+    ) -> Result<bool, JsValue> {
+        let settings: SpectralSettings = settings_from_js(&settings)?;
+        self.checkpoint_with_label("spectral layout");
+        return Ok(self.spectral_layout_with(&settings));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::EdgeData;
+    use crate::test_utils::{edge, graph, id, node};
+
+    #[test]
+    fn test_spectral_layout_of_components() {
+        // An 8-cycle, a path of three and an isolated node.
+        let mut edges: Vec<EdgeData> =
+            (0..8).map(|i| edge(i, (i + 1) % 8)).collect();
+        edges.push(edge(8, 9));
+        edges.push(edge(9, 10));
+        let mut layout =
+            graph((0..12).map(|i| node(i, 0.0, 0.0)).collect(), edges);
+        assert!(layout.spectral_layout_with(&SpectralSettings::default()));
+
+        let at = |i: usize| {
+            let idx = layout.node_index(&id(i)).unwrap();
+            *layout.node_geo(idx).unwrap()
+        };
+        let distance = |a: NodeGeo, b: NodeGeo| {
+            ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+        };
+        // The cycle comes out as a regular octagon.
+        let sides: Vec<f64> =
+            (0..8).map(|i| distance(at(i), at((i + 1) % 8))).collect();
+        for side in sides.iter() {
+            assert!((side - 50.0).abs() < 1e-3);
+        }
+        // Nodes in different components don't land on each other.
+        for i in 0..12 {
+            for j in i + 1..12 {
+                assert!(distance(at(i), at(j)) > 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_power_iteration_reports_convergence() {
+        // A long path has nearly equal small eigenvalues, so a handful of
+        // iterations can't separate them.
+        let adjacency: Vec<Vec<usize>> = (0..200)
+            .map(|i| {
+                let mut neighbors = vec![];
+                if i > 0 {
+                    neighbors.push(i - 1);
+                }
+                if i < 199 {
+                    neighbors.push(i + 1);
+                }
+                neighbors
+            })
+            .collect();
+        let run = |max_iterations| {
+            let settings = SpectralSettings {
+                max_iterations: max_iterations,
+                ..SpectralSettings::default()
+            };
+            let mut rng = SmallRng::seed_from_u64(0);
+            laplacian_coordinates(&adjacency, &settings, &mut rng).1
+        };
+        assert!(!run(10));
+        let settings = SpectralSettings::default();
+        let cycle: Vec<Vec<usize>> =
+            (0..8).map(|i| vec![(i + 7) % 8, (i + 1) % 8]).collect();
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert!(laplacian_coordinates(&cycle, &settings, &mut rng).1);
+    }
+}
@@ -0,0 +1,696 @@
+use crate::geometry::{NodeGeo, MIN_DISTANCE};
+use crate::layout::{GraphLayout, NodeDataId};
+use crate::optimize::objective::LayoutObjective;
+use crate::utils::settings_from_js;
+use geo::Coordinate;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+
+/// Where the permutation a symmetric layout follows comes from.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SymmetrySource {
+    /// Sends each node's pitch-class set to its transposition.
+    Transposition { semitones: usize },
+    /// Sends each listed node to the other; unlisted nodes stay put.
+    Permutation { pairs: Vec<(String, String)> },
+    /// Searches the graph for an automorphism, drawable as a rotation, of
+    /// the largest order it can find.
+    Automorphism,
+}
+
+impl Default for SymmetrySource {
+    fn default() -> Self {
+        return SymmetrySource::Automorphism;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SymmetrySettings {
+    pub source: SymmetrySource,
+    /// How far from the centre to put orbits that would otherwise collapse
+    /// onto it.
+    pub radius: f64,
+}
+
+impl Default for SymmetrySettings {
+    fn default() -> Self {
+        return SymmetrySettings {
+            source: SymmetrySource::default(),
+            radius: 100.0,
+        };
+    }
+}
+
+/// A graph automorphism drawn as a rotation: the `j`th node along an orbit
+/// of `m` nodes sits at its orbit's first node turned by `j / m` of a full
+/// turn about `center`. For that to be one rotation, every orbit of more
+/// than one node has the same size `m`, and the one node it may fix sits on
+/// `center`.
+#[derive(Clone, Debug)]
+pub struct Symmetry {
+    pub orbits: Vec<Vec<NodeIndex>>,
+    pub center: NodeGeo,
+    orbit_of: HashMap<NodeIndex, (usize, usize)>,
+}
+
+fn rotate(p: NodeGeo, center: NodeGeo, angle: f64) -> NodeGeo {
+    let (sin, cos) = angle.sin_cos();
+    let d = p - center;
+    return Coordinate {
+        x: center.x + d.x * cos - d.y * sin,
+        y: center.y + d.x * sin + d.y * cos,
+    };
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    return if b == 0 { a } else { gcd(b, a % b) };
+}
+
+/// Whether a permutation with cycles of these lengths can be drawn as a
+/// rotation: its non-trivial cycles all have one length and it fixes at
+/// most one point, unless it fixes everything.
+fn rotational(lengths: &[usize]) -> bool {
+    let moved: Vec<usize> =
+        lengths.iter().copied().filter(|l| *l > 1).collect();
+    let fixed = lengths.len() - moved.len();
+    return moved.is_empty()
+        || (fixed <= 1 && moved.iter().all(|l| *l == moved[0]));
+}
+
+impl Symmetry {
+    /// The symmetry of `permutation` about `center`, if it can be drawn as
+    /// a rotation.
+    pub fn new(
+        permutation: &HashMap<NodeIndex, NodeIndex>,
+        center: NodeGeo,
+    ) -> Option<Symmetry> {
+        let mut nodes: Vec<NodeIndex> = permutation.keys().copied().collect();
+        nodes.sort();
+        let mut orbit_of = HashMap::new();
+        let mut orbits = vec![];
+        for start in nodes {
+            if orbit_of.contains_key(&start) {
+                continue;
+            }
+            let mut orbit = vec![start];
+            let mut next = permutation[&start];
+            while next != start {
+                orbit.push(next);
+                next = permutation.get(&next).copied().unwrap_or(start);
+            }
+            for (j, idx) in orbit.iter().enumerate() {
+                orbit_of.insert(*idx, (orbits.len(), j));
+            }
+            orbits.push(orbit);
+        }
+        let lengths: Vec<usize> = orbits.iter().map(|o| o.len()).collect();
+        if !rotational(&lengths) {
+            return None;
+        }
+        return Some(Symmetry {
+            orbits: orbits,
+            center: center,
+            orbit_of: orbit_of,
+        });
+    }
+
+    /// Least common multiple of the orbit sizes.
+    pub fn order(&self) -> usize {
+        return self
+            .orbits
+            .iter()
+            .fold(1, |l, o| l / gcd(l, o.len()) * o.len());
+    }
+
+    /// Where every node of `idx`'s orbit goes when `idx` moves to
+    /// `position`.
+    pub fn orbit_positions(
+        &self,
+        idx: NodeIndex,
+        position: NodeGeo,
+    ) -> Vec<(NodeIndex, NodeGeo)> {
+        let (o, j) = match self.orbit_of.get(&idx) {
+            Some(found) => *found,
+            None => return vec![(idx, position)],
+        };
+        let orbit = &self.orbits[o];
+        if orbit.len() == 1 && self.order() > 1 {
+            // The only point a rotation leaves in place.
+            return vec![(idx, self.center)];
+        }
+        let step = 2.0 * PI / orbit.len() as f64;
+        let first = rotate(position, self.center, -step * j as f64);
+        return orbit
+            .iter()
+            .enumerate()
+            .map(|(k, n)| (*n, rotate(first, self.center, step * k as f64)))
+            .collect();
+    }
+}
+
+/// Colours nodes by degree, then repeatedly by their colour together with
+/// their neighbours' colours, until no class splits further. Automorphisms
+/// only ever map nodes to nodes of the same colour.
+fn refine_colors(adjacency: &[Vec<usize>]) -> Vec<usize> {
+    let mut colors: Vec<usize> = adjacency.iter().map(|a| a.len()).collect();
+    let mut classes = 0;
+    loop {
+        let signatures: Vec<(usize, Vec<usize>)> = adjacency
+            .iter()
+            .enumerate()
+            .map(|(v, neighbors)| {
+                let mut around: Vec<usize> =
+                    neighbors.iter().map(|w| colors[*w]).collect();
+                around.sort();
+                (colors[v], around)
+            })
+            .collect();
+        let mut distinct = signatures.clone();
+        distinct.sort();
+        distinct.dedup();
+        colors = signatures
+            .iter()
+            .map(|s| distinct.binary_search(s).unwrap())
+            .collect();
+        if distinct.len() == classes {
+            return colors;
+        }
+        classes = distinct.len();
+    }
+}
+
+/// Backtracking state for extending a partial map into an automorphism.
+struct Search<'a> {
+    adjacency: &'a [Vec<usize>],
+    neighbors: Vec<HashSet<usize>>,
+    colors: Vec<usize>,
+    order: Vec<usize>,
+    image: Vec<Option<usize>>,
+    used: Vec<bool>,
+    /// Candidate checks left before giving up.
+    budget: usize,
+}
+
+impl<'a> Search<'a> {
+    fn extend(&mut self, depth: usize) -> bool {
+        if depth == self.order.len() {
+            return true;
+        }
+        let v = self.order[depth];
+        if self.image[v].is_some() {
+            return self.extend(depth + 1);
+        }
+        for u in 0..self.adjacency.len() {
+            if self.used[u] || self.colors[u] != self.colors[v] {
+                continue;
+            }
+            if self.budget == 0 {
+                return false;
+            }
+            self.budget -= 1;
+            // Mapped neighbours of v must land on neighbours of u, and u
+            // can't have more mapped-to neighbours than v has mapped
+            // neighbours.
+            let mapped: Vec<usize> = self.adjacency[v]
+                .iter()
+                .filter_map(|w| self.image[*w])
+                .collect();
+            let consistent = mapped
+                .iter()
+                .all(|x| self.neighbors[u].contains(x))
+                && self.adjacency[u].iter().filter(|x| self.used[**x]).count()
+                    == mapped.len();
+            if !consistent {
+                continue;
+            }
+            self.image[v] = Some(u);
+            self.used[u] = true;
+            if self.extend(depth + 1) {
+                return true;
+            }
+            self.image[v] = None;
+            self.used[u] = false;
+        }
+        return false;
+    }
+}
+
+/// A non-trivial automorphism of the graph given by neighbour lists that
+/// can be drawn as a rotation, the one of largest order among those sending
+/// the first node of the first ambiguous colour class to each other member
+/// of it.
+pub fn find_automorphism(adjacency: &[Vec<usize>]) -> Option<Vec<usize>> {
+    let n = adjacency.len();
+    let colors = refine_colors(adjacency);
+    let mut class_size = HashMap::new();
+    for c in colors.iter() {
+        *class_size.entry(*c).or_insert(0) += 1;
+    }
+    let start = (0..n).find(|v| class_size[&colors[*v]] > 1)?;
+    let neighbors: Vec<HashSet<usize>> = adjacency
+        .iter()
+        .map(|a| a.iter().copied().collect())
+        .collect();
+
+    // Breadth-first from `start`, so each node is constrained by mapped
+    // neighbours as early as possible.
+    let mut order = vec![];
+    let mut seen = vec![false; n];
+    for root in std::iter::once(start).chain(0..n) {
+        if seen[root] {
+            continue;
+        }
+        seen[root] = true;
+        let mut queue = VecDeque::from(vec![root]);
+        while let Some(v) = queue.pop_front() {
+            order.push(v);
+            for w in adjacency[v].iter() {
+                if !seen[*w] {
+                    seen[*w] = true;
+                    queue.push_back(*w);
+                }
+            }
+        }
+    }
+
+    let cycle_lengths = |map: &[usize]| {
+        let mut visited = vec![false; n];
+        let mut lengths = vec![];
+        for v in 0..n {
+            let mut length = 0;
+            let mut w = v;
+            while !visited[w] {
+                visited[w] = true;
+                w = map[w];
+                length += 1;
+            }
+            if length > 0 {
+                lengths.push(length);
+            }
+        }
+        lengths
+    };
+    let mut best: Option<(usize, Vec<usize>)> = None;
+    for target in (0..n).filter(|u| *u != start && colors[*u] == colors[start])
+    {
+        let mut search = Search {
+            adjacency: adjacency,
+            neighbors: neighbors.clone(),
+            colors: colors.clone(),
+            order: order.clone(),
+            image: vec![None; n],
+            used: vec![false; n],
+            budget: 100 * n + 1000,
+        };
+        search.image[start] = Some(target);
+        search.used[target] = true;
+        if !search.extend(0) {
+            continue;
+        }
+        let map: Vec<usize> =
+            search.image.into_iter().map(|u| u.unwrap()).collect();
+        let lengths = cycle_lengths(&map);
+        if !rotational(&lengths) {
+            continue;
+        }
+        let found = lengths.iter().fold(1, |l, m| l / gcd(l, *m) * m);
+        if best.as_ref().map_or(true, |(o, _)| found > *o) {
+            best = Some((found, map));
+        }
+    }
+    return best.map(|(_, map)| map);
+}
+
+impl GraphLayout {
+    /// The permutation `source` describes, if it's an automorphism of the
+    /// graph.
+    pub fn symmetry_permutation(
+        &self,
+        source: &SymmetrySource,
+    ) -> Option<HashMap<NodeIndex, NodeIndex>> {
+        let nodes: Vec<NodeIndex> = self.graph.node_indices().collect();
+        let permutation: HashMap<NodeIndex, NodeIndex> = match source {
+            SymmetrySource::Transposition { semitones } => {
+                let by_set: HashMap<_, NodeIndex> = nodes
+                    .iter()
+                    .filter_map(|idx| Some((self.pitch_class_set(*idx)?, *idx)))
+                    .collect();
+                nodes
+                    .iter()
+                    .map(|idx| {
+                        let set = self.pitch_class_set(*idx)?;
+                        Some((*idx, *by_set.get(&set.transpose(*semitones))?))
+                    })
+                    .collect::<Option<_>>()?
+            }
+            SymmetrySource::Permutation { pairs } => {
+                let mut permutation: HashMap<NodeIndex, NodeIndex> =
+                    nodes.iter().map(|idx| (*idx, *idx)).collect();
+                for (from, to) in pairs.iter() {
+                    let index = |id: &str| {
+                        self.node_index(&NodeDataId::from_str_truncate(id))
+                    };
+                    permutation.insert(index(from)?, index(to)?);
+                }
+                permutation
+            }
+            SymmetrySource::Automorphism => {
+                let map = find_automorphism(&self.adjacency(&nodes))?;
+                map.into_iter()
+                    .enumerate()
+                    .map(|(v, u)| (nodes[v], nodes[u]))
+                    .collect()
+            }
+        };
+
+        let images: HashSet<NodeIndex> =
+            permutation.values().copied().collect();
+        if images.len() != nodes.len() {
+            return None;
+        }
+        let edges: HashSet<(NodeIndex, NodeIndex)> = self
+            .segment_edge_indices()
+            .filter_map(|e| self.graph.edge_endpoints(e))
+            .flat_map(|(a, b)| vec![(a, b), (b, a)])
+            .collect();
+        let preserved = edges
+            .iter()
+            .all(|(a, b)| edges.contains(&(permutation[a], permutation[b])));
+        return if preserved { Some(permutation) } else { None };
+    }
+
+    /// Makes the layout symmetric under `symmetry`: each orbit's first node
+    /// goes to the average of its members' positions turned back onto it,
+    /// and the rest follow by rotation. Until cleared, the optimizers move
+    /// whole orbits, so they only search one fundamental domain. Not for
+    /// grid mode, where `symmetry_with` finds nothing.
+    pub fn apply_symmetry(&mut self, symmetry: Symmetry, radius: f64) {
+        let center = symmetry.center;
+        let count = symmetry.orbits.len().max(1) as f64;
+        for (o, orbit) in symmetry.orbits.iter().enumerate() {
+            let step = 2.0 * PI / orbit.len() as f64;
+            let sum = orbit.iter().enumerate().fold(
+                Coordinate { x: 0.0, y: 0.0 },
+                |sum, (j, idx)| match self.node_geo(*idx) {
+                    Some(p) => sum + rotate(*p, center, -step * j as f64),
+                    None => sum,
+                },
+            );
+            let mut first = sum / orbit.len() as f64;
+            let d = first - center;
+            if orbit.len() > 1 && (d.x * d.x + d.y * d.y).sqrt() < MIN_DISTANCE
+            {
+                // Spread collapsed orbits across the fundamental domain.
+                let angle = step * o as f64 / count;
+                first = Coordinate {
+                    x: center.x
+                        + radius * (1.0 + o as f64 / count) * angle.cos(),
+                    y: center.y
+                        + radius * (1.0 + o as f64 / count) * angle.sin(),
+                };
+            }
+            for (idx, position) in symmetry.orbit_positions(orbit[0], first) {
+                self.set_node_geo(idx, position);
+            }
+        }
+        self.symmetry = Some(symmetry);
+    }
+
+    /// The symmetry `settings` describes, if its permutation is an
+    /// automorphism that can be drawn as a rotation. Rotated grid cells
+    /// don't land on grid cells, so there's none in grid mode.
+    pub fn symmetry_with(
+        &self,
+        settings: &SymmetrySettings,
+    ) -> Option<Symmetry> {
+        if self.grid_power().is_some() {
+            return None;
+        }
+        let permutation = self.symmetry_permutation(&settings.source)?;
+        let center = self.placement_center(&HashSet::new());
+        return Symmetry::new(&permutation, center);
+    }
+
+    pub fn symmetric_layout_with(
+        &mut self,
+        settings: &SymmetrySettings,
+    ) -> bool {
+        let symmetry = match self.symmetry_with(settings) {
+            Some(symmetry) => symmetry,
+            None => return false,
+        };
+        self.apply_symmetry(symmetry, settings.radius);
+        return true;
+    }
+
+    /// The moves `move_node` makes: just this one, or its whole orbit's if
+    /// a symmetry is set.
+    pub fn orbit_moves(
+        &self,
+        idx: NodeIndex,
+        position: NodeGeo,
+    ) -> Vec<(NodeIndex, NodeGeo)> {
+        return match &self.symmetry {
+            Some(symmetry) => symmetry.orbit_positions(idx, position),
+            None => vec![(idx, position)],
+        };
+    }
+
+    /// Moves the node, along with the rest of its orbit if a symmetry is
+    /// set, through `place_nodes_at`.
+    pub fn move_node(&mut self, idx: NodeIndex, position: NodeGeo) {
+        let moves = self.orbit_moves(idx, position);
+        self.place_nodes_at(moves);
+    }
+
+    /// Like `objective_delta`, but for `move_node`. With a symmetry set the
+    /// orbit is moved one node at a time, summing the changes, and then put
+    /// back. That's never in grid mode, so the nodes can go straight where
+    /// the rotation puts them.
+    pub fn move_delta(
+        &mut self,
+        objective: &LayoutObjective,
+        idx: NodeIndex,
+        position: NodeGeo,
+    ) -> f64 {
+        if self.symmetry.is_none() {
+            return self.objective_delta(objective, idx, position);
+        }
+        let moves = self.orbit_moves(idx, position);
+        let mut previous = vec![];
+        let mut delta = 0.0;
+        for (n, p) in moves {
+            if let Some(before) = self.node_geo(n).copied() {
+                delta += self.objective_delta(objective, n, p);
+                previous.push((n, before));
+                self.set_node_geo(n, p);
+            }
+        }
+        for (n, before) in previous.into_iter().rev() {
+            self.set_node_geo(n, before);
+        }
+        return delta;
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Lays the graph out symmetrically under a transposition, a given
+    /// permutation or a detected automorphism, as one undo step. Returns
+    /// false, changing nothing, if the permutation isn't an automorphism
+    /// or can't be drawn as a rotation.
+    pub fn symmetric_layout(
+        &mut self,
+        settings: JsValue,
+    ) -> Result<bool, JsValue> {
+        let settings: SymmetrySettings = settings_from_js(&settings)?;
+        let symmetry = match self.symmetry_with(&settings) {
+            Some(symmetry) => symmetry,
+            None => return Ok(false),
+        };
+        self.checkpoint_with_label("symmetric layout");
+        self.apply_symmetry(symmetry, settings.radius);
+        return Ok(true);
+    }
+
+    /// Lets the optimizers move nodes independently again.
+    pub fn clear_symmetry(&mut self) {
+        self.symmetry = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::LayoutOptions;
+    use crate::optimize::{DescentSettings, Optimizer, OptimizerSettings};
+    use crate::pitch::PitchClassSet;
+    use crate::voice_leading::{voice_leading_graph, VoiceLeadingSettings};
+
+    #[test]
+    fn test_automorphism_of_cycle() {
+        let adjacency: Vec<Vec<usize>> =
+            (0..6).map(|i| vec![(i + 5) % 6, (i + 1) % 6]).collect();
+        let map = find_automorphism(&adjacency).unwrap();
+        assert!(map.iter().enumerate().any(|(v, u)| v != *u));
+        for (v, neighbors) in adjacency.iter().enumerate() {
+            for w in neighbors {
+                assert!(adjacency[map[v]].contains(&map[*w]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_transposition_symmetric_layout() {
+        let mut data = voice_leading_graph(&VoiceLeadingSettings {
+            cardinality: 3,
+            set_class: PitchClassSet::parse("0,1,2"),
+            inversion: true,
+            max_distance: 3,
+        });
+        // Lopsided starting positions.
+        for (i, node) in data.nodes.iter_mut().enumerate() {
+            node.x = Some((i * 37 % 11) as f64 * 10.0);
+            node.y = Some((i * 17 % 13) as f64 * 3.0);
+        }
+        let mut layout =
+            GraphLayout::from_graph_data(data, LayoutOptions::default());
+        let transpose = SymmetrySettings {
+            source: SymmetrySource::Transposition { semitones: 1 },
+            ..SymmetrySettings::default()
+        };
+        assert!(layout.symmetric_layout_with(&transpose));
+        // Swapping two clusters a minor third apart isn't an automorphism.
+        let wrong = SymmetrySource::Permutation {
+            pairs: vec![
+                ("0,1,2".to_string(), "3,4,5".to_string()),
+                ("3,4,5".to_string(), "0,1,2".to_string()),
+            ],
+        };
+        assert!(layout.symmetry_permutation(&wrong).is_none());
+
+        let check = |layout: &GraphLayout| {
+            let symmetry = layout.symmetry.as_ref().unwrap();
+            assert_eq!(symmetry.order(), 12);
+            for orbit in symmetry.orbits.iter() {
+                let first = *layout.node_geo(orbit[0]).unwrap();
+                for (idx, p) in symmetry.orbit_positions(orbit[0], first) {
+                    let actual = layout.node_geo(idx).unwrap();
+                    assert!((actual.x - p.x).abs() < 1e-6);
+                    assert!((actual.y - p.y).abs() < 1e-6);
+                }
+            }
+        };
+        check(&layout);
+
+        let mut optimizer = Optimizer::new(
+            OptimizerSettings::Descent(DescentSettings::default()),
+            3,
+        );
+        let before = layout
+            .objective_breakdown(&LayoutObjective::default())
+            .total;
+        optimizer.run(&mut layout, 200, |_| true);
+        check(&layout);
+        let after = layout
+            .objective_breakdown(&LayoutObjective::default())
+            .total;
+        assert!(after <= before + 1e-9);
+    }
+
+    #[test]
+    fn test_no_symmetry_on_a_grid() {
+        use crate::test_utils::{edge, graph, node};
+
+        let mut layout = graph(
+            (0..4).map(|i| node(i, i as f64 * 10.0, 0.0)).collect(),
+            (0..4).map(|i| edge(i, (i + 1) % 4)).collect(),
+        );
+        let turn = SymmetrySettings {
+            source: SymmetrySource::Permutation {
+                pairs: (0..4)
+                    .map(|i| (i.to_string(), ((i + 1) % 4).to_string()))
+                    .collect(),
+            },
+            ..SymmetrySettings::default()
+        };
+        assert!(layout.symmetric_layout_with(&turn));
+        // Snapping drops the symmetry, and it can't be set again.
+        layout.snap_to_grid(-2);
+        assert!(layout.symmetry.is_none());
+        assert!(!layout.symmetric_layout_with(&turn));
+        layout.leave_grid();
+        assert!(layout.symmetric_layout_with(&turn));
+    }
+
+    #[test]
+    fn test_only_rotations_are_drawn() {
+        use crate::test_utils::{edge, graph, id, node};
+
+        // A star on 0 with leaves 1, 2, 3, next to a lone edge 4-5.
+        let mut layout = graph(
+            (0..6)
+                .map(|i| node(i, i as f64 * 10.0, (i * i) as f64))
+                .collect(),
+            vec![edge(0, 1), edge(0, 2), edge(0, 3), edge(4, 5)],
+        );
+        let permutation = |cycles: Vec<Vec<usize>>| {
+            let mut pairs = vec![];
+            for cycle in cycles {
+                for k in 0..cycle.len() {
+                    pairs.push((
+                        cycle[k].to_string(),
+                        cycle[(k + 1) % cycle.len()].to_string(),
+                    ));
+                }
+            }
+            SymmetrySettings {
+                source: SymmetrySource::Permutation { pairs: pairs },
+                ..SymmetrySettings::default()
+            }
+        };
+        // Orbits of three and two nodes make no single rotation.
+        let mixed = permutation(vec![vec![1, 2, 3], vec![4, 5]]);
+        assert!(layout.symmetry_permutation(&mixed.source).is_some());
+        assert!(!layout.symmetric_layout_with(&mixed));
+        // Turning only the leaves fixes 0, 4 and 5.
+        let leaves = permutation(vec![vec![1, 2, 3]]);
+        assert!(!layout.symmetric_layout_with(&leaves));
+
+        // Without the lone edge the star's centre is the only fixed point,
+        // and it goes to the centre of the rotation.
+        let star = |i: usize| layout.node_index(&id(i)).unwrap();
+        let permutation: HashMap<NodeIndex, NodeIndex> =
+            vec![(0, 0), (1, 2), (2, 3), (3, 1)]
+                .into_iter()
+                .map(|(a, b)| (star(a), star(b)))
+                .collect();
+        let center = Coordinate { x: 5.0, y: 5.0 };
+        let symmetry = Symmetry::new(&permutation, center).unwrap();
+        assert_eq!(symmetry.order(), 3);
+        let moved = Coordinate { x: 50.0, y: 0.0 };
+        assert_eq!(
+            symmetry.orbit_positions(star(0), moved),
+            vec![(star(0), center)]
+        );
+
+        // Detected automorphisms are always drawable. With the lone edge
+        // there's none: every automorphism fixes it or swaps its ends.
+        let mut adjacency =
+            vec![vec![1, 2, 3], vec![0], vec![0], vec![0], vec![5], vec![4]];
+        assert!(find_automorphism(&adjacency).is_none());
+        adjacency.truncate(4);
+        let map = find_automorphism(&adjacency).unwrap();
+        let permutation: HashMap<NodeIndex, NodeIndex> = map
+            .iter()
+            .enumerate()
+            .map(|(v, u)| (NodeIndex::new(v), NodeIndex::new(*u)))
+            .collect();
+        assert!(Symmetry::new(&permutation, center).is_some());
+    }
+}
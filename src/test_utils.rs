@@ -0,0 +1,54 @@
+//! Graph fixtures for the unit tests.
+
+use crate::layout::{
+    EdgeData, GraphData, GraphLayout, LayoutOptions, NodeData, NodeDataId,
+};
+
+pub fn id<T: ToString>(name: T) -> NodeDataId {
+    return NodeDataId::from_str_truncate(&name.to_string());
+}
+
+/// A node placed at `(x, y)`.
+pub fn node<T: ToString>(name: T, x: f64, y: f64) -> NodeData {
+    return NodeData {
+        id: id(name),
+        x: Some(x),
+        y: Some(y),
+        ..NodeData::default()
+    };
+}
+
+/// A node without a position, left to the placement strategy.
+pub fn unplaced<T: ToString>(name: T) -> NodeData {
+    return NodeData {
+        id: id(name),
+        ..NodeData::default()
+    };
+}
+
+pub fn edge<T: ToString>(from: T, to: T) -> EdgeData {
+    return EdgeData {
+        from: id(from),
+        to: id(to),
+        ..EdgeData::default()
+    };
+}
+
+/// A layout of these nodes and edges with the default options.
+pub fn graph(nodes: Vec<NodeData>, edges: Vec<EdgeData>) -> GraphLayout {
+    return graph_with(nodes, edges, LayoutOptions::default());
+}
+
+pub fn graph_with(
+    nodes: Vec<NodeData>,
+    edges: Vec<EdgeData>,
+    options: LayoutOptions,
+) -> GraphLayout {
+    return GraphLayout::from_graph_data(
+        GraphData {
+            nodes: nodes,
+            edges: edges,
+        },
+        options,
+    );
+}
@@ -0,0 +1,352 @@
+use crate::geometry::{EdgeGeo, NodeGeo};
+use crate::history::Snapshot;
+use crate::intersections::crosses;
+use crate::layout::GraphLayout;
+use crate::utils::settings_from_js;
+use im::OrdMap;
+use js_sys::Float64Array;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    Linear,
+    EaseInOut,
+    /// Moves nodes a group at a time, in an order chosen so that each group
+    /// arriving doesn't add crossings.
+    Staged,
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        return Easing::EaseInOut;
+    }
+}
+
+fn ease_in_out(t: f64) -> f64 {
+    if t < 0.5 {
+        return 4.0 * t * t * t;
+    }
+    return 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0;
+}
+
+#[derive(Clone, Debug)]
+pub struct Transition {
+    from: OrdMap<NodeIndex, NodeGeo>,
+    to: OrdMap<NodeIndex, NodeGeo>,
+    frames: usize,
+    easing: Easing,
+    /// Which stage each moving node belongs to, and how many stages there
+    /// are. Only used by `Easing::Staged`.
+    stage_of: OrdMap<NodeIndex, usize>,
+    stages: usize,
+    frame: usize,
+}
+
+impl Transition {
+    pub fn frames(&self) -> usize {
+        return self.frames;
+    }
+
+    fn progress(&self, idx: NodeIndex, frame: usize) -> f64 {
+        let t = (frame as f64 / self.frames.max(1) as f64).min(1.0);
+        return match self.easing {
+            Easing::Linear => t,
+            Easing::EaseInOut => ease_in_out(t),
+            Easing::Staged => {
+                let stage = self.stage_of.get(&idx).copied().unwrap_or(0);
+                let local = t * self.stages as f64 - stage as f64;
+                ease_in_out(local.max(0.0).min(1.0))
+            }
+        };
+    }
+
+    /// Positions of every node at `frame`, from 0 (where the transition
+    /// started) to `frames` (the target).
+    pub fn positions_at(&self, frame: usize) -> OrdMap<NodeIndex, NodeGeo> {
+        return self
+            .from
+            .iter()
+            .map(|(idx, from)| match self.to.get(idx) {
+                // Lands exactly on the target, whatever the rounding.
+                Some(to) if self.progress(*idx, frame) >= 1.0 => (*idx, *to),
+                Some(to) => {
+                    (*idx, *from + (*to - *from) * self.progress(*idx, frame))
+                }
+                None => (*idx, *from),
+            })
+            .collect();
+    }
+}
+
+impl GraphLayout {
+    fn crossings_among(
+        &self,
+        positions: &OrdMap<NodeIndex, NodeGeo>,
+        edges: &[EdgeIndex],
+        others: &[EdgeIndex],
+    ) -> usize {
+        let geo = |e: EdgeIndex| {
+            let (a, b) = self.graph.edge_endpoints(e)?;
+            Some((
+                (a, b),
+                EdgeGeo::new(*positions.get(&a)?, *positions.get(&b)?),
+            ))
+        };
+        let others: Vec<((NodeIndex, NodeIndex), EdgeGeo)> =
+            others.iter().filter_map(|e| geo(*e)).collect();
+        return edges
+            .iter()
+            .filter_map(|e| geo(*e))
+            .map(|((a, b), edge)| {
+                others
+                    .iter()
+                    .filter(|((c, d), _)| {
+                        a != *c && a != *d && b != *c && b != *d
+                    })
+                    .filter(|(_, other)| {
+                        crosses(&edge, other, self.grid_power())
+                    })
+                    .count()
+            })
+            .sum();
+    }
+
+    /// How many crossings the node's edges gain against `others` when it
+    /// arrives at its target.
+    fn arrival_delta(
+        &self,
+        current: &OrdMap<NodeIndex, NodeGeo>,
+        to: &OrdMap<NodeIndex, NodeGeo>,
+        idx: NodeIndex,
+        others: &[EdgeIndex],
+    ) -> i64 {
+        let incident = self.incident_edges(idx);
+        let moved = current.update(idx, to[&idx]);
+        let before = self.crossings_among(current, &incident, others);
+        let after = self.crossings_among(&moved, &incident, others);
+        return after as i64 - before as i64;
+    }
+
+    /// Groups the moving nodes into stages. Each stage greedily takes the
+    /// non-adjacent nodes whose arrival doesn't add crossings, or failing
+    /// that, the single node whose arrival adds the fewest.
+    ///
+    /// Every node is scored once up front. After each stage, neighbours of
+    /// the nodes that moved are rescored and everyone else only has the
+    /// crossings with the moved edges swapped out.
+    fn plan_stages(
+        &self,
+        from: &OrdMap<NodeIndex, NodeGeo>,
+        to: &OrdMap<NodeIndex, NodeGeo>,
+    ) -> (OrdMap<NodeIndex, usize>, usize) {
+        let edges: Vec<EdgeIndex> = self.segment_edge_indices().collect();
+        let mut current = from.clone();
+        let mut remaining: Vec<NodeIndex> = to
+            .iter()
+            .filter(|(idx, geo)| from.get(idx).map_or(false, |f| f != *geo))
+            .map(|(idx, _)| *idx)
+            .collect();
+        let mut delta: HashMap<NodeIndex, i64> = remaining
+            .iter()
+            .map(|idx| (*idx, self.arrival_delta(&current, to, *idx, &edges)))
+            .collect();
+        let mut stage_of = OrdMap::new();
+        let mut stages = 0;
+
+        while !remaining.is_empty() {
+            let mut scored: Vec<(i64, NodeIndex)> =
+                remaining.iter().map(|idx| (delta[idx], *idx)).collect();
+            scored.sort();
+
+            let mut stage: Vec<NodeIndex> = vec![];
+            let mut blocked: HashSet<NodeIndex> = HashSet::new();
+            for (delta, idx) in scored.iter() {
+                if *delta > 0 && !stage.is_empty() {
+                    break;
+                }
+                if blocked.contains(idx) {
+                    continue;
+                }
+                stage.push(*idx);
+                blocked.extend(self.graph.neighbors_undirected(*idx));
+                if *delta > 0 {
+                    break;
+                }
+            }
+            remaining.retain(|idx| !stage.contains(idx));
+
+            let mut moved_edges: Vec<EdgeIndex> = stage
+                .iter()
+                .flat_map(|idx| self.incident_edges(*idx))
+                .collect();
+            moved_edges.sort();
+            moved_edges.dedup();
+            let (near, far): (Vec<NodeIndex>, Vec<NodeIndex>) =
+                remaining.iter().partition(|idx| blocked.contains(idx));
+
+            for idx in far.iter() {
+                *delta.get_mut(idx).unwrap() -=
+                    self.arrival_delta(&current, to, *idx, &moved_edges);
+            }
+            for idx in stage.iter() {
+                current.insert(*idx, to[idx]);
+                stage_of.insert(*idx, stages);
+            }
+            for idx in far.iter() {
+                *delta.get_mut(idx).unwrap() +=
+                    self.arrival_delta(&current, to, *idx, &moved_edges);
+            }
+            for idx in near.iter() {
+                delta.insert(
+                    *idx,
+                    self.arrival_delta(&current, to, *idx, &edges),
+                );
+            }
+            stages += 1;
+        }
+        return (stage_of, stages.max(1));
+    }
+
+    pub fn plan_transition(
+        &self,
+        target: &OrdMap<NodeIndex, NodeGeo>,
+        frames: usize,
+        easing: Easing,
+    ) -> Transition {
+        let from = self.node_positions.clone();
+        let (stage_of, stages) = match easing {
+            Easing::Staged => self.plan_stages(&from, target),
+            _ => (OrdMap::new(), 1),
+        };
+        return Transition {
+            from: from,
+            to: target.clone(),
+            frames: frames,
+            easing: easing,
+            stage_of: stage_of,
+            stages: stages,
+            frame: 0,
+        };
+    }
+
+    /// Starts animating towards `target`. The whole animation is one undo
+    /// step.
+    pub fn start_transition_to(
+        &mut self,
+        target: &Snapshot,
+        frames: usize,
+        easing: Easing,
+    ) {
+        self.checkpoint_with_label(&target.label);
+        self.transition =
+            Some(self.plan_transition(&target.positions, frames, easing));
+    }
+
+    /// Moves the nodes to the next frame of the running transition,
+    /// returning false once it has finished.
+    pub fn advance_transition(&mut self) -> bool {
+        let mut transition = match self.transition.take() {
+            Some(transition) => transition,
+            None => return false,
+        };
+        // A transition of no frames still takes one to land on the target.
+        if transition.frame >= transition.frames.max(1) {
+            return false;
+        }
+        transition.frame += 1;
+        self.restore_positions(&transition.positions_at(transition.frame));
+        self.transition = Some(transition);
+        return true;
+    }
+}
+
+#[wasm_bindgen]
+impl GraphLayout {
+    /// Starts animating towards a saved snapshot; call
+    /// `next_transition_frame` once per frame to move the nodes.
+    pub fn start_transition(
+        &mut self,
+        index: usize,
+        frames: usize,
+        easing: JsValue,
+    ) -> Result<bool, JsValue> {
+        let easing: Easing = settings_from_js(&easing)?;
+        let target = match self.saved_snapshot(index) {
+            Some(snapshot) => snapshot.clone(),
+            None => return Ok(false),
+        };
+        self.start_transition_to(&target, frames, easing);
+        return Ok(true);
+    }
+
+    /// Advances the running transition by one frame and returns the new
+    /// positions, laid out like `positions()`, or nothing once it's over.
+    pub fn next_transition_frame(&mut self) -> Option<Float64Array> {
+        if !self.advance_transition() {
+            return None;
+        }
+        return Some(self.positions());
+    }
+
+    pub fn cancel_transition(&mut self) {
+        self.transition = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{edge, graph, id, node};
+    use geo::Coordinate;
+
+    #[test]
+    fn test_transitions_reach_target() {
+        let mut layout = graph(
+            vec![
+                node("a", 0., 0.),
+                node("b", 10., 0.),
+                node("c", 2., 5.),
+                node("d", 12., 5.),
+            ],
+            vec![edge("a", "b"), edge("c", "d")],
+        );
+        let start = layout.snapshot("start");
+        // Swings c-d below a-b. Moving c first would make c-d cross a-b,
+        // moving d first wouldn't.
+        let c = layout.node_index(&id("c")).unwrap();
+        let d = layout.node_index(&id("d")).unwrap();
+        layout.set_node_geo(c, Coordinate { x: 2., y: -5. });
+        layout.set_node_geo(d, Coordinate { x: 12., y: -1. });
+        let target = layout.snapshot("target");
+        layout.restore_positions(&start.positions);
+
+        let linear =
+            layout.plan_transition(&target.positions, 4, Easing::Linear);
+        assert_eq!(linear.positions_at(2)[&c], Coordinate { x: 2., y: 0. });
+        assert_eq!(linear.positions_at(4), target.positions);
+
+        layout.start_transition_to(&target, 10, Easing::Staged);
+        let mut frames = 0;
+        while layout.advance_transition() {
+            frames += 1;
+            if frames == 5 {
+                assert_eq!(layout.node_geo(c), start.positions.get(&c));
+                assert_eq!(layout.node_geo(d), target.positions.get(&d));
+                assert_eq!(layout.metrics().crossings, 0);
+            }
+        }
+        assert_eq!(frames, 10);
+        assert_eq!(layout.node_positions, target.positions);
+        assert!(layout.undo());
+        assert_eq!(layout.node_positions, start.positions);
+
+        layout.start_transition_to(&target, 0, Easing::EaseInOut);
+        assert!(layout.advance_transition());
+        assert_eq!(layout.node_positions, target.positions);
+        assert!(!layout.advance_transition());
+    }
+}
@@ -1,3 +1,6 @@
+use serde::de::DeserializeOwned;
+use wasm_bindgen::prelude::*;
+
 pub fn set_panic_hook() {
     // When the `console_error_panic_hook` feature is enabled, we can call the
     // `set_panic_hook` function at least once during initialization, and then
@@ -8,3 +11,14 @@ pub fn set_panic_hook() {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
 }
+
+/// Reads settings passed from JS. `undefined` and `null` give the defaults;
+/// anything else that doesn't deserialize is thrown back to the caller.
+pub fn settings_from_js<T: DeserializeOwned + Default>(
+    settings: &JsValue,
+) -> Result<T, JsValue> {
+    let settings: Option<T> = settings
+        .into_serde()
+        .map_err(|error| JsValue::from_str(&error.to_string()))?;
+    return Ok(settings.unwrap_or_default());
+}
@@ -0,0 +1,170 @@
+use crate::layout::{EdgeData, GraphData, NodeData, NodeDataId};
+use crate::pitch::{Intervals, PitchClassSet};
+use crate::utils::settings_from_js;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceLeadingSettings {
+    /// How many pitch classes each set has.
+    pub cardinality: usize,
+    /// Keeps only the sets in this set's class.
+    pub set_class: Option<PitchClassSet>,
+    /// Whether inversions count as the same class, both for `set_class` and
+    /// for the nodes' groups.
+    pub inversion: bool,
+    /// Largest total number of semitones the voices may move along an edge.
+    pub max_distance: usize,
+}
+
+impl Default for VoiceLeadingSettings {
+    fn default() -> Self {
+        return VoiceLeadingSettings {
+            cardinality: 7,
+            set_class: None,
+            inversion: true,
+            max_distance: 1,
+        };
+    }
+}
+
+/// A node as `voiceLeadingGraph` sends it to JS: its `NodeData` along with
+/// its set's intervals and complement.
+#[derive(Serialize)]
+struct VoiceLeadingNode {
+    #[serde(flatten)]
+    node: NodeData,
+    /// Steps between the pitch classes of `chroma`, from its lowest.
+    intervals: Option<Intervals>,
+    /// The pitch classes not in `chroma`.
+    complement: Option<PitchClassSet>,
+}
+
+#[derive(Serialize)]
+struct VoiceLeadingGraph {
+    nodes: Vec<VoiceLeadingNode>,
+    edges: Vec<EdgeData>,
+}
+
+/// Every pitch-class set of the given size, in class `set_class` if any.
+pub fn pitch_class_sets(settings: &VoiceLeadingSettings) -> Vec<PitchClassSet> {
+    let class = settings
+        .set_class
+        .map(|set| set.set_class(settings.inversion));
+    return (0..1u16 << 12)
+        .map(PitchClassSet)
+        .filter(|set| set.len() == settings.cardinality)
+        .filter(|set| match class {
+            Some(class) => set.set_class(settings.inversion) == class,
+            None => true,
+        })
+        .collect();
+}
+
+/// The graph of pitch-class sets joined by voice leadings of at most
+/// `max_distance` semitones. Nodes are identified by their pitch classes,
+/// like `"0,2,4,5,7,9,11"`, and grouped by set class.
+pub fn voice_leading_graph(settings: &VoiceLeadingSettings) -> GraphData {
+    let sets = pitch_class_sets(settings);
+    let id =
+        |set: &PitchClassSet| NodeDataId::from_str_truncate(&set.to_string());
+    let nodes = sets
+        .iter()
+        .map(|set| NodeData {
+            id: id(set),
+            x: None,
+            y: None,
+            group: Some(id(&set.set_class(settings.inversion))),
+            chroma: Some(*set),
+        })
+        .collect();
+    let mut edges = vec![];
+    for (i, a) in sets.iter().enumerate() {
+        for b in sets.iter().skip(i + 1) {
+            let distance = a.voice_leading_distance(b).unwrap_or(0);
+            if distance > 0 && distance <= settings.max_distance {
+                edges.push(EdgeData {
+                    from: id(a),
+                    to: id(b),
+                    multiplicity: None,
+                    reciprocal: None,
+                    path: None,
+                });
+            }
+        }
+    }
+    return GraphData {
+        nodes: nodes,
+        edges: edges,
+    };
+}
+
+/// Generates a voice-leading graph as `{ nodes, edges }`, ready to pass to
+/// `new GraphLayout`.
+#[wasm_bindgen(js_name = voiceLeadingGraph)]
+pub fn voice_leading_graph_data(settings: JsValue) -> Result<JsValue, JsValue> {
+    let settings: VoiceLeadingSettings = settings_from_js(&settings)?;
+    let data = voice_leading_graph(&settings);
+    let nodes = data
+        .nodes
+        .into_iter()
+        .map(|node| VoiceLeadingNode {
+            node: node,
+            intervals: node.chroma.map(|set| set.intervals()),
+            complement: node.chroma.map(|set| set.complement()),
+        })
+        .collect();
+    return Ok(JsValue::from_serde(&VoiceLeadingGraph {
+        nodes: nodes,
+        edges: data.edges,
+    })
+    .unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diatonic_voice_leading_graph() {
+        let major = PitchClassSet::parse("0,2,4,5,7,9,11").unwrap();
+        assert_eq!(major.intervals().steps(), vec![2, 2, 1, 2, 2, 2, 1]);
+        assert_eq!(major.complement().to_string(), "1,3,6,8,10");
+        assert_eq!(major.transpose(7).to_string(), "0,2,4,6,7,9,11");
+        let g = major.transpose(7);
+        assert_eq!(major.voice_leading_distance(&g), Some(1));
+        // Wrapping round the octave: 11 to 0 is one semitone.
+        let (a, b) = (
+            PitchClassSet::parse("0,4,7").unwrap(),
+            PitchClassSet::parse("11,4,7").unwrap(),
+        );
+        assert_eq!(a.voice_leading_distance(&b), Some(1));
+
+        // The twelve diatonic scales form a cycle of fifths.
+        let data = voice_leading_graph(&VoiceLeadingSettings {
+            set_class: Some(major),
+            ..VoiceLeadingSettings::default()
+        });
+        assert_eq!(data.nodes.len(), 12);
+        assert_eq!(data.edges.len(), 12);
+        assert!(data.nodes.iter().all(|n| n.group == data.nodes[0].group));
+
+        // All 792 heptachords. In the major scale each voice next to one of
+        // the five missing pitch classes can step into it: ten ways.
+        let data = voice_leading_graph(&VoiceLeadingSettings::default());
+        assert_eq!(data.nodes.len(), 792);
+        let node = data
+            .nodes
+            .iter()
+            .find(|n| n.id.as_str() == "0,2,4,5,7,9,11")
+            .unwrap();
+        assert_eq!(node.chroma, Some(major));
+        let degree = data
+            .edges
+            .iter()
+            .filter(|e| e.from == node.id || e.to == node.id)
+            .count();
+        assert_eq!(degree, 10);
+    }
+}
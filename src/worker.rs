@@ -0,0 +1,343 @@
+use crate::layout::{
+    EdgeData, GraphData, GraphLayout, LayoutOptions, NodeData,
+};
+use crate::optimize::{LayoutMetrics, Optimizer, OptimizerSettings};
+use crate::spectral::SpectralSettings;
+use js_sys::{Array, Float64Array, Reflect};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{DedicatedWorkerGlobalScope, MessageEvent};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionSettings {
+    pub optimizer: OptimizerSettings,
+    pub seed: u64,
+    /// Iterations to run before finishing, or 0 to run until cancelled.
+    pub iterations: usize,
+    /// Iterations run between checks for incoming messages.
+    pub iterations_per_tick: usize,
+    /// Ticks between progress reports.
+    pub report_every: usize,
+    /// Send positions as a transferred `Float64Array` of `x, y` pairs, in
+    /// the order the nodes were given, instead of a list of nodes.
+    pub transfer_positions: bool,
+    /// Start from a spectral layout instead of the given positions.
+    pub spectral_start: bool,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        return SessionSettings {
+            optimizer: OptimizerSettings::default(),
+            seed: 0,
+            iterations: 0,
+            iterations_per_tick: 100,
+            report_every: 1,
+            transfer_positions: false,
+            spectral_start: false,
+        };
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SessionRequest {
+    Start {
+        nodes: Vec<NodeData>,
+        edges: Vec<EdgeData>,
+        #[serde(default)]
+        options: LayoutOptions,
+        #[serde(default)]
+        settings: SessionSettings,
+    },
+    Pause,
+    Resume,
+    Cancel,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SessionReport {
+    Ready,
+    Progress {
+        iteration: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nodes: Option<Vec<NodeData>>,
+        metrics: LayoutMetrics,
+    },
+    Paused {
+        iteration: usize,
+    },
+    Done {
+        iteration: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nodes: Option<Vec<NodeData>>,
+        metrics: LayoutMetrics,
+    },
+    Cancelled {
+        iteration: usize,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SessionState {
+    Running,
+    Paused,
+    Finished,
+}
+
+/// A layout being optimized a few iterations at a time, so that whoever
+/// drives it can handle messages in between.
+pub struct LayoutSession {
+    pub layout: GraphLayout,
+    pub state: SessionState,
+    optimizer: Optimizer,
+    settings: SessionSettings,
+    iteration: usize,
+    ticks: usize,
+}
+
+impl LayoutSession {
+    pub fn new(
+        data: GraphData,
+        options: LayoutOptions,
+        settings: SessionSettings,
+    ) -> LayoutSession {
+        let mut layout = GraphLayout::from_graph_data(data, options);
+        if settings.spectral_start {
+            layout.spectral_layout_with(&SpectralSettings {
+                seed: settings.seed,
+                ..SpectralSettings::default()
+            });
+        }
+        return LayoutSession {
+            layout: layout,
+            state: SessionState::Running,
+            optimizer: Optimizer::new(settings.optimizer, settings.seed),
+            settings: settings,
+            iteration: 0,
+            ticks: 0,
+        };
+    }
+
+    fn snapshot(&self) -> (Option<Vec<NodeData>>, LayoutMetrics) {
+        let nodes = if self.settings.transfer_positions {
+            None
+        } else {
+            Some(self.layout.all_nodes_data())
+        };
+        return (nodes, self.layout.metrics());
+    }
+
+    /// The positions to send along with progress reports, if they're being
+    /// transferred rather than serialized.
+    pub fn transferred_positions(&self) -> Option<Vec<f64>> {
+        if !self.settings.transfer_positions {
+            return None;
+        }
+        return Some(self.layout.positions_vec());
+    }
+
+    /// Runs one batch of iterations, returning a report if one is due.
+    pub fn tick(&mut self) -> Option<SessionReport> {
+        if self.state != SessionState::Running {
+            return None;
+        }
+        for _ in 0..self.settings.iterations_per_tick.max(1) {
+            if self.settings.iterations > 0
+                && self.iteration >= self.settings.iterations
+            {
+                self.state = SessionState::Finished;
+                let (nodes, metrics) = self.snapshot();
+                return Some(SessionReport::Done {
+                    iteration: self.iteration,
+                    nodes: nodes,
+                    metrics: metrics,
+                });
+            }
+            self.optimizer.step(&mut self.layout);
+            self.iteration += 1;
+        }
+        self.ticks += 1;
+        if self.ticks % self.settings.report_every.max(1) != 0 {
+            return None;
+        }
+        let (nodes, metrics) = self.snapshot();
+        return Some(SessionReport::Progress {
+            iteration: self.iteration,
+            nodes: nodes,
+            metrics: metrics,
+        });
+    }
+
+    pub fn pause(&mut self) -> SessionReport {
+        if self.state == SessionState::Running {
+            self.state = SessionState::Paused;
+        }
+        return SessionReport::Paused {
+            iteration: self.iteration,
+        };
+    }
+
+    pub fn resume(&mut self) {
+        if self.state == SessionState::Paused {
+            self.state = SessionState::Running;
+        }
+    }
+
+    pub fn cancel(&mut self) -> SessionReport {
+        self.state = SessionState::Finished;
+        return SessionReport::Cancelled {
+            iteration: self.iteration,
+        };
+    }
+}
+
+struct WorkerHost {
+    scope: DedicatedWorkerGlobalScope,
+    session: Option<LayoutSession>,
+    scheduled: bool,
+}
+
+type SharedHost = Rc<RefCell<WorkerHost>>;
+
+fn post(scope: &DedicatedWorkerGlobalScope, report: &SessionReport) {
+    match JsValue::from_serde(report) {
+        Ok(message) => {
+            let _ = scope.post_message(&message);
+        }
+        Err(_) => {}
+    };
+}
+
+fn post_with_positions(
+    scope: &DedicatedWorkerGlobalScope,
+    report: &SessionReport,
+    positions: Vec<f64>,
+) {
+    let message = match JsValue::from_serde(report) {
+        Ok(message) => message,
+        Err(_) => return,
+    };
+    let buffer = Float64Array::from(&positions[..]);
+    let _ = Reflect::set(&message, &JsValue::from_str("positions"), &buffer);
+    let transfer = Array::of1(&buffer.buffer());
+    let _ = scope.post_message_with_transfer(&message, &transfer);
+}
+
+fn schedule_tick(host: &SharedHost) {
+    let mut state = host.borrow_mut();
+    if state.scheduled {
+        return;
+    }
+    state.scheduled = true;
+    let next = host.clone();
+    let callback = Closure::once_into_js(move || run_tick(&next));
+    let _ = state
+        .scope
+        .set_timeout_with_callback_and_timeout_and_arguments_0(
+            callback.unchecked_ref(),
+            0,
+        );
+}
+
+fn run_tick(host: &SharedHost) {
+    let (report, positions, running) = {
+        let mut state = host.borrow_mut();
+        state.scheduled = false;
+        match state.session.as_mut() {
+            Some(session) => {
+                let report = session.tick();
+                let positions = match report {
+                    Some(_) => session.transferred_positions(),
+                    None => None,
+                };
+                (report, positions, session.state == SessionState::Running)
+            }
+            None => (None, None, false),
+        }
+    };
+    match (report, positions) {
+        (Some(report), Some(positions)) => {
+            post_with_positions(&host.borrow().scope, &report, positions)
+        }
+        (Some(report), None) => post(&host.borrow().scope, &report),
+        _ => {}
+    };
+    if running {
+        schedule_tick(host);
+    }
+}
+
+fn handle_request(host: &SharedHost, request: SessionRequest) {
+    let report = {
+        let mut state = host.borrow_mut();
+        match request {
+            SessionRequest::Start {
+                nodes,
+                edges,
+                options,
+                settings,
+            } => {
+                state.session = Some(LayoutSession::new(
+                    GraphData {
+                        nodes: nodes,
+                        edges: edges,
+                    },
+                    options,
+                    settings,
+                ));
+                None
+            }
+            SessionRequest::Pause => state.session.as_mut().map(|s| s.pause()),
+            SessionRequest::Resume => {
+                state.session.as_mut().map(|s| s.resume());
+                None
+            }
+            SessionRequest::Cancel => {
+                state.session.take().map(|mut s| s.cancel())
+            }
+        }
+    };
+    if let Some(report) = report {
+        post(&host.borrow().scope, &report);
+    }
+    schedule_tick(host);
+}
+
+/// Turns the current Web Worker into a layout host. The worker answers
+/// `start`, `pause`, `resume` and `cancel` messages and posts `progress`
+/// snapshots while iterating.
+#[wasm_bindgen]
+pub fn start_layout_worker() {
+    let scope: DedicatedWorkerGlobalScope = js_sys::global().unchecked_into();
+    let host: SharedHost = Rc::new(RefCell::new(WorkerHost {
+        scope: scope.clone(),
+        session: None,
+        scheduled: false,
+    }));
+
+    let onmessage = Closure::wrap(Box::new(move |event: MessageEvent| {
+        let request: Result<SessionRequest, _> = event.data().into_serde();
+        match request {
+            Ok(request) => handle_request(&host, request),
+            Err(error) => post(
+                &host.borrow().scope,
+                &SessionReport::Error {
+                    message: error.to_string(),
+                },
+            ),
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+    scope.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+
+    post(&scope, &SessionReport::Ready);
+}
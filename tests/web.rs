@@ -3,6 +3,8 @@
 #![cfg(target_arch = "wasm32")]
 
 extern crate wasm_bindgen_test;
+use cobwebs_rs::GraphLayout;
+use wasm_bindgen::JsValue;
 use wasm_bindgen_test::*;
 
 wasm_bindgen_test_configure!(run_in_browser);
@@ -11,3 +13,16 @@ wasm_bindgen_test_configure!(run_in_browser);
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+#[wasm_bindgen_test]
+fn malformed_options_throw() {
+    let options = |json: &str| js_sys::JSON::parse(json).unwrap();
+    let layout = |options: JsValue| {
+        GraphLayout::new(JsValue::UNDEFINED, JsValue::UNDEFINED, options)
+    };
+    assert!(layout(JsValue::UNDEFINED).is_ok());
+    assert!(layout(JsValue::NULL).is_ok());
+    assert!(layout(options(r#"{"edge_mode": "undirected"}"#)).is_ok());
+    assert!(layout(options(r#"{"edge_mod": "undirected"}"#)).is_err());
+    assert!(layout(options(r#"{"edge_mode": "sideways"}"#)).is_err());
+}
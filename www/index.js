@@ -46,48 +46,39 @@ nodes.forEach(node => {
   node.y = positions[node.id].y;
 })
 
-import init, { GraphLayout } from './pkg/cobwebs_rs';
-
-async function run() {
-  await init();
-  let layout = new GraphLayout(graphData.nodes, graphData.edges);
-  const peak = 50
-  let initialTime = null
-  const step = () => {
-    initialTime ||= Date.now()
-    // layout.randomize_node_positions(peak*Math.sin((Date.now() - initialTime)/4000)**2);
-    let i = 0
-    while (i < 50) {
-      // layout.inside_box(250, true);
-      i=i+1
-    }
-    // for(let j=0; j<50; j++) {
-    //   layout.count_graph_intersections(true);
-      // layout.count_edges_intersections(false);
-    // }
-
-    // layout.tree_facts();
-    nodes.update([
-      ...layout.nodes_data(),
-      {
-        id: 2,
-        x: 500 * Math.cos(Date.now() / 1000),
-        y: 500 * Math.sin(Date.now() / 1000),
-      },
-      {
-        id: 3,
-        x: 200 * Math.cos(Date.now() / 500)+150,
-        y: 200 * Math.sin(Date.now() / 500)+300,
-      },
-      {
-        id: 4,
-        x: 200 * Math.cos(Date.now() / 2000)-100,
-        y: 200 * Math.sin(Date.now() / 2000)-200,
-      },
-    ])
-    setTimeout(() => window.requestAnimationFrame(step), 1000/60)
-  };
-  setTimeout(() => window.requestAnimationFrame(step), 3000)
-}
+const worker = new Worker(new URL("./layout-worker.js", import.meta.url), {
+  type: "module",
+});
+window.layoutWorker = worker;
 
-run()
\ No newline at end of file
+worker.onmessage = ({ data: message }) => {
+  switch (message.type) {
+    case "ready":
+      worker.postMessage({
+        type: "start",
+        nodes: graphData.nodes,
+        edges: graphData.edges,
+        options: { edge_mode: "undirected" },
+        settings: {
+          iterations: 20000,
+          iterations_per_tick: 200,
+          transfer_positions: true,
+        },
+      });
+      break;
+    case "progress":
+    case "done":
+      // Positions arrive as x, y pairs in the order the nodes were sent.
+      nodes.update(
+        graphData.nodes.map(({ id }, i) => ({
+          id,
+          x: message.positions[2 * i],
+          y: message.positions[2 * i + 1],
+        }))
+      );
+      console.log(message.type, message.iteration, message.metrics);
+      break;
+    default:
+      console.log(message);
+  }
+};
@@ -0,0 +1,3 @@
+import init, { start_layout_worker } from './pkg/cobwebs_rs.js';
+
+init().then(() => start_layout_worker());
@@ -0,0 +1,22 @@
+const path = require("path");
+const HtmlWebpackPlugin = require("html-webpack-plugin");
+const WasmPackPlugin = require("@wasm-tool/wasm-pack-plugin");
+
+module.exports = {
+  mode: "development",
+  entry: "./index.js",
+  output: {
+    path: path.resolve(__dirname, "dist"),
+    filename: "index.js",
+  },
+  plugins: [
+    // index.html already loads ./index.js, which is the bundle in dist.
+    new HtmlWebpackPlugin({ template: "index.html", inject: false }),
+    // Builds the crate into ../pkg, which ./pkg links to. The worker calls
+    // the web target's init(), which finds the .wasm through import.meta.url.
+    new WasmPackPlugin({
+      crateDirectory: path.resolve(__dirname, ".."),
+      extraArgs: "--target web",
+    }),
+  ],
+};